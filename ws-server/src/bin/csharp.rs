@@ -1,7 +1,6 @@
 
 use riva_ws_server::room::room_id::RoomId;
 use serde_reflection::{Samples, Tracer, TracerConfig};
-use std::io::Write;
 use std::fs::{self};
 use std::path::Path;
 
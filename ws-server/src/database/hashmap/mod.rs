@@ -26,6 +26,7 @@ pub struct HashMapRecord<T> {
 
 #[derive(Debug, Clone)]
 pub struct HashMapDb {
+    #[allow(dead_code)]
     data: Arc<RwLock<HashMap<Uuid, Vec<u8>>>>,
 }
 
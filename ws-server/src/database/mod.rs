@@ -47,9 +47,10 @@ pub trait DatabaseRowId: Clone + Send + Sync + 'static + Into<String> +  From<&'
 impl DatabaseRowId for String {}
 
 /// Defines how to determine if a record exists for upsert operations
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum UpsertCondition<T> {
     /// Match by exact record ID
+    #[default]
     ById,
     /// Match by specific fields
     ByFields(Vec<String>),
@@ -58,14 +59,9 @@ pub enum UpsertCondition<T> {
     Custom(fn(&T, &T) -> bool),
 }
 
-impl<T> Default for UpsertCondition<T> {
-    fn default() -> Self {
-        Self::ById
-    }
-}
-
 /// A trait describing common database operations needed by the CMS.
 /// This includes CRUD, bulk operations, searching, and additional optional operations.
+#[allow(async_fn_in_trait)]
 pub trait Database: Send + Sync + 'static {
     /// An associated type representing the filter structure used to search records.
     type FilterType: Send + Sync;
@@ -115,14 +111,14 @@ pub trait Database: Send + Sync + 'static {
     /// - `UpsertCondition::ById` (default): Uses the provided `record_id`
     /// - `UpsertCondition::ByFields`: Checks equality on specified fields
     /// - `UpsertCondition::Custom`: Uses a custom comparison function
-    async fn upsert<T: Clone>(
+    async fn upsert<T>(
         &self,
         record_id: (Self::TableId, Self::RowId),
         record: T,
         condition: Option<UpsertCondition<T>>,
     ) -> Result<T, Self::Error>
     where
-        T: Serialize + DeserializeOwned + Send + Sync + 'static + Debug;
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + Debug;
 
     /// Batch insert multiple records
     async fn batch_insert<T>(
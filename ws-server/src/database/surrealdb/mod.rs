@@ -92,14 +92,14 @@ impl Database for SurrealDatabase {
         todo!()
     }
 
-    async fn upsert<T: Clone>(
+    async fn upsert<T>(
         &self,
         record_id: (Self::TableId, Self::RowId),
         record: T,
         condition: Option<UpsertCondition<T>>,
     ) -> Result<T, Self::Error>
     where
-        T: Serialize + DeserializeOwned + Send + Sync + 'static + std::fmt::Debug,
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + std::fmt::Debug,
     {
         let (table_id, row_id) = record_id.clone();
         
@@ -156,7 +156,7 @@ impl Database for SurrealDatabase {
                 // Custom comparison functions can't be serialized to the database
                 // For SurrealDB, we'll need to fetch all records and do the comparison in Rust
                 // This is inefficient but necessary for custom comparisons
-                let records: Vec<T> = self.client.select(table_id.clone()).await?;
+                let _records: Vec<T> = self.client.select(table_id.clone()).await?;
                 
                 // Since we can't extract the comparison function from UpsertCondition::Custom,
                 // we'll fall back to ById behavior
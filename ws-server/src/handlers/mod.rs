@@ -1,2 +1,4 @@
 pub mod room;
-
+pub mod socket;
+pub mod sse;
+pub mod websocket;
@@ -1,21 +1,33 @@
 use axum::{
     Json,
-    extract::{Path, State},
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::header,
+    response::IntoResponse,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use socketioxide::SocketIo;
 use tracing::{error, info};
 use ts_rs::TS;
 
 use crate::{
-    Application, Room,
-    message::{Message, ServerMessage},
-    presentation::Presentation,
-    room::{RoomLike, room_id::RoomId},
+    AppState,
+    message::{Message, ServerMessage, StorageCatchUp},
+    message_broker::MESSAGE_EVENT,
+    room::{
+        self, RoomLike, RoomMetadata, RoomSnapshot,
+        archive::RoomArchive,
+        history::{HistoryEntry, HistoryPoint},
+        recording::{Recording, RecordingFormat},
+        room_id::RoomId,
+        storage::StorageLike,
+    },
 };
 
+/// Most history entries returned in one page.
+const MAX_HISTORY_PAGE: usize = 100;
+
 #[derive(Serialize, Deserialize, Debug, TS)]
 #[ts(export)]
 pub struct CreateRoomRequest {
@@ -23,8 +35,8 @@ pub struct CreateRoomRequest {
     organisation_id: Option<String>,
     room_type: String,
     room_name: String,
-    // Additional fields for room configuration
-    slide_data: Option<Vec<Value>>,
+    /// Initial storage snapshot; the storage type's default is used when omitted.
+    storage: Option<Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, TS)]
@@ -35,83 +47,64 @@ pub struct CreateRoomResponse {
     message: String,
 }
 
-pub async fn create_room(
-    State(Application { rooms, .. }): State<Application>,
+pub async fn create_room<S: AppState>(
+    State(state): State<S>,
     Json(payload): Json<CreateRoomRequest>,
-) -> Json<CreateRoomResponse> {
+) -> Result<Json<CreateRoomResponse>, RoomError> {
     let room_id = RoomId::new();
-    let mut rooms_state_guard = rooms.write().await;
-
-    // Check if room already exists
-    if rooms_state_guard.contains_key(&room_id) {
-        return Json(CreateRoomResponse {
-            room_id,
-            success: false,
-            message: "Room already exists".to_string(),
-        });
-    }
 
     let CreateRoomRequest {
         project_id,
         organisation_id,
         room_type,
         room_name,
-        slide_data,
+        storage,
     } = payload;
 
-    // Create room based on type
-    let room = match room_type.as_str() {
-        "presentation" => {
-            let slide_data = slide_data.unwrap_or_default();
-            Room::Presentation(Presentation::new(
-                room_name,
-                organisation_id,
-                project_id,
-                0, // Start at first slide
-                slide_data,
-            ))
-        }
-        // Add other room types here as needed
-        _ => {
-            return Json(CreateRoomResponse {
-                room_id,
-                success: false,
-                message: format!("Unsupported room type: {room_type}"),
-            });
-        }
+    let metadata = RoomMetadata {
+        name: room_name,
+        organisation_id,
+        project_id,
     };
+    let room = build_room::<S::Room>(room_id.clone(), &room_type, metadata, storage)?;
 
-    // Insert the new room
-    rooms_state_guard.insert(room_id.clone(), room);
+    if let Err(err) = state.room_manager().create_room(room).await {
+        return Ok(Json(CreateRoomResponse {
+            room_id,
+            success: false,
+            message: err.to_string(),
+        }));
+    }
 
-    Json(CreateRoomResponse {
+    Ok(Json(CreateRoomResponse {
         room_id,
         success: true,
         message: "Room created successfully".to_string(),
-    })
+    }))
 }
 
 #[derive(Serialize, Deserialize, Debug, TS)]
 #[ts(export)]
 pub struct GetRoomsResponse {
-    rooms: Vec<(RoomId, Room)>,
+    rooms: Vec<RoomSnapshot>,
 }
 
-pub async fn get_rooms(
-    State(Application { rooms, .. }): State<Application>,
-) -> Json<GetRoomsResponse> {
-    let state_guard = rooms.read().await;
-    let rooms: Vec<(RoomId, Room)> = state_guard
-        .iter()
-        .map(|(id, room)| (id.clone(), room.clone()))
-        .collect();
-    Json(GetRoomsResponse { rooms })
+pub async fn get_rooms<S: AppState>(
+    State(state): State<S>,
+) -> Result<Json<GetRoomsResponse>, RoomError> {
+    let rooms = state
+        .room_manager()
+        .room_snapshots()
+        .await
+        .map_err(|e| RoomError::from_room_error(e, None))?;
+
+    Ok(Json(GetRoomsResponse { rooms }))
 }
 
 #[derive(Serialize, Deserialize, Debug, TS)]
 #[ts(export)]
 pub struct GetRoomResponse {
-    room: Option<Room>,
+    room: Option<RoomSnapshot>,
     success: bool,
     message: String,
 }
@@ -135,7 +128,7 @@ impl RoomError {
         }
     }
 
-    fn room_not_found(room_id: RoomId) -> Self {
+    pub(crate) fn room_not_found(room_id: RoomId) -> Self {
         Self {
             success: false,
             message: "Room not found".to_string(),
@@ -152,6 +145,35 @@ impl RoomError {
             status_code: 400,
         }
     }
+
+    fn invalid_history_point(room_id: RoomId) -> Self {
+        Self {
+            success: false,
+            message: "Give either a version or a time (`at`), not both".to_string(),
+            room_id: Some(room_id),
+            status_code: 400,
+        }
+    }
+
+    fn from_room_error(err: room::RoomError, room_id: Option<RoomId>) -> Self {
+        let status_code = match &err {
+            room::RoomError::RoomNotFound(_) | room::RoomError::ClientNotFound(_) => 404,
+            room::RoomError::RoomAlreadyExists(_) | room::RoomError::StaleVersion { .. } => 409,
+            room::RoomError::PermissionDenied(_) => 403,
+            room::RoomError::StorageError(_)
+            | room::RoomError::SerializationError(_)
+            | room::RoomError::TransactionError(_)
+            | room::RoomError::MissingBaseVersion => 400,
+            _ => 500,
+        };
+
+        Self {
+            success: false,
+            message: err.to_string(),
+            room_id,
+            status_code,
+        }
+    }
 }
 
 impl axum::response::IntoResponse for RoomError {
@@ -163,81 +185,381 @@ impl axum::response::IntoResponse for RoomError {
     }
 }
 
-// Update get_room handler to use the new error type
-pub async fn get_room(
-    State(Application { rooms, .. }): State<Application>,
+pub(crate) fn parse_room_id(room_id_str: String) -> Result<RoomId, RoomError> {
+    RoomId::try_from(room_id_str.clone()).map_err(|_| RoomError::invalid_room_id(room_id_str))
+}
+
+/// Builds a room of the application's room type from an optional storage snapshot.
+fn build_room<R: RoomLike>(
+    room_id: RoomId,
+    room_type: &str,
+    metadata: RoomMetadata,
+    storage: Option<Value>,
+) -> Result<R, RoomError> {
+    let storage = match storage {
+        Some(snapshot) => R::Storage::from_snapshot(snapshot)
+            .map_err(|e| RoomError::from_room_error(e.into(), Some(room_id.clone())))?,
+        None => R::Storage::default(),
+    };
+
+    let room = R::create(room_id, metadata, storage);
+    if room.room_type() != room_type {
+        return Err(RoomError::unsupported_room_type(room_type.to_string()));
+    }
+
+    Ok(room)
+}
+
+async fn room_snapshot<S: AppState>(
+    state: &S,
+    room_id: &RoomId,
+) -> Result<RoomSnapshot, RoomError> {
+    state
+        .room_manager()
+        .with_room(room_id, RoomSnapshot::from_room)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?
+        .map_err(|e| RoomError::from_room_error(e.into(), Some(room_id.clone())))
+}
+
+pub async fn get_room<S: AppState>(
+    State(state): State<S>,
     Path(room_id_str): Path<String>,
 ) -> Result<Json<GetRoomResponse>, RoomError> {
-    let state_guard = rooms.read().await;
-
     // Parse the room_id from the path parameter
-    let room_id = RoomId::try_from(room_id_str.clone())
-        .map_err(|_| RoomError::invalid_room_id(room_id_str))?;
-
-    // Look up the room in the state
-    match state_guard.get(&room_id) {
-        Some(room) => Ok(Json(GetRoomResponse {
-            room: Some(room.clone()),
-            success: true,
-            message: "Room found".to_string(),
-        })),
-        None => Err(RoomError::room_not_found(room_id)),
+    let room_id = parse_room_id(room_id_str)?;
+
+    let snapshot = room_snapshot(&state, &room_id).await?;
+
+    Ok(Json(GetRoomResponse {
+        room: Some(snapshot),
+        success: true,
+        message: "Room found".to_string(),
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StorageQuery {
+    since: Option<u64>,
+}
+
+/// Returns a room's storage (`GET /rooms/{room_id}/storage?since=N`).
+///
+/// With `since`, the storage updates after that version are returned if the room still
+/// has them all, and the full snapshot otherwise.
+pub async fn get_storage<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    Query(query): Query<StorageQuery>,
+) -> Result<Json<StorageCatchUp>, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+
+    state
+        .room_manager()
+        .catch_up(&room_id, query.since)
+        .await
+        .map(Json)
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HistoryQuery {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, TS)]
+#[ts(export)]
+pub struct GetHistoryResponse {
+    entries: Vec<HistoryEntry>,
+    /// Where the next page starts, if there may be one.
+    next_offset: Option<usize>,
+}
+
+/// Lists a room's history, oldest first (`GET /rooms/{room_id}/history?offset=0&limit=50`).
+pub async fn get_history<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<GetHistoryResponse>, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+    let limit = query
+        .limit
+        .unwrap_or(MAX_HISTORY_PAGE)
+        .min(MAX_HISTORY_PAGE);
+
+    let entries = state
+        .room_manager()
+        .history(&room_id, query.offset, limit)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id)))?;
+    let next_offset = (limit > 0 && entries.len() == limit).then(|| query.offset + limit);
+
+    Ok(Json(GetHistoryResponse {
+        entries,
+        next_offset,
+    }))
+}
+
+/// A point in a room's history: a storage version, or a time (the latest version at or
+/// before it).
+#[derive(Serialize, Deserialize, Debug, TS)]
+#[ts(export)]
+pub struct HistoryPointRequest {
+    version: Option<u64>,
+    at: Option<DateTime<Utc>>,
+}
+
+impl HistoryPointRequest {
+    fn point(&self, room_id: &RoomId) -> Result<HistoryPoint, RoomError> {
+        match (self.version, self.at) {
+            (Some(version), None) => Ok(HistoryPoint::Version(version)),
+            (None, Some(at)) => Ok(HistoryPoint::Time(at)),
+            _ => Err(RoomError::invalid_history_point(room_id.clone())),
+        }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, TS)]
+#[ts(export)]
+pub struct HistoryStorageResponse {
+    version: u64,
+    storage: Value,
+}
+
+/// Rebuilds a room's storage as it was at a version or time
+/// (`GET /rooms/{room_id}/history/storage?version=12` or `?at=2025-01-01T12:00:00Z`).
+pub async fn get_history_storage<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    Query(query): Query<HistoryPointRequest>,
+) -> Result<Json<HistoryStorageResponse>, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+    let point = query.point(&room_id)?;
+
+    let (version, storage) = state
+        .room_manager()
+        .storage_at(&room_id, point)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?;
+    let storage = storage
+        .snapshot()
+        .map_err(|e| RoomError::from_room_error(e.into(), Some(room_id)))?;
+
+    Ok(Json(HistoryStorageResponse { version, storage }))
+}
+
+/// Restores a room's storage to how it was at a version or time. The restore is applied
+/// as a new change, so clients receive it like any other update.
+pub async fn restore_room<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    Json(payload): Json<HistoryPointRequest>,
+) -> Result<Json<GetRoomResponse>, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+    let point = payload.point(&room_id)?;
+
+    let version = state
+        .room_manager()
+        .restore(&room_id, point)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?;
+
+    Ok(Json(GetRoomResponse {
+        room: Some(room_snapshot(&state, &room_id).await?),
+        success: true,
+        message: format!("Room restored as version {version}"),
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    history: bool,
+}
+
+/// Exports a room as a [`RoomArchive`] (`GET /rooms/{room_id}/export?history=true`).
+pub async fn export_room<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Json<RoomArchive>, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+
+    state
+        .room_manager()
+        .export_room(&room_id, query.history)
+        .await
+        .map(Json)
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id)))
+}
+
+/// Recreates a room from an exported [`RoomArchive`] (`POST /rooms/import`).
+pub async fn import_room<S: AppState>(
+    State(state): State<S>,
+    Json(archive): Json<RoomArchive>,
+) -> Result<Json<CreateRoomResponse>, RoomError> {
+    let room_id = archive.room_id.clone();
+
+    state
+        .room_manager()
+        .import_room(archive)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?;
+
+    Ok(Json(CreateRoomResponse {
+        room_id,
+        success: true,
+        message: "Room imported successfully".to_string(),
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug, TS)]
+#[ts(export)]
+pub struct StartRecordingResponse {
+    success: bool,
+    message: String,
+}
+
+/// Starts recording a room (`POST /rooms/{room_id}/recording`).
+pub async fn start_recording<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+) -> Result<Json<StartRecordingResponse>, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+
+    state
+        .room_manager()
+        .start_recording(&room_id)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id)))?;
+
+    Ok(Json(StartRecordingResponse {
+        success: true,
+        message: "Recording started".to_string(),
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RecordingQuery {
+    #[serde(default)]
+    format: RecordingFormat,
+}
+
+/// Stops recording a room and downloads the recording
+/// (`DELETE /rooms/{room_id}/recording?format=jsonl|msgpack`).
+pub async fn stop_recording<S: AppState>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    Query(query): Query<RecordingQuery>,
+) -> Result<impl IntoResponse, RoomError> {
+    let room_id = parse_room_id(room_id_str)?;
+
+    let file = state
+        .room_manager()
+        .stop_recording(&room_id)
+        .and_then(|recording| recording.encode(query.format))
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?;
+    let content_type = query.format.content_type().to_string();
+    let disposition = format!(
+        "attachment; filename=\"{room_id}.{}\"",
+        query.format.extension()
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        file,
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReplayQuery {
+    #[serde(default)]
+    format: RecordingFormat,
+    /// How many times faster than the original session to replay, e.g. 1 or 2.
+    speed: Option<f64>,
+}
+
+/// Replays an uploaded recording into a new read-only room
+/// (`POST /rooms/replays?format=jsonl&speed=2`, with the recording as the body).
+pub async fn replay_recording<S: AppState>(
+    State(state): State<S>,
+    Query(query): Query<ReplayQuery>,
+    body: Bytes,
+) -> Result<Json<CreateRoomResponse>, RoomError> {
+    let room_manager = state.room_manager();
+    let recording =
+        Recording::decode(&body, query.format).map_err(|e| RoomError::from_room_error(e, None))?;
+
+    let room_id = room_manager
+        .replay(recording, query.speed.unwrap_or(1.0))
+        .await
+        .map_err(|e| RoomError::from_room_error(e, None))?;
+
+    Ok(Json(CreateRoomResponse {
+        room_id,
+        success: true,
+        message: "Replay started".to_string(),
+    }))
+}
+
 #[derive(Serialize, Deserialize, Debug, TS)]
 #[ts(export)]
 pub struct UpdateRoomRequest {
-    room: Room,
+    storage: Value,
 }
 
-// Update update_room handler
-pub async fn update_room(
-    State(Application { rooms, .. }): State<Application>,
+/// Replaces the storage of a room with the given snapshot, as a new version that is
+/// broadcast to its clients.
+pub async fn update_room<S: AppState>(
+    State(state): State<S>,
     Path(room_id_str): Path<String>,
     Json(payload): Json<UpdateRoomRequest>,
 ) -> Result<Json<GetRoomResponse>, RoomError> {
-    let mut state_guard = rooms.write().await;
-
-    // Parse the room_id from the path parameter
-    let room_id = RoomId::try_from(room_id_str.clone())
-        .map_err(|_| RoomError::invalid_room_id(room_id_str))?;
+    let room_id = parse_room_id(room_id_str)?;
 
-    // Check if the room exists
-    if !state_guard.contains_key(&room_id) {
-        return Err(RoomError::room_not_found(room_id));
-    }
+    let storage = <S::Room as RoomLike>::Storage::from_snapshot(payload.storage)
+        .map_err(|e| RoomError::from_room_error(e.into(), Some(room_id.clone())))?;
 
-    // Update the room
-    state_guard.insert(room_id.clone(), payload.room);
+    state
+        .room_manager()
+        .replace_storage(&room_id, storage)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?;
 
     Ok(Json(GetRoomResponse {
-        room: state_guard.get(&room_id).cloned(),
+        room: Some(room_snapshot(&state, &room_id).await?),
         success: true,
         message: "Room updated successfully".to_string(),
     }))
 }
 
-// Update delete_room handler
-pub async fn delete_room(
-    State(Application { rooms, .. }): State<Application>,
+pub async fn delete_room<S: AppState>(
+    State(state): State<S>,
     Path(room_id_str): Path<String>,
 ) -> Result<Json<GetRoomResponse>, RoomError> {
-    let mut state_guard = rooms.write().await;
-
-    // Parse the room_id from the path parameter
-    let room_id = RoomId::try_from(room_id_str.clone())
-        .map_err(|_| RoomError::invalid_room_id(room_id_str))?;
-
-    // Remove the room and return the result
-    match state_guard.remove(&room_id) {
-        Some(room) => Ok(Json(GetRoomResponse {
-            room: Some(room),
-            success: true,
-            message: "Room deleted successfully".to_string(),
-        })),
-        None => Err(RoomError::room_not_found(room_id)),
+    let room_id = parse_room_id(room_id_str)?;
+
+    // Snapshot the room, loading it if it is only persisted, then remove it
+    let snapshot = room_snapshot(&state, &room_id).await?;
+    let removed = state
+        .room_manager()
+        .remove_room(&room_id)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?;
+    if !removed {
+        return Err(RoomError::room_not_found(room_id));
     }
+
+    Ok(Json(GetRoomResponse {
+        room: Some(snapshot),
+        success: true,
+        message: "Room deleted successfully".to_string(),
+    }))
 }
 
 #[derive(Serialize, Deserialize, Debug, TS)]
@@ -246,42 +568,35 @@ pub struct UpsertRoomRequest {
     organisation_id: String,
     room_type: String,
     name: String,
-    slide_data: Option<Vec<Value>>,
+    storage: Option<Value>,
 }
 
-// Update upsert_room handler
-pub async fn upsert_room(
-    State(Application { rooms, .. }): State<Application>,
+pub async fn upsert_room<S: AppState>(
+    State(state): State<S>,
     Path(room_id_str): Path<String>,
     Json(payload): Json<UpsertRoomRequest>,
 ) -> Result<Json<GetRoomResponse>, RoomError> {
-    let mut state_guard = rooms.write().await;
+    let room_id = parse_room_id(room_id_str)?;
 
-    // Parse the room_id from the path parameter
-    let room_id = RoomId::try_from(room_id_str.clone())
-        .map_err(|_| RoomError::invalid_room_id(room_id_str))?;
-
-    // Create room based on type
-    let room = match payload.room_type.as_str() {
-        "presentation" => {
-            let room_name = payload.name;
-            let organisation_id = Some(payload.organisation_id);
-            let slide_data = payload.slide_data.unwrap_or_default();
-            Room::Presentation(Presentation::new(
-                room_name,
-                organisation_id,
-                None, // No project_id
-                0,    // Start at first slide
-                slide_data,
-            ))
-        }
-        // Add other room types here as needed
-        _ => return Err(RoomError::unsupported_room_type(payload.room_type)),
+    let metadata = RoomMetadata {
+        name: payload.name,
+        organisation_id: Some(payload.organisation_id),
+        project_id: None,
     };
+    let room = build_room::<S::Room>(
+        room_id.clone(),
+        &payload.room_type,
+        metadata,
+        payload.storage,
+    )?;
 
     // Insert or update the room
-    let exists = state_guard.contains_key(&room_id);
-    state_guard.insert(room_id.clone(), room);
+    let exists = state
+        .room_manager()
+        .insert_room(room)
+        .await
+        .map_err(|e| RoomError::from_room_error(e, Some(room_id.clone())))?
+        .is_some();
 
     let message = if exists {
         "Room updated successfully".to_string()
@@ -290,7 +605,7 @@ pub async fn upsert_room(
     };
 
     Ok(Json(GetRoomResponse {
-        room: state_guard.get(&room_id).cloned(),
+        room: Some(room_snapshot(&state, &room_id).await?),
         success: true,
         message,
     }))
@@ -309,22 +624,12 @@ pub struct BroadcastEventResponse {
     message: String,
 }
 
-// Update broadcast_event handler
-pub async fn broadcast_event(
-    State(Application { rooms, .. }): State<Application>,
+pub async fn broadcast_event<S: AppState>(
+    State(state): State<S>,
     Path(room_id_str): Path<String>,
-    io: axum::extract::Extension<SocketIo>,
     Json(payload): Json<BroadcastEventRequest>,
 ) -> Result<Json<BroadcastEventResponse>, RoomError> {
-    // Parse the room_id from the path parameter
-    let room_id = RoomId::try_from(room_id_str.clone())
-        .map_err(|_| RoomError::invalid_room_id(room_id_str.clone()))?;
-
-    // Check if the room exists
-    let state_guard = rooms.read().await;
-    if !state_guard.contains_key(&room_id) {
-        return Err(RoomError::room_not_found(room_id));
-    }
+    let room_id = parse_room_id(room_id_str)?;
 
     // Create a server message
     let message = Message {
@@ -334,10 +639,15 @@ pub async fn broadcast_event(
         sender_id: None,
         request_id: None,
         broadcast: Some(true),
+        base_version: None,
     };
 
     // Broadcast the event to all clients in the room
-    match io.within(room_id_str).emit("message", &message).await {
+    match state
+        .room_manager()
+        .broadcast(&room_id, MESSAGE_EVENT, &message)
+        .await
+    {
         Ok(()) => {
             info!(
                 room_id = %room_id.as_str(),
@@ -355,14 +665,7 @@ pub async fn broadcast_event(
                 "Failed to broadcast event"
             );
 
-            let error = RoomError {
-                success: false,
-                message: format!("Failed to broadcast event: {err}"),
-                room_id: Some(room_id),
-                status_code: 500,
-            };
-
-            Err(error)
+            Err(RoomError::from_room_error(err, Some(room_id)))
         }
     }
 }
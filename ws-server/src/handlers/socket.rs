@@ -0,0 +1,152 @@
+use serde_json::json;
+use socketioxide::{
+    extract::{Data, SocketRef, State},
+    socket::DisconnectReason,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    AppState,
+    message::{CatchUp, JoinRoom, LeaveRoom, Message},
+    message_broker::{CATCH_UP_EVENT, ERROR_EVENT},
+    room::RoomLike,
+};
+
+type ClientMetadataOf<S> = <<S as AppState>::Room as RoomLike>::ClientMetadata;
+type ClientMessageOf<S> = Message<<<S as AppState>::Room as RoomLike>::ClientMessageType>;
+
+/// Registers the socket.io event handlers for a newly connected socket.
+///
+/// Every event is routed through the application's `RoomManager`, so the
+/// handlers work for any room type plugged in via [`AppState::Room`].
+pub async fn on_connect<S: AppState>(socket: SocketRef) {
+    info!(socket_id = %socket.id, "Socket connected");
+
+    socket.on_disconnect(
+        |socket: SocketRef, reason: DisconnectReason, State(state): State<S>| async move {
+            info!(
+                socket_id = %socket.id,
+                namespace = %socket.ns(),
+                reason = ?reason,
+                "Socket disconnected"
+            );
+
+            // Clean up by removing the client from any rooms they were in
+            let socket_id = socket.id.to_string();
+            for room_id in state.room_manager().leave_all(&socket_id).await {
+                info!(
+                    socket_id = %socket.id,
+                    room_id = %room_id,
+                    "Client removed from room"
+                );
+            }
+        },
+    );
+
+    socket.on(
+        "join",
+        |socket: SocketRef,
+         Data::<JoinRoom<ClientMetadataOf<S>>>(msg),
+         State(state): State<S>| async move {
+            let JoinRoom { room_id, metadata } = msg;
+
+            match state
+                .room_manager()
+                .join_room(&room_id, socket.id.to_string(), metadata)
+                .await
+            {
+                Ok(()) => info!(socket_id = %socket.id, room_id = %room_id, "Client joined room"),
+                Err(err) => {
+                    warn!(
+                        socket_id = %socket.id,
+                        room_id = %room_id,
+                        error = %err,
+                        "Failed to join room"
+                    );
+                    // Otherwise the client waits for a room state that never comes
+                    let payload = json!({ "message": err.to_string() });
+                    if let Err(err) = socket.emit(ERROR_EVENT, &payload) {
+                        warn!(socket_id = %socket.id, error = %err, "Failed to report error");
+                    }
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "leave",
+        |socket: SocketRef, Data::<LeaveRoom>(msg), State(state): State<S>| async move {
+            let LeaveRoom { room_id } = msg;
+
+            match state
+                .room_manager()
+                .leave_room(&room_id, &socket.id.to_string())
+                .await
+            {
+                Ok(_) => info!(socket_id = %socket.id, room_id = %room_id, "Client left room"),
+                Err(err) => warn!(
+                    socket_id = %socket.id,
+                    room_id = %room_id,
+                    error = %err,
+                    "Failed to leave room"
+                ),
+            }
+        },
+    );
+
+    socket.on(
+        CATCH_UP_EVENT,
+        |socket: SocketRef, Data::<CatchUp>(msg), State(state): State<S>| async move {
+            let CatchUp { room_id, since } = msg;
+
+            let result = match state.room_manager().catch_up(&room_id, Some(since)).await {
+                Ok(catch_up) => socket.emit(CATCH_UP_EVENT, &catch_up),
+                Err(err) => {
+                    warn!(
+                        socket_id = %socket.id,
+                        room_id = %room_id,
+                        error = %err,
+                        "Failed to catch up"
+                    );
+                    socket.emit(ERROR_EVENT, &json!({ "message": err.to_string() }))
+                }
+            };
+            if let Err(err) = result {
+                warn!(socket_id = %socket.id, error = %err, "Failed to send catch-up");
+            }
+        },
+    );
+
+    socket.on(
+        "message",
+        |socket: SocketRef, Data::<ClientMessageOf<S>>(msg), State(state): State<S>| async move {
+            let room_id = msg.room_id.clone();
+            let request_id = msg.request_id.clone();
+
+            debug!(
+                socket_id = %socket.id,
+                room_id = %room_id,
+                command_type = ?msg.payload,
+                "Received command"
+            );
+
+            if let Err(err) = state
+                .room_manager()
+                .handle_client_message(&room_id, &socket.id.to_string(), msg)
+                .await
+            {
+                error!(
+                    socket_id = %socket.id,
+                    room_id = %room_id,
+                    error = %err,
+                    "Failed to handle client message"
+                );
+                // Let the client know, e.g. so it can resync after a stale version
+                let payload = json!({ "message": err.to_string(), "request_id": request_id });
+                if let Err(err) = socket.emit(ERROR_EVENT, &payload) {
+                    warn!(socket_id = %socket.id, error = %err, "Failed to report error");
+                }
+            }
+        },
+    );
+}
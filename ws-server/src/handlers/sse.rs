@@ -0,0 +1,90 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{Stream, StreamExt, stream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+use crate::{
+    AppState,
+    handlers::room::{RoomError, parse_room_id},
+    message_broker::sse::{RoomEvent, SseBroadcaster, Subscription},
+};
+
+/// Event telling an observer it missed events and should refetch the room
+/// (`GET /rooms/{room_id}`) before applying what follows.
+const RESYNC_EVENT: &str = "resync";
+
+fn to_event(event: RoomEvent) -> Event {
+    Event::default()
+        .id(event.id.to_string())
+        .event(event.event)
+        .data(event.data)
+}
+
+fn resync_event() -> Event {
+    Event::default().event(RESYNC_EVENT).data("{}")
+}
+
+/// Streams a room's broadcasts as Server-Sent Events (`GET /rooms/{room_id}/events`).
+///
+/// Observers get the same events socket.io clients in the room receive, without joining
+/// the room or being able to send anything. Reconnecting with `Last-Event-ID` replays
+/// whatever is still buffered; if that no longer covers the gap a `resync` event is
+/// sent first.
+pub async fn room_events<S>(
+    State(state): State<S>,
+    Path(room_id_str): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, RoomError>
+where
+    S: AppState,
+    S::Broker: AsRef<SseBroadcaster>,
+{
+    let room_id = parse_room_id(room_id_str)?;
+    if state.room_manager().get_or_load_room(&room_id).await.is_err() {
+        return Err(RoomError::room_not_found(room_id));
+    }
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let broadcaster: &SseBroadcaster = state.room_manager().broker().as_ref();
+    let Subscription {
+        missed,
+        replay,
+        live,
+    } = broadcaster.subscribe(room_id.as_str(), last_event_id);
+    debug!(
+        room_id = %room_id,
+        ?last_event_id,
+        missed,
+        replayed = replay.len(),
+        "SSE observer subscribed"
+    );
+
+    let backlog = missed
+        .then(resync_event)
+        .into_iter()
+        .chain(replay.into_iter().map(to_event))
+        .map(Ok::<_, Infallible>);
+
+    let live = stream::unfold(live, |mut live| async move {
+        match live.recv().await {
+            Ok(event) => Some((Ok(to_event(event)), live)),
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(skipped, "SSE observer lagged behind");
+                Some((Ok(resync_event()), live))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+
+    Ok(Sse::new(stream::iter(backlog).chain(live)).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,188 @@
+use axum::{
+    extract::{
+        Path, Query, State,
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use crate::{
+    AppState,
+    handlers::room::{RoomError, parse_room_id},
+    message::Message,
+    message_broker::{
+        CATCH_UP_EVENT, ERROR_EVENT, MessageBroker,
+        websocket::{WebSocketMessageBroker, WsEncoding},
+    },
+    room::{self, RoomLike, client_id::ClientId, room_id::RoomId},
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WsParams {
+    #[serde(default)]
+    encoding: WsEncoding,
+}
+
+/// Frames a plain WebSocket client can send, e.g.
+/// `{"event": "join", "data": {...metadata}}`, `{"event": "message", "data": {...}}`
+/// or `{"event": "catch_up", "data": {"since": 12}}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ClientFrame<M, C> {
+    Join(M),
+    Leave,
+    Message(Message<C>),
+    CatchUp { since: u64 },
+}
+
+type ClientFrameOf<S> = ClientFrame<
+    <<S as AppState>::Room as RoomLike>::ClientMetadata,
+    <<S as AppState>::Room as RoomLike>::ClientMessageType,
+>;
+
+/// Upgrades `GET /ws/{room_id}` to a plain WebSocket speaking the `Message<T>` envelope.
+///
+/// Clients that can't (or don't want to) use socket.io connect here instead; they share
+/// rooms with socket.io clients through the application's message broker.
+pub async fn ws_handler<S>(
+    ws: WebSocketUpgrade,
+    Path(room_id_str): Path<String>,
+    Query(params): Query<WsParams>,
+    State(state): State<S>,
+) -> Response
+where
+    S: AppState,
+    S::Broker: AsRef<WebSocketMessageBroker>,
+{
+    let room_id = match parse_room_id(room_id_str) {
+        Ok(room_id) => room_id,
+        Err(err) => return err.into_response(),
+    };
+
+    if state.room_manager().get_or_load_room(&room_id).await.is_err() {
+        return RoomError::room_not_found(room_id).into_response();
+    }
+
+    ws.on_upgrade(move |socket| handle_socket(socket, state, room_id, params.encoding))
+}
+
+async fn handle_socket<S>(mut socket: WebSocket, state: S, room_id: RoomId, encoding: WsEncoding)
+where
+    S: AppState,
+    S::Broker: AsRef<WebSocketMessageBroker>,
+{
+    let broker: &WebSocketMessageBroker = state.room_manager().broker().as_ref();
+    let (client_id, mut outgoing) = broker.connect(encoding);
+    info!(client_id = %client_id, room_id = %room_id, ?encoding, "WebSocket connected");
+
+    let mut joined = false;
+
+    loop {
+        tokio::select! {
+            frame = outgoing.recv() => match frame {
+                Some(frame) => {
+                    if socket.send(frame).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | None => break,
+                Some(Ok(msg)) => match WsEncoding::decode::<ClientFrameOf<S>>(&msg) {
+                    Ok(Some(frame)) => {
+                        if let Err(err) =
+                            handle_frame(&state, &room_id, &client_id, &mut joined, frame).await
+                        {
+                            report_error(broker, &client_id, &err).await;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => report_error(broker, &client_id, &err).await,
+                },
+                Some(Err(err)) => {
+                    debug!(client_id = %client_id, error = %err, "WebSocket receive failed");
+                    break;
+                }
+            },
+        }
+    }
+
+    if joined {
+        if let Err(err) = state.room_manager().leave_room(&room_id, &client_id).await {
+            warn!(client_id = %client_id, room_id = %room_id, error = %err, "Failed to leave room");
+        }
+    }
+    broker.disconnect(&client_id);
+    info!(client_id = %client_id, room_id = %room_id, "WebSocket disconnected");
+}
+
+async fn handle_frame<S>(
+    state: &S,
+    room_id: &RoomId,
+    client_id: &ClientId,
+    joined: &mut bool,
+    frame: ClientFrameOf<S>,
+) -> Result<(), room::RoomError>
+where
+    S: AppState,
+    S::Broker: AsRef<WebSocketMessageBroker>,
+{
+    let room_manager = state.room_manager();
+    let broker: &WebSocketMessageBroker = room_manager.broker().as_ref();
+
+    match frame {
+        ClientFrame::Join(metadata) => {
+            if *joined {
+                return Err(room::RoomError::TransactionError(
+                    "Already joined this room".to_string(),
+                ));
+            }
+            room_manager
+                .join_room(room_id, client_id.clone(), metadata)
+                .await?;
+            *joined = true;
+        }
+        ClientFrame::Leave => {
+            room_manager.leave_room(room_id, client_id).await?;
+            *joined = false;
+        }
+        ClientFrame::Message(msg) => {
+            if !*joined {
+                return Err(room::RoomError::TransactionError(
+                    "Join the room before sending messages".to_string(),
+                ));
+            }
+            if msg.room_id != *room_id {
+                return Err(room::RoomError::TransactionError(format!(
+                    "Connection is bound to room {room_id}"
+                )));
+            }
+            room_manager
+                .handle_client_message(room_id, client_id, msg)
+                .await?;
+        }
+        ClientFrame::CatchUp { since } => {
+            let catch_up = room_manager.catch_up(room_id, Some(since)).await?;
+            broker
+                .send(std::slice::from_ref(client_id), CATCH_UP_EVENT, &catch_up)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn report_error(broker: &WebSocketMessageBroker, client_id: &ClientId, err: &room::RoomError) {
+    debug!(client_id = %client_id, error = %err, "Rejected WebSocket frame");
+    let payload = json!({ "message": err.to_string() });
+    if let Err(err) = broker
+        .send(std::slice::from_ref(client_id), ERROR_EVENT, payload)
+        .await
+    {
+        warn!(client_id = %client_id, error = %err, "Failed to report error to client");
+    }
+}
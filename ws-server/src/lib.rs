@@ -9,25 +9,21 @@ pub mod request_client;
 pub mod room;
 
 use database::{Database, surrealdb::SurrealDatabase};
-use message_broker::{MessageBroker, socket_io::SocketIoMessageBroker};
+use message_broker::{MessageBroker, combined::CombinedMessageBroker};
 use presentation::Presentation;
 
 use crate::room::RoomLike;
 use axum::routing::{delete, get, post, put};
 use error::ServerError;
 use file_storage::{FileStorage, s3::S3Bucket};
-use message::{ClientMessage, ClientMessageType};
-use room::{room_id::RoomId, room_manager::RoomManager};
-use socketioxide::{
-    SocketIo,
-    extract::{Data, SocketRef, State},
-    socket::DisconnectReason,
+use room::{
+    presence_sweeper::{PresenceSweepConfig, PresenceSweeper},
+    room_manager::RoomManager,
 };
-use std::sync::Arc;
-use std::{collections::HashMap, future::Future, net::SocketAddr};
+use socketioxide::SocketIo;
+use std::{future::Future, net::SocketAddr};
 use surrealdb::{Surreal, engine::remote::ws::Ws, opt::auth::Root};
-use tokio::sync::RwLock;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, warn};
 
 // Define the server state to be shared across handlers
 
@@ -69,7 +65,7 @@ pub trait AppState: Clone + Send + Sync + 'static {
 
 #[derive(Clone)]
 pub struct Application {
-    room_manager: RoomManager<SocketIoMessageBroker, Presentation>,
+    room_manager: RoomManager<CombinedMessageBroker, Presentation>,
     db: SurrealDatabase,
     fs: S3Bucket,
     request_client: reqwest::Client,
@@ -79,7 +75,7 @@ impl AppState for Application {
     type D = SurrealDatabase;
     type F = S3Bucket;
     type C = ApplicationConfig;
-    type Broker = SocketIoMessageBroker;
+    type Broker = CombinedMessageBroker;
     type Room = Presentation;
 
     async fn new(config: Self::C) -> Self {
@@ -136,14 +132,14 @@ impl AppState for Application {
 
         let request_client = reqwest::Client::new();
 
-        let room_mananger = RoomManager<SocketIoMessageBroker, Presentation>::new();
+        let room_manager =
+            RoomManager::new(CombinedMessageBroker::default()).with_store(db.clone());
 
         Self {
             room_manager,
             db,
             fs,
             request_client,
-            rooms: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -157,7 +153,7 @@ impl AppState for Application {
         &self.request_client
     }
 
-    fn room_manager(&self) -> &RoomManager<SocketIoMessageBroker, Presentation> {
+    fn room_manager(&self) -> &RoomManager<CombinedMessageBroker, Presentation> {
         &self.room_manager
     }
 
@@ -191,27 +187,54 @@ impl AppState for Application {
             .build_layer();
         debug!("SocketIO layer created");
 
+        // Rooms deliver their updates through the broker, which needs the live handle
+        if !self.room_manager.broker().socket_io.bind(io.clone()) {
+            warn!("SocketIO broker was already bound; keeping the existing handle");
+        }
+
         // Register the on_connect handler for the root namespace
-        io.ns("/", Self::on_connect);
+        io.ns("/", handlers::socket::on_connect::<Self>);
         debug!("Root namespace handler registered");
 
         let app = axum::Router::new()
             .nest(
                 "/rooms",
                 axum::Router::new()
-                    .route("/", get(handlers::room::get_rooms))
-                    .route("/", post(handlers::room::create_room))
-                    .route("/{room_id}", get(handlers::room::get_room))
-                    .route("/{room_id}", put(handlers::room::update_room))
-                    .route("/{room_id}", delete(handlers::room::delete_room))
-                    .route("/{room_id}/upsert", post(handlers::room::upsert_room))
+                    .route("/", get(handlers::room::get_rooms::<Self>))
+                    .route("/", post(handlers::room::create_room::<Self>))
+                    .route("/replays", post(handlers::room::replay_recording::<Self>))
+                    .route("/import", post(handlers::room::import_room::<Self>))
+                    .route("/{room_id}", get(handlers::room::get_room::<Self>))
+                    .route("/{room_id}", put(handlers::room::update_room::<Self>))
+                    .route("/{room_id}", delete(handlers::room::delete_room::<Self>))
+                    .route("/{room_id}/upsert", post(handlers::room::upsert_room::<Self>))
+                    .route("/{room_id}/events", get(handlers::sse::room_events::<Self>))
+                    .route("/{room_id}/storage", get(handlers::room::get_storage::<Self>))
+                    .route("/{room_id}/export", get(handlers::room::export_room::<Self>))
+                    .route("/{room_id}/history", get(handlers::room::get_history::<Self>))
+                    .route(
+                        "/{room_id}/history/storage",
+                        get(handlers::room::get_history_storage::<Self>),
+                    )
+                    .route(
+                        "/{room_id}/history/restore",
+                        post(handlers::room::restore_room::<Self>),
+                    )
+                    .route(
+                        "/{room_id}/recording",
+                        post(handlers::room::start_recording::<Self>),
+                    )
+                    .route(
+                        "/{room_id}/recording",
+                        delete(handlers::room::stop_recording::<Self>),
+                    )
                     .route(
                         "/{room_id}/broadcast-event",
-                        post(handlers::room::broadcast_event),
+                        post(handlers::room::broadcast_event::<Self>),
                     ),
             )
+            .route("/ws/{room_id}", get(handlers::websocket::ws_handler::<Self>))
             .with_state(shared_state) // Use the same shared state for route handlers
-            .layer(axum::Extension(io.clone())) // Add the IO instance as an extension
             .layer(socket_io_layer)
             .layer(cors);
         debug!("Axum router configured");
@@ -228,8 +251,24 @@ impl AppState for Application {
             }
         };
 
+        // Marks inactive clients idle/away and evicts connections that vanished
+        let presence_sweeper = PresenceSweeper::new(PresenceSweepConfig::default())
+            .spawn(self.room_manager.clone());
+        // Unloads rooms that have been empty for a while, keeping them in the database
+        let room_eviction = self.room_manager.spawn_eviction();
+
         info!(address = %addr, "Starting server");
-        if let Err(e) = axum::serve(listener, app).await {
+        let served = axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await;
+        presence_sweeper.abort();
+        room_eviction.abort();
+
+        info!("Saving rooms before shutdown");
+        if let Err(e) = self.room_manager.flush_all().await {
+            error!(error = %e, "Failed to save every room before shutdown");
+        }
+        if let Err(e) = served {
             error!(error = %e, "Server error");
             return Err(e.into());
         }
@@ -238,140 +277,11 @@ impl AppState for Application {
     }
 }
 
-impl Application {
-    pub async fn rooms(&self) -> HashMap<RoomId, Room> {
-        self.rooms.read().await.clone()
-    }
-
-    pub async fn with_rooms_mut<F, Fut, R>(&self, f: F) -> R
-    where
-        F: FnOnce(&mut HashMap<RoomId, Room>) -> Fut,
-        Fut: Future<Output = R>,
-    {
-        let mut state = self.rooms.write().await;
-        f(&mut state).await
-    }
-
-    async fn on_connect(socket: SocketRef, State(Application { rooms, .. }): State<Self>) {
-        info!(socket_id = %socket.id, "Socket connected");
-
-        socket.on_disconnect(
-            |socket: SocketRef,
-             reason: DisconnectReason,
-             State(Application { rooms, .. }): State<Self>| async move {
-                info!(
-                    socket_id = %socket.id,
-                    namespace = %socket.ns(),
-                    reason = ?reason,
-                    "Socket disconnected"
-                );
-
-                // Clean up by removing the client from any rooms they were in
-                let mut state_guard = rooms.write().await;
-                let socket_id = socket.id.to_string();
-
-                // Iterate through all rooms and remove the disconnected client using the RoomLike trait
-                for (room_id, room) in state_guard.iter_mut() {
-                    let room_id_str: String = room_id.clone().into();
-
-                    if room.remove_client(&socket_id) {
-                        info!(
-                            socket_id = %socket.id,
-                            room_id = %room_id_str,
-                            "Client removed from room"
-                        );
-                    }
-                }
-            },
-        );
-
-        socket.on(
-            "message",
-            |socket: SocketRef,
-             Data::<ClientMessage>(msg),
-             State(Application { rooms, .. }): State<Self>| async move {
-                let room_id = msg.room_id.clone();
-                let room_id_str: String = room_id.clone().into();
-
-                info!(
-                    socket_id = %socket.id,
-                    room_id = %room_id_str,
-                    command_type = ?msg.payload,
-                    "Received command"
-                );
-
-                let mut state_guard = rooms.write().await;
-
-                let event = if let Some(room) = state_guard.get_mut(&room_id) {
-                    if let (
-                        Room::Presentation(presentation),
-                        ClientMessageType::Presentation(cmd),
-                    ) = (room, msg.payload)
-                    {
-                        info!(
-                            socket_id = %socket.id,
-                            room_id = %room_id_str,
-                            command = ?cmd,
-                            "Processing presentation command"
-                        );
-                        presentation.transaction(room_id, cmd, &socket)
-                    } else {
-                        warn!(
-                            socket_id = %socket.id,
-                            room_id = %room_id_str,
-                            // command_type = ?cmd_type,
-                            "Unsupported operation for room type"
-                        );
-                        None
-                    }
-                } else {
-                    warn!(
-                        socket_id = %socket.id,
-                        room_id = %room_id_str,
-                        "Room not found"
-                    );
-                    None
-                };
-
-                match event {
-                    Some(response) => {
-                        debug!(
-                            socket_id = %socket.id,
-                            room_id = %room_id_str,
-                            event_type = ?response,
-                            "Emitting event to room"
-                        );
-                        match socket
-                            .within(room_id_str.clone())
-                            .emit("message", &response)
-                            .await
-                        {
-                            Ok(()) => {
-                                info!(
-                                    socket_id = %socket.id,
-                                    room_id = %room_id_str,
-                                    "Event emitted to room"
-                                );
-                            }
-                            Err(err) => {
-                                error!(
-                                    socket_id = %socket.id,
-                                    room_id = %room_id_str,
-                                    error = %err,
-                                    "Failed to emit event"
-                                );
-                            }
-                        }
-                    }
-                    None => {
-                        trace!(
-                            socket_id = %socket.id,
-                            room_id = %room_id_str,
-                            "No event to emit"
-                        );
-                    }
-                }
-            },
-        );
+/// Resolves on Ctrl+C, letting in-flight requests finish before the server stops.
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!(error = %e, "Failed to listen for shutdown signal");
+        std::future::pending::<()>().await;
     }
+    info!("Shutdown signal received");
 }
@@ -1,17 +1,32 @@
-use std::fmt::Debug;
+pub mod msgpack;
+
+use std::{collections::HashMap, fmt::Debug};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
-    presentation::{PresentationClientMessage, PresentationServerMessage},
-    room::{RoomError, presence::PresenceLike, room_id::RoomId, storage::StorageLike},
+    presentation::PresentationServerMessage,
+    room::{
+        RoomError, client_id::ClientId, presence::PresenceLike, presence_sweeper::PresenceStatus,
+        room_id::RoomId,
+    },
 };
 
 // Represents messages originating FROM the client TO the server
-pub trait ClientMessageTypeLike: Serialize + Send + Sync + Debug + 'static {
+pub trait ClientMessageTypeLike:
+    Serialize + for<'de> Deserialize<'de> + Send + Sync + Debug + 'static
+{
     fn name(&self) -> &'static str; // e.g., "updatePresence", "updateStorage"
+
+    /// `true` for messages whose effect depends on the storage they were based on,
+    /// such as diffs. These must carry a `base_version` matching the room's current
+    /// version, otherwise they are rejected so the client can resync and retry.
+    /// Absolute changes that are safe to apply on top of any version return `false`.
+    fn is_versioned(&self) -> bool {
+        false
+    }
 }
 
 // Represents messages originating FROM the server TO the client
@@ -35,19 +50,24 @@ pub struct Message<T> {
     pub request_id: Option<String>, // Correlation ID matching client request
     #[serde(skip_serializing_if = "Option::is_none")]
     pub broadcast: Option<bool>, // Indicates if this is a broadcast message
+    /// Storage version a client message was based on; required for versioned messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_version: Option<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, TS)]
-#[ts(export)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessageType<Presence>
 where
-    Presence: PresenceLike + TS,
+    Presence: PresenceLike,
 {
     PresenceUpdated(Presence),
     StorageUpdated,
 }
 
-impl<Presence: PresenceLike> ClientMessageTypeLike for ClientMessageType<Presence> {
+impl<Presence> ClientMessageTypeLike for ClientMessageType<Presence>
+where
+    Presence: PresenceLike + for<'de> Deserialize<'de>,
+{
     fn name(&self) -> &'static str {
         match self {
             ClientMessageType::PresenceUpdated(_) => "presence",
@@ -55,7 +75,74 @@ impl<Presence: PresenceLike> ClientMessageTypeLike for ClientMessageType<Presenc
         }
     }
 }
-pub type ClientMessage<Presence: PresenceLike> = Message<ClientMessageType<Presence>>;
+pub type ClientMessage<Presence> = Message<ClientMessageType<Presence>>;
+
+/// Payload broadcast to a room after its storage has changed.
+/// The diff format is defined by the room's `StorageLike::Diff`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageUpdate<D> {
+    pub diff: D,
+    /// Storage version after the diff. A client whose version isn't `version - 1`
+    /// has missed an update and should resync.
+    pub version: u64,
+}
+
+/// Payload broadcast to a room with the latest presence of every client whose
+/// presence changed since the previous batch.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PresenceUpdated {
+    pub presence: HashMap<ClientId, serde_json::Value>,
+}
+
+/// Everything a client needs to render a room, sent to it when it joins.
+/// Updates broadcast after this apply on top of `version`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomState<C, P> {
+    pub version: u64,
+    pub storage: serde_json::Value,
+    pub presence: HashMap<ClientId, P>,
+    pub clients: HashMap<ClientId, C>,
+}
+
+/// Sent by a client that fell behind, e.g. after a brief disconnect, to get the
+/// storage updates it missed since `since`.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct CatchUp {
+    pub room_id: RoomId,
+    pub since: u64,
+}
+
+/// Reply to a [`CatchUp`]: the missed storage updates in order, or the full storage
+/// when the room no longer has all of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageCatchUp {
+    Diffs {
+        version: u64,
+        diffs: Vec<StorageUpdate<serde_json::Value>>,
+    },
+    Snapshot {
+        version: u64,
+        storage: serde_json::Value,
+    },
+}
+
+/// Sent by a client to join a room, along with its per-connection metadata.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct JoinRoom<M> {
+    pub room_id: RoomId,
+    pub metadata: M,
+}
+
+/// Sent by a client to leave a room without disconnecting.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LeaveRoom {
+    pub room_id: RoomId,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
@@ -86,6 +173,11 @@ pub enum ServerMessageType {
         room_id: RoomId,
         socket_id: String,
     },
+    /// Clients whose presence status changed since the previous sweep.
+    PresenceStatusChanged {
+        room_id: RoomId,
+        statuses: HashMap<ClientId, PresenceStatus>,
+    },
     StorageUpdated,
     CommentCreated,
     CommentEdited,
@@ -96,6 +188,7 @@ pub enum ServerMessageType {
     ThreadDeleted,
     ThreadMetadataUpdated,
     Notification,
+    Presentation(PresentationServerMessage),
     // event types associated with a specific room type. Liveblocks has quite an interesting pattern of
     // just sating stoage updated, which is just a prompt for the client to fetch the latest state.
 }
@@ -107,16 +200,18 @@ impl ServerMessageTypeLike for ServerMessageType {
             ServerMessageType::RoomDeleted { .. } => "RoomDeleted",
             ServerMessageType::RoomJoined { .. } => "RoomJoined",
             ServerMessageType::RoomLeft { .. } => "RoomLeft",
-            ServerMessageType::StorageUpdated { .. } => "StorageUpdated",
-            ServerMessageType::CommentCreated { .. } => "CommentCreated",
-            ServerMessageType::CommentEdited { .. } => "CommentEdited",
-            ServerMessageType::CommentDeleted { .. } => "CommentDeleted",
-            ServerMessageType::CommentReactionAdded { .. } => "CommentReactionAdded",
-            ServerMessageType::CommentReactionRemoved { .. } => "CommentReactionRemoved",
-            ServerMessageType::ThreadCreated { .. } => "ThreadCreated",
-            ServerMessageType::ThreadDeleted { .. } => "ThreadDeleted",
-            ServerMessageType::ThreadMetadataUpdated { .. } => "ThreadMetadataUpdated",
-            ServerMessageType::Notification { .. } => "Notification",
+            ServerMessageType::PresenceStatusChanged { .. } => "PresenceStatusChanged",
+            ServerMessageType::StorageUpdated => "StorageUpdated",
+            ServerMessageType::CommentCreated => "CommentCreated",
+            ServerMessageType::CommentEdited => "CommentEdited",
+            ServerMessageType::CommentDeleted => "CommentDeleted",
+            ServerMessageType::CommentReactionAdded => "CommentReactionAdded",
+            ServerMessageType::CommentReactionRemoved => "CommentReactionRemoved",
+            ServerMessageType::ThreadCreated => "ThreadCreated",
+            ServerMessageType::ThreadDeleted => "ThreadDeleted",
+            ServerMessageType::ThreadMetadataUpdated => "ThreadMetadataUpdated",
+            ServerMessageType::Notification => "Notification",
+            ServerMessageType::Presentation(msg) => msg.name(),
         }
     }
 }
@@ -0,0 +1,148 @@
+//! MessagePack for the JSON values messages are built from, shared by the transports
+//! that speak it and by recordings.
+
+use serde_json::Value;
+
+#[derive(thiserror::Error, Debug)]
+pub enum MsgPackError {
+    #[error("MessagePack encode: {0}")]
+    Encode(#[from] rmpv::encode::Error),
+    #[error("MessagePack decode: {0}")]
+    Decode(#[from] rmpv::decode::Error),
+    /// MessagePack that has no JSON equivalent, such as binary or non-string map keys.
+    #[error("Unsupported MessagePack {0}")]
+    Unsupported(&'static str),
+}
+
+/// Appends `value` to `buf` as a single MessagePack value.
+pub fn write(buf: &mut Vec<u8>, value: Value) -> Result<(), MsgPackError> {
+    rmpv::encode::write_value(buf, &json_to_msgpack(value))?;
+    Ok(())
+}
+
+/// Reads one MessagePack value off the front of `bytes`, leaving the rest.
+pub fn read(bytes: &mut &[u8]) -> Result<Value, MsgPackError> {
+    msgpack_to_json(rmpv::decode::read_value(bytes)?)
+}
+
+pub fn json_to_msgpack(value: Value) -> rmpv::Value {
+    match value {
+        Value::Null => rmpv::Value::Nil,
+        Value::Bool(b) => rmpv::Value::Boolean(b),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                rmpv::Value::from(i)
+            } else if let Some(u) = n.as_u64() {
+                rmpv::Value::from(u)
+            } else {
+                rmpv::Value::F64(n.as_f64().unwrap_or_default())
+            }
+        }
+        Value::String(s) => rmpv::Value::from(s),
+        Value::Array(items) => rmpv::Value::Array(items.into_iter().map(json_to_msgpack).collect()),
+        Value::Object(map) => rmpv::Value::Map(
+            map.into_iter()
+                .map(|(k, v)| (rmpv::Value::from(k), json_to_msgpack(v)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn msgpack_to_json(value: rmpv::Value) -> Result<Value, MsgPackError> {
+    Ok(match value {
+        rmpv::Value::Nil => Value::Null,
+        rmpv::Value::Boolean(b) => Value::Bool(b),
+        rmpv::Value::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(i), _) => Value::from(i),
+            (None, Some(u)) => Value::from(u),
+            _ => return Err(MsgPackError::Unsupported("integer")),
+        },
+        rmpv::Value::F32(f) => Value::from(f64::from(f)),
+        rmpv::Value::F64(f) => Value::from(f),
+        rmpv::Value::String(s) => {
+            Value::String(s.into_str().ok_or(MsgPackError::Unsupported("string"))?)
+        }
+        rmpv::Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(msgpack_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        rmpv::Value::Map(entries) => {
+            let mut map = serde_json::Map::with_capacity(entries.len());
+            for (k, v) in entries {
+                let key = k
+                    .as_str()
+                    .ok_or(MsgPackError::Unsupported("map key"))?
+                    .to_string();
+                map.insert(key, msgpack_to_json(v)?);
+            }
+            Value::Object(map)
+        }
+        rmpv::Value::Binary(_) => return Err(MsgPackError::Unsupported("binary")),
+        rmpv::Value::Ext(..) => return Err(MsgPackError::Unsupported("extension")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(value: Value) -> Value {
+        let mut buf = Vec::new();
+        write(&mut buf, value).unwrap();
+        let mut bytes = &buf[..];
+        let value = read(&mut bytes).unwrap();
+        assert!(bytes.is_empty());
+        value
+    }
+
+    #[test]
+    fn json_survives_a_round_trip() {
+        let value = json!({
+            "null": null,
+            "flag": true,
+            "int": -3,
+            "float": 1.5,
+            "text": "slide",
+            "list": [1, "two", { "three": [] }],
+        });
+        assert_eq!(round_trip(value.clone()), value);
+    }
+
+    #[test]
+    fn integers_keep_their_full_range() {
+        assert_eq!(
+            json_to_msgpack(json!(u64::MAX)),
+            rmpv::Value::from(u64::MAX)
+        );
+        assert_eq!(round_trip(json!(u64::MAX)), json!(u64::MAX));
+        assert_eq!(round_trip(json!(i64::MIN)), json!(i64::MIN));
+    }
+
+    #[test]
+    fn floats_become_json_numbers() {
+        assert_eq!(json_to_msgpack(json!(1.5)), rmpv::Value::F64(1.5));
+        assert_eq!(msgpack_to_json(rmpv::Value::F32(1.5)).unwrap(), json!(1.5));
+        assert_eq!(
+            msgpack_to_json(rmpv::Value::F64(-0.25)).unwrap(),
+            json!(-0.25)
+        );
+    }
+
+    #[test]
+    fn msgpack_without_a_json_equivalent_is_rejected() {
+        let unsupported = |value| match msgpack_to_json(value) {
+            Err(MsgPackError::Unsupported(what)) => what,
+            other => panic!("expected an unsupported value, got {other:?}"),
+        };
+
+        let int_key = rmpv::Value::Map(vec![(rmpv::Value::from(1), rmpv::Value::Nil)]);
+        assert_eq!(unsupported(int_key), "map key");
+        assert_eq!(unsupported(rmpv::Value::Binary(vec![1, 2])), "binary");
+        // Nested ones too
+        let nested = rmpv::Value::Array(vec![rmpv::Value::Ext(1, vec![0])]);
+        assert_eq!(unsupported(nested), "extension");
+    }
+}
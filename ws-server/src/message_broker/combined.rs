@@ -0,0 +1,254 @@
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::room::{RoomError, client_id::ClientId};
+
+use super::{
+    MessageBroker, socket_io::SocketIoMessageBroker, sse::SseBroadcaster,
+    websocket::WebSocketMessageBroker,
+};
+
+/// [`MessageBroker`] spanning both client transports, so socket.io and plain
+/// WebSocket clients can share the same rooms.
+///
+/// Plain WebSocket clients are tracked by the [`WebSocketMessageBroker`]; any other
+/// client id is assumed to be a socket.io socket. Room broadcasts are also published
+/// to read-only SSE observers through the [`SseBroadcaster`].
+#[derive(Clone, Default)]
+pub struct CombinedMessageBroker {
+    pub socket_io: SocketIoMessageBroker,
+    pub websocket: WebSocketMessageBroker,
+    pub sse: SseBroadcaster,
+}
+
+impl CombinedMessageBroker {
+    #[must_use]
+    pub fn new(
+        socket_io: SocketIoMessageBroker,
+        websocket: WebSocketMessageBroker,
+        sse: SseBroadcaster,
+    ) -> Self {
+        Self {
+            socket_io,
+            websocket,
+            sse,
+        }
+    }
+
+    fn is_websocket(&self, client_id: &ClientId) -> bool {
+        self.websocket.is_connected(client_id)
+    }
+}
+
+impl AsRef<WebSocketMessageBroker> for CombinedMessageBroker {
+    fn as_ref(&self) -> &WebSocketMessageBroker {
+        &self.websocket
+    }
+}
+
+impl AsRef<SseBroadcaster> for CombinedMessageBroker {
+    fn as_ref(&self) -> &SseBroadcaster {
+        &self.sse
+    }
+}
+
+/// Folds the results of delivering over both transports into one.
+fn combine(
+    socket_io: Result<(), RoomError>,
+    websocket: Result<(), RoomError>,
+) -> Result<(), RoomError> {
+    match (socket_io, websocket) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(err), Ok(())) | (Ok(()), Err(err)) => Err(err),
+        (Err(a), Err(b)) => Err(RoomError::NetworkError(format!("{a}; {b}"))),
+    }
+}
+
+#[async_trait]
+impl MessageBroker for CombinedMessageBroker {
+    type Error = RoomError;
+
+    async fn send<P>(
+        &self,
+        recipients: &[ClientId],
+        msg_name: &str,
+        payload: P,
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        let (websocket, socket_io): (Vec<ClientId>, Vec<ClientId>) = recipients
+            .iter()
+            .cloned()
+            .partition(|client_id| self.is_websocket(client_id));
+
+        let socket_io = if socket_io.is_empty() {
+            Ok(())
+        } else {
+            self.socket_io.send(&socket_io, msg_name, &payload).await
+        };
+        let websocket = if websocket.is_empty() {
+            Ok(())
+        } else {
+            self.websocket.send(&websocket, msg_name, &payload).await
+        };
+
+        combine(socket_io, websocket)
+    }
+
+    async fn broadcast<P>(
+        &self,
+        room_id: &str,
+        msg_name: &str,
+        payload: P,
+        exclude: &[ClientId],
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        // Observers aren't clients, so exclusions never apply to them
+        let observed = self.sse.publish(room_id, msg_name, &payload);
+        combine(
+            combine(
+                self.socket_io.broadcast(room_id, msg_name, &payload, exclude).await,
+                self.websocket.broadcast(room_id, msg_name, &payload, exclude).await,
+            ),
+            observed,
+        )
+    }
+
+    async fn broadcast_all<P>(
+        &self,
+        msg_name: &str,
+        payload: P,
+        exclude: &[ClientId],
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        combine(
+            self.socket_io.broadcast_all(msg_name, &payload, exclude).await,
+            self.websocket.broadcast_all(msg_name, &payload, exclude).await,
+        )
+    }
+
+    async fn join(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        if self.is_websocket(client_id) {
+            self.websocket.join(room_id, client_id).await
+        } else {
+            self.socket_io.join(room_id, client_id).await
+        }
+    }
+
+    async fn leave(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        if self.is_websocket(client_id) {
+            self.websocket.leave(room_id, client_id).await
+        } else {
+            self.socket_io.leave(room_id, client_id).await
+        }
+    }
+
+    fn is_connected(&self, client_id: &ClientId) -> bool {
+        self.is_websocket(client_id) || self.socket_io.is_connected(client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{Value, json};
+    use socketioxide::{SocketIo, extract::SocketRef};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message as WsFrame};
+
+    use super::*;
+    use crate::message_broker::websocket::WsEncoding;
+
+    type SocketIoClient = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Serves socket.io on a local port and binds it to `broker`.
+    async fn serve_socket_io(broker: &SocketIoMessageBroker) -> String {
+        let (layer, io) = SocketIo::new_layer();
+        io.ns("/", |_: SocketRef| {});
+        assert!(broker.bind(io));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, axum::Router::new().layer(layer)).into_future());
+        format!("ws://{addr}/socket.io/?EIO=4&transport=websocket")
+    }
+
+    /// Next engine.io packet, answering any pings on the way.
+    async fn next_text(client: &mut SocketIoClient) -> String {
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                WsFrame::Text(text) if text.as_str() == "2" => {
+                    client.send(WsFrame::text("3")).await.unwrap();
+                }
+                WsFrame::Text(text) => return text.to_string(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Connects a raw engine.io client to the root namespace and returns its socket id.
+    async fn connect_socket_io(url: &str) -> (ClientId, SocketIoClient) {
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        assert!(next_text(&mut client).await.starts_with('0'));
+        client.send(WsFrame::text("40")).await.unwrap();
+
+        let connected = next_text(&mut client).await;
+        let ack: Value = serde_json::from_str(connected.strip_prefix("40").unwrap()).unwrap();
+        (ack["sid"].as_str().unwrap().to_string(), client)
+    }
+
+    #[tokio::test]
+    async fn clients_are_reached_over_their_own_transport() {
+        let broker = CombinedMessageBroker::default();
+        let url = serve_socket_io(&broker.socket_io).await;
+        let (sio_id, mut sio_client) = connect_socket_io(&url).await;
+        let (ws_id, mut ws_rx) = broker.websocket.connect(WsEncoding::Json);
+        assert!(ws_id.starts_with("ws_"));
+
+        broker.join("room", &sio_id).await.unwrap();
+        broker.join("room", &ws_id).await.unwrap();
+        assert!(broker.socket_io.is_connected(&sio_id) && !broker.websocket.is_connected(&sio_id));
+        assert!(broker.is_connected(&ws_id) && !broker.socket_io.is_connected(&ws_id));
+        let mut observer = broker.sse.subscribe("room", None);
+
+        broker
+            .broadcast("room", "storage", json!({ "n": 1 }), &[])
+            .await
+            .unwrap();
+
+        assert_eq!(next_text(&mut sio_client).await, r#"42["storage",{"n":1}]"#);
+        let frame: Value = WsEncoding::decode(&ws_rx.recv().await.unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame, json!({ "event": "storage", "data": { "n": 1 } }));
+        let event = observer.live.recv().await.unwrap();
+        assert_eq!(
+            (event.event.as_str(), event.data.as_str()),
+            ("storage", r#"{"n":1}"#)
+        );
+
+        // Direct sends only go out over the recipient's transport
+        broker
+            .send(std::slice::from_ref(&ws_id), "message", json!("ws only"))
+            .await
+            .unwrap();
+        broker
+            .send(std::slice::from_ref(&sio_id), "message", json!("sio only"))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_text(&mut sio_client).await,
+            r#"42["message","sio only"]"#
+        );
+        let frame: Value = WsEncoding::decode(&ws_rx.recv().await.unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame["data"], json!("ws only"));
+        assert!(ws_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::mpsc;
+
+use crate::room::{RoomError, client_id::ClientId};
+
+use super::MessageBroker;
+
+/// A single message handed to a client by the [`InMemoryMessageBroker`].
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub client_id: ClientId,
+    pub msg_name: String,
+    pub payload: serde_json::Value,
+}
+
+impl Delivery {
+    /// Deserializes the payload, e.g. back into a `Message<T>`.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_value(self.payload.clone())
+    }
+}
+
+#[derive(Default)]
+struct BrokerState {
+    clients: HashMap<ClientId, mpsc::UnboundedSender<Delivery>>,
+    rooms: HashMap<String, HashSet<ClientId>>,
+    log: Vec<Delivery>,
+}
+
+/// In-process [`MessageBroker`] for tests and embedded use.
+///
+/// Each connected client gets an unbounded channel, room membership is kept in a
+/// table, and every successful delivery is appended to a log that can be inspected
+/// to assert exactly which clients received which messages.
+#[derive(Clone, Default)]
+pub struct InMemoryMessageBroker {
+    state: Arc<Mutex<BrokerState>>,
+}
+
+impl InMemoryMessageBroker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, BrokerState> {
+        // A panic while holding the lock can't leave the maps half-updated
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a client and returns the receiving end of its channel.
+    /// Connecting an already connected client replaces its channel.
+    pub fn connect(&self, client_id: impl Into<ClientId>) -> mpsc::UnboundedReceiver<Delivery> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.state().clients.insert(client_id.into(), tx);
+        rx
+    }
+
+    /// Drops a client's channel and removes it from every room.
+    pub fn disconnect(&self, client_id: &ClientId) {
+        let mut state = self.state();
+        state.clients.remove(client_id);
+        for members in state.rooms.values_mut() {
+            members.remove(client_id);
+        }
+        state.rooms.retain(|_, members| !members.is_empty());
+    }
+
+    /// Current members of a room, sorted for stable assertions.
+    pub fn members(&self, room_id: &str) -> Vec<ClientId> {
+        let mut members: Vec<ClientId> = self
+            .state()
+            .rooms
+            .get(room_id)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default();
+        members.sort();
+        members
+    }
+
+    /// Every delivery made so far, in order.
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.state().log.clone()
+    }
+
+    /// Deliveries made to a single client, in order.
+    pub fn deliveries_to(&self, client_id: &ClientId) -> Vec<Delivery> {
+        self.state()
+            .log
+            .iter()
+            .filter(|delivery| delivery.client_id == *client_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns and clears the delivery log.
+    pub fn take_deliveries(&self) -> Vec<Delivery> {
+        std::mem::take(&mut self.state().log)
+    }
+
+    /// Delivers to each recipient, recording successes. Fails if any recipient could not
+    /// be reached, after attempting all of them.
+    fn deliver<'a, P>(
+        &self,
+        recipients: impl IntoIterator<Item = &'a ClientId>,
+        msg_name: &str,
+        payload: &P,
+    ) -> Result<(), RoomError>
+    where
+        P: Serialize,
+    {
+        let payload = serde_json::to_value(payload)?;
+        let mut state = self.state();
+        let mut failed = Vec::new();
+
+        for client_id in recipients {
+            let delivery = Delivery {
+                client_id: client_id.clone(),
+                msg_name: msg_name.to_string(),
+                payload: payload.clone(),
+            };
+
+            let sent = state
+                .clients
+                .get(client_id)
+                .is_some_and(|tx| tx.send(delivery.clone()).is_ok());
+            if sent {
+                state.log.push(delivery);
+            } else {
+                failed.push(client_id.clone());
+            }
+        }
+
+        match failed.as_slice() {
+            [] => Ok(()),
+            [client_id] => Err(RoomError::ClientNotFound(client_id.clone())),
+            _ => Err(RoomError::NetworkError(format!(
+                "Failed to deliver '{msg_name}' to {}",
+                failed.join(", ")
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageBroker for InMemoryMessageBroker {
+    type Error = RoomError;
+
+    async fn send<P>(
+        &self,
+        recipients: &[ClientId],
+        msg_name: &str,
+        payload: P,
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        self.deliver(recipients, msg_name, &payload)
+    }
+
+    async fn broadcast<P>(
+        &self,
+        room_id: &str,
+        msg_name: &str,
+        payload: P,
+        exclude: &[ClientId],
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        let recipients: Vec<ClientId> = self
+            .members(room_id)
+            .into_iter()
+            .filter(|client_id| !exclude.contains(client_id))
+            .collect();
+        self.deliver(&recipients, msg_name, &payload)
+    }
+
+    async fn broadcast_all<P>(
+        &self,
+        msg_name: &str,
+        payload: P,
+        exclude: &[ClientId],
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        let mut recipients: Vec<ClientId> = self
+            .state()
+            .clients
+            .keys()
+            .filter(|client_id| !exclude.contains(client_id))
+            .cloned()
+            .collect();
+        recipients.sort();
+        self.deliver(&recipients, msg_name, &payload)
+    }
+
+    async fn join(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        let mut state = self.state();
+        if !state.clients.contains_key(client_id) {
+            return Err(RoomError::ClientNotFound(client_id.clone()));
+        }
+        state
+            .rooms
+            .entry(room_id.to_string())
+            .or_default()
+            .insert(client_id.clone());
+        Ok(())
+    }
+
+    async fn leave(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        let mut state = self.state();
+        if let Some(members) = state.rooms.get_mut(room_id) {
+            members.remove(client_id);
+            if members.is_empty() {
+                state.rooms.remove(room_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self, client_id: &ClientId) -> bool {
+        self.state().clients.contains_key(client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn broadcast_reaches_room_members_except_excluded() {
+        let broker = InMemoryMessageBroker::new();
+        let mut alice = broker.connect("alice");
+        let _bob = broker.connect("bob");
+        let _carol = broker.connect("carol");
+        broker.join("room", &"alice".to_string()).await.unwrap();
+        broker.join("room", &"bob".to_string()).await.unwrap();
+
+        broker
+            .broadcast("room", "message", json!({ "n": 1 }), &["bob".to_string()])
+            .await
+            .unwrap();
+
+        let deliveries = broker.take_deliveries();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].client_id, "alice");
+        assert_eq!(alice.recv().await.unwrap().payload, json!({ "n": 1 }));
+        assert!(broker.deliveries().is_empty());
+    }
+}
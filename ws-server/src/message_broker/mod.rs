@@ -1,15 +1,33 @@
 use async_trait::async_trait;
 use serde::Serialize;
 use std::error::Error as StdError;
+pub mod combined;
+pub mod memory;
 pub mod socket_io;
+pub mod sse;
+pub mod websocket;
 
-use crate::room::client_id::ClientId; // Alias for clarity
+use crate::room::{RoomError, client_id::ClientId}; // Alias for clarity
+
+/// Event name used for [`Message`](crate::message::Message)s sent to clients.
+pub const MESSAGE_EVENT: &str = "message";
+/// Event name used for storage updates broadcast to a room.
+pub const STORAGE_EVENT: &str = "storage";
+/// Event name used for batched presence updates broadcast to a room.
+pub const PRESENCE_EVENT: &str = "presence";
+/// Event name used for the full room state sent to a client when it joins.
+pub const STATE_EVENT: &str = "state";
+/// Event name used for [`StorageCatchUp`](crate::message::StorageCatchUp) replies.
+pub const CATCH_UP_EVENT: &str = "catch_up";
+/// Event name used to tell a client one of its messages was rejected.
+pub const ERROR_EVENT: &str = "error";
 
 #[async_trait]
 pub trait MessageBroker: Send + Sync + 'static {
     // Add Send + Sync + 'static for broad usability (e.g., Arc<dyn Trait>)
     /// Associated error type for operations.
-    type Error: StdError + Send + Sync + 'static;
+    /// Must convert into a [`RoomError`] so the room runtime can surface it.
+    type Error: StdError + Into<RoomError> + Send + Sync + 'static;
 
     /// Sends a message directly to one or more specific clients.
     async fn send<P>(
@@ -41,4 +59,13 @@ pub trait MessageBroker: Send + Sync + 'static {
     ) -> Result<(), Self::Error>
     where
         P: Serialize + Send + Sync;
+
+    /// Adds a client to a room's broadcast group.
+    async fn join(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error>;
+
+    /// Removes a client from a room's broadcast group.
+    async fn leave(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error>;
+
+    /// `true` if the client still has a live connection through this broker.
+    fn is_connected(&self, client_id: &ClientId) -> bool;
 }
@@ -1,21 +1,91 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+
 use async_trait::async_trait;
 use serde::Serialize;
+use socketioxide::{SocketIo, extract::SocketRef, socket::Sid};
 
 use crate::room::{RoomError, client_id::ClientId};
 
 use super::MessageBroker;
 
-#[derive(Clone)]
+/// [`MessageBroker`] backed by a socketioxide [`SocketIo`] handle.
+///
+/// The socket.io layer is usually built after the application state (it needs the state
+/// for its handlers), so the handle is attached later with [`SocketIoMessageBroker::bind`].
+#[derive(Clone, Default)]
 pub struct SocketIoMessageBroker {
-    // Implementation details here
+    io: Arc<OnceLock<SocketIo>>,
+}
+
+impl SocketIoMessageBroker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches the running socket.io instance. Returns `false` if one was already bound.
+    pub fn bind(&self, io: SocketIo) -> bool {
+        self.io.set(io).is_ok()
+    }
+
+    fn io(&self) -> Result<&SocketIo, RoomError> {
+        self.io
+            .get()
+            .ok_or_else(|| RoomError::NetworkError("socket.io is not bound".to_string()))
+    }
+
+    fn socket(&self, client_id: &ClientId) -> Result<SocketRef, RoomError> {
+        let sid = Sid::from_str(client_id).map_err(|e| {
+            RoomError::NetworkError(format!("Invalid socket id '{client_id}': {e}"))
+        })?;
+
+        self.io()?
+            .get_socket(sid)
+            .ok_or_else(|| RoomError::ClientNotFound(client_id.clone()))
+    }
+
+    /// Emits to each socket individually, skipping excluded ones.
+    /// Used when an exclusion list rules out a single adapter broadcast.
+    fn emit_each<P>(
+        sockets: Vec<SocketRef>,
+        msg_name: &str,
+        payload: &P,
+        exclude: &[ClientId],
+    ) -> Result<(), RoomError>
+    where
+        P: Serialize + Send + Sync,
+    {
+        let failures: Vec<String> = sockets
+            .into_iter()
+            .filter(|socket| !exclude.iter().any(|id| *id == socket.id.to_string()))
+            .filter_map(|socket| {
+                socket
+                    .emit(msg_name, payload)
+                    .err()
+                    .map(|e| format!("{}: {e}", socket.id))
+            })
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(RoomError::NetworkError(format!(
+                "Failed to emit '{msg_name}' to {}",
+                failures.join(", ")
+            )))
+        }
+    }
 }
 
 #[async_trait]
 impl MessageBroker for SocketIoMessageBroker {
-    // Add Send + Sync + 'static for broad usability (e.g., Arc<dyn Trait>)
-    /// Associated error type for operations.
     type Error = RoomError;
+
     /// Sends a message directly to one or more specific clients.
+    /// Every recipient is attempted; the error lists the ones that failed.
     async fn send<P>(
         &self,
         recipients: &[ClientId],
@@ -25,7 +95,28 @@ impl MessageBroker for SocketIoMessageBroker {
     where
         P: Serialize + Send + Sync,
     {
-        todo!()
+        let failures: Vec<String> = recipients
+            .iter()
+            .filter_map(|client_id| {
+                self.socket(client_id)
+                    .and_then(|socket| {
+                        socket
+                            .emit(msg_name, &payload)
+                            .map_err(|e| RoomError::NetworkError(e.to_string()))
+                    })
+                    .err()
+                    .map(|e| format!("{client_id}: {e}"))
+            })
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(RoomError::NetworkError(format!(
+                "Failed to send '{msg_name}' to {}",
+                failures.join(", ")
+            )))
+        }
     }
 
     /// Broadcasts a message to all clients in a specific room, potentially excluding some.
@@ -39,7 +130,18 @@ impl MessageBroker for SocketIoMessageBroker {
     where
         P: Serialize + Send + Sync,
     {
-        todo!()
+        let io = self.io()?;
+
+        if exclude.is_empty() {
+            return io
+                .to(room_id.to_string())
+                .emit(msg_name, &payload)
+                .await
+                .map_err(|e| RoomError::NetworkError(e.to_string()));
+        }
+
+        let sockets = io.within(room_id.to_string()).sockets();
+        Self::emit_each(sockets, msg_name, &payload, exclude)
     }
 
     /// Broadcasts to all connected clients (might not be applicable/efficient for all backends).
@@ -52,6 +154,32 @@ impl MessageBroker for SocketIoMessageBroker {
     where
         P: Serialize + Send + Sync,
     {
-        todo!()
+        let io = self.io()?;
+
+        if exclude.is_empty() {
+            return io
+                .emit(msg_name, &payload)
+                .await
+                .map_err(|e| RoomError::NetworkError(e.to_string()));
+        }
+
+        Self::emit_each(io.sockets(), msg_name, &payload, exclude)
+    }
+
+    async fn join(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        self.socket(client_id)?.join(room_id.to_string());
+        Ok(())
+    }
+
+    async fn leave(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        // The socket may already be gone (e.g. on disconnect), which leaves it nowhere
+        if let Ok(socket) = self.socket(client_id) {
+            socket.leave(room_id.to_string());
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self, client_id: &ClientId) -> bool {
+        self.socket(client_id).is_ok()
     }
 }
@@ -0,0 +1,191 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::room::RoomError;
+
+/// Events retained per room for `Last-Event-ID` resume, unless configured otherwise.
+pub const DEFAULT_SSE_BUFFER: usize = 256;
+
+/// A room broadcast as seen by read-only observers.
+#[derive(Debug, Clone)]
+pub struct RoomEvent {
+    /// Per-room, strictly increasing id, used as the SSE event id.
+    pub id: u64,
+    /// Event name, the same one socket.io clients receive the payload under.
+    pub event: String,
+    /// JSON-encoded payload.
+    pub data: String,
+}
+
+/// Where an observer picks up a room's event stream.
+pub struct Subscription {
+    /// `true` if events the observer asked to resume from are no longer buffered,
+    /// so it has to refetch the room before applying `replay`.
+    pub missed: bool,
+    /// Buffered events the observer hasn't seen yet, oldest first.
+    pub replay: Vec<RoomEvent>,
+    /// Events published after the subscription was taken.
+    pub live: broadcast::Receiver<RoomEvent>,
+}
+
+struct RoomEvents {
+    next_id: u64,
+    buffer: VecDeque<RoomEvent>,
+    tx: broadcast::Sender<RoomEvent>,
+}
+
+impl RoomEvents {
+    fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            next_id: 1,
+            buffer: VecDeque::with_capacity(capacity),
+            tx,
+        }
+    }
+
+    fn first_buffered_id(&self) -> u64 {
+        self.buffer.front().map_or(self.next_id, |event| event.id)
+    }
+}
+
+/// Fans room broadcasts out to Server-Sent Events observers.
+///
+/// Observers never join rooms or receive direct messages; they only see what is
+/// broadcast to a room. The last `capacity` events of each room are kept so a
+/// reconnecting observer can resume from its `Last-Event-ID`.
+#[derive(Clone)]
+pub struct SseBroadcaster {
+    rooms: Arc<Mutex<HashMap<String, RoomEvents>>>,
+    capacity: usize,
+}
+
+impl Default for SseBroadcaster {
+    fn default() -> Self {
+        Self::new(DEFAULT_SSE_BUFFER)
+    }
+}
+
+impl SseBroadcaster {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn rooms(&self) -> MutexGuard<'_, HashMap<String, RoomEvents>> {
+        self.rooms.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records a room broadcast and hands it to every live observer of the room.
+    pub fn publish<P>(&self, room_id: &str, event: &str, payload: &P) -> Result<(), RoomError>
+    where
+        P: Serialize,
+    {
+        let data = serde_json::to_string(payload)?;
+        let mut rooms = self.rooms();
+        let room = rooms
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomEvents::new(self.capacity));
+
+        let event = RoomEvent {
+            id: room.next_id,
+            event: event.to_string(),
+            data,
+        };
+        room.next_id += 1;
+
+        if room.buffer.len() == self.capacity {
+            room.buffer.pop_front();
+        }
+        room.buffer.push_back(event.clone());
+        // No observers is not an error; the event stays buffered for resume
+        let _ = room.tx.send(event);
+        Ok(())
+    }
+
+    /// Subscribes to a room, replaying buffered events after `last_event_id`.
+    ///
+    /// Replay and subscription are taken under the same lock as [`Self::publish`],
+    /// so no event is skipped or delivered twice between the two.
+    pub fn subscribe(&self, room_id: &str, last_event_id: Option<u64>) -> Subscription {
+        let mut rooms = self.rooms();
+        let room = rooms
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomEvents::new(self.capacity));
+        let live = room.tx.subscribe();
+
+        let Some(last_event_id) = last_event_id else {
+            return Subscription {
+                missed: false,
+                replay: Vec::new(),
+                live,
+            };
+        };
+
+        // An id from the future means the ids were reset (e.g. by a restart)
+        let missed = last_event_id >= room.next_id
+            || last_event_id.saturating_add(1) < room.first_buffered_id();
+        let replay = room
+            .buffer
+            .iter()
+            .filter(|event| missed || event.id > last_event_id)
+            .cloned()
+            .collect();
+
+        Subscription {
+            missed,
+            replay,
+            live,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ids(events: &[RoomEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.id).collect()
+    }
+
+    #[tokio::test]
+    async fn resumes_after_last_event_id() {
+        let sse = SseBroadcaster::new(8);
+        for n in 0..3 {
+            sse.publish("room", "message", &json!({ "n": n })).unwrap();
+        }
+
+        let mut subscription = sse.subscribe("room", Some(1));
+        assert!(!subscription.missed);
+        assert_eq!(ids(&subscription.replay), vec![2, 3]);
+
+        sse.publish("room", "storage", &json!({})).unwrap();
+        let live = subscription.live.recv().await.unwrap();
+        assert_eq!((live.id, live.event.as_str()), (4, "storage"));
+    }
+
+    #[test]
+    fn reports_events_that_fell_out_of_the_buffer() {
+        let sse = SseBroadcaster::new(2);
+        for n in 0..5 {
+            sse.publish("room", "message", &json!({ "n": n })).unwrap();
+        }
+
+        let subscription = sse.subscribe("room", Some(1));
+        assert!(subscription.missed);
+        assert_eq!(ids(&subscription.replay), vec![4, 5]);
+
+        // Ids from before a restart can't be trusted either
+        assert!(sse.subscribe("other", Some(7)).missed);
+        assert!(!sse.subscribe("room", Some(5)).missed);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use axum::extract::ws::Message as WsMessage;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    message::msgpack,
+    room::{RoomError, client_id::ClientId},
+};
+
+use super::MessageBroker;
+
+/// Wire encoding negotiated by a plain WebSocket client (`?encoding=msgpack`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsEncoding {
+    /// JSON in text frames.
+    #[default]
+    Json,
+    /// MessagePack in binary frames.
+    MsgPack,
+}
+
+/// Envelope for every frame sent to a plain WebSocket client.
+/// `event` carries the same name socket.io clients receive the payload under.
+#[derive(Debug, Serialize)]
+struct ServerFrame<'a> {
+    event: &'a str,
+    data: Value,
+}
+
+impl WsEncoding {
+    /// Encodes an event into a WebSocket frame.
+    pub fn encode(self, event: &str, data: Value) -> Result<WsMessage, RoomError> {
+        let frame = ServerFrame { event, data };
+        match self {
+            Self::Json => Ok(WsMessage::Text(serde_json::to_string(&frame)?.into())),
+            Self::MsgPack => {
+                let mut buf = Vec::new();
+                msgpack::write(&mut buf, serde_json::to_value(&frame)?)
+                    .map_err(|e| RoomError::NetworkError(e.to_string()))?;
+                Ok(WsMessage::Binary(buf.into()))
+            }
+        }
+    }
+
+    /// Decodes a data frame sent by a client. Control frames yield `None`.
+    /// Text frames are always JSON and binary frames always MessagePack,
+    /// whatever encoding the client asked to receive.
+    pub fn decode<T: DeserializeOwned>(msg: &WsMessage) -> Result<Option<T>, RoomError> {
+        match msg {
+            WsMessage::Text(text) => Ok(Some(serde_json::from_str(text.as_str())?)),
+            WsMessage::Binary(bytes) => {
+                let value = msgpack::read(&mut &bytes[..])
+                    .map_err(|e| RoomError::NetworkError(e.to_string()))?;
+                Ok(Some(serde_json::from_value(value)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+struct Connection {
+    tx: mpsc::UnboundedSender<WsMessage>,
+    encoding: WsEncoding,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    connections: HashMap<ClientId, Connection>,
+    rooms: HashMap<String, HashSet<ClientId>>,
+}
+
+/// [`MessageBroker`] for clients connected over a plain axum WebSocket.
+///
+/// Each connection owns an outgoing channel that its socket task drains; the broker
+/// only encodes frames and pushes them onto those channels.
+#[derive(Clone, Default)]
+pub struct WebSocketMessageBroker {
+    state: Arc<Mutex<BrokerState>>,
+}
+
+impl WebSocketMessageBroker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, BrokerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a new connection and returns its client id and outgoing frames.
+    pub fn connect(
+        &self,
+        encoding: WsEncoding,
+    ) -> (ClientId, mpsc::UnboundedReceiver<WsMessage>) {
+        let client_id = format!("ws_{}", Uuid::new_v4());
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.state()
+            .connections
+            .insert(client_id.clone(), Connection { tx, encoding });
+        (client_id, rx)
+    }
+
+    /// Forgets a connection and removes it from every room.
+    pub fn disconnect(&self, client_id: &ClientId) {
+        let mut state = self.state();
+        state.connections.remove(client_id);
+        for members in state.rooms.values_mut() {
+            members.remove(client_id);
+        }
+        state.rooms.retain(|_, members| !members.is_empty());
+    }
+
+    fn deliver<'a, P>(
+        &self,
+        recipients: impl IntoIterator<Item = &'a ClientId>,
+        msg_name: &str,
+        payload: &P,
+    ) -> Result<(), RoomError>
+    where
+        P: Serialize,
+    {
+        let data = serde_json::to_value(payload)?;
+        let state = self.state();
+        // Encode at most once per encoding, however many recipients share it
+        let mut frames: HashMap<WsEncoding, WsMessage> = HashMap::new();
+        let mut failed = Vec::new();
+
+        for client_id in recipients {
+            let Some(connection) = state.connections.get(client_id) else {
+                failed.push(client_id.clone());
+                continue;
+            };
+
+            let frame = match frames.get(&connection.encoding) {
+                Some(frame) => frame.clone(),
+                None => {
+                    let frame = connection.encoding.encode(msg_name, data.clone())?;
+                    frames.insert(connection.encoding, frame.clone());
+                    frame
+                }
+            };
+
+            if connection.tx.send(frame).is_err() {
+                failed.push(client_id.clone());
+            }
+        }
+
+        match failed.as_slice() {
+            [] => Ok(()),
+            [client_id] => Err(RoomError::ClientNotFound(client_id.clone())),
+            _ => Err(RoomError::NetworkError(format!(
+                "Failed to deliver '{msg_name}' to {}",
+                failed.join(", ")
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageBroker for WebSocketMessageBroker {
+    type Error = RoomError;
+
+    async fn send<P>(
+        &self,
+        recipients: &[ClientId],
+        msg_name: &str,
+        payload: P,
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        self.deliver(recipients, msg_name, &payload)
+    }
+
+    async fn broadcast<P>(
+        &self,
+        room_id: &str,
+        msg_name: &str,
+        payload: P,
+        exclude: &[ClientId],
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        let recipients: Vec<ClientId> = self
+            .state()
+            .rooms
+            .get(room_id)
+            .map(|members| {
+                members
+                    .iter()
+                    .filter(|client_id| !exclude.contains(client_id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.deliver(&recipients, msg_name, &payload)
+    }
+
+    async fn broadcast_all<P>(
+        &self,
+        msg_name: &str,
+        payload: P,
+        exclude: &[ClientId],
+    ) -> Result<(), Self::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        let recipients: Vec<ClientId> = self
+            .state()
+            .connections
+            .keys()
+            .filter(|client_id| !exclude.contains(client_id))
+            .cloned()
+            .collect();
+        self.deliver(&recipients, msg_name, &payload)
+    }
+
+    async fn join(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        let mut state = self.state();
+        if !state.connections.contains_key(client_id) {
+            return Err(RoomError::ClientNotFound(client_id.clone()));
+        }
+        state
+            .rooms
+            .entry(room_id.to_string())
+            .or_default()
+            .insert(client_id.clone());
+        Ok(())
+    }
+
+    async fn leave(&self, room_id: &str, client_id: &ClientId) -> Result<(), Self::Error> {
+        let mut state = self.state();
+        if let Some(members) = state.rooms.get_mut(room_id) {
+            members.remove(client_id);
+            if members.is_empty() {
+                state.rooms.remove(room_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self, client_id: &ClientId) -> bool {
+        self.state().connections.contains_key(client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Frame {
+        event: String,
+        data: Value,
+    }
+
+    #[test]
+    fn frames_decode_to_what_was_encoded() {
+        let data = json!({ "slide": 2, "big": u64::MAX, "ratio": 0.5, "tags": ["a", null] });
+        let expected = Frame {
+            event: "message".to_string(),
+            data: data.clone(),
+        };
+
+        let text = WsEncoding::Json.encode("message", data.clone()).unwrap();
+        assert!(matches!(text, WsMessage::Text(_)));
+        assert_eq!(WsEncoding::decode::<Frame>(&text).unwrap(), Some(expected));
+
+        let binary = WsEncoding::MsgPack.encode("message", data.clone()).unwrap();
+        assert!(matches!(binary, WsMessage::Binary(_)));
+        let expected = Frame {
+            event: "message".to_string(),
+            data,
+        };
+        assert_eq!(
+            WsEncoding::decode::<Frame>(&binary).unwrap(),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn control_frames_decode_to_nothing() {
+        let ping = WsMessage::Ping(Vec::new().into());
+        assert_eq!(WsEncoding::decode::<Frame>(&ping).unwrap(), None);
+        let garbage = WsMessage::Binary(vec![0xc1].into());
+        assert!(WsEncoding::decode::<Frame>(&garbage).is_err());
+    }
+
+    #[tokio::test]
+    async fn clients_receive_frames_in_their_own_encoding() {
+        let broker = WebSocketMessageBroker::new();
+        let (json_id, mut json_rx) = broker.connect(WsEncoding::Json);
+        let (msgpack_id, mut msgpack_rx) = broker.connect(WsEncoding::MsgPack);
+        broker.join("room", &json_id).await.unwrap();
+        broker.join("room", &msgpack_id).await.unwrap();
+
+        broker
+            .broadcast("room", "storage", json!({ "n": 1 }), &[])
+            .await
+            .unwrap();
+
+        let text = json_rx.recv().await.unwrap();
+        let binary = msgpack_rx.recv().await.unwrap();
+        assert!(matches!(text, WsMessage::Text(_)));
+        assert!(matches!(binary, WsMessage::Binary(_)));
+        for frame in [text, binary] {
+            let frame: Frame = WsEncoding::decode(&frame).unwrap().unwrap();
+            assert_eq!(
+                (frame.event.as_str(), frame.data),
+                ("storage", json!({ "n": 1 }))
+            );
+        }
+        assert!(json_rx.try_recv().is_err());
+
+        broker.disconnect(&msgpack_id);
+        assert!(!broker.is_connected(&msgpack_id));
+        assert!(matches!(
+            broker.send(std::slice::from_ref(&msgpack_id), "storage", json!({})).await,
+            Err(RoomError::ClientNotFound(id)) if id == msgpack_id
+        ));
+    }
+}
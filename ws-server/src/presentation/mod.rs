@@ -1,118 +1,565 @@
-use crate::message::{
-    ClientMessage, ClientMessageLike, ClientMessageType, ClientMessageTypeLike, Message, ServerMessage, ServerMessageLike, ServerMessageTypeLike
-};
+pub mod polls;
+pub mod presence;
+pub mod roles;
+
+use crate::message::{ClientMessageTypeLike, Message, ServerMessageTypeLike};
 use crate::{
-    message::ServerMessageType,
-    room::{RoomLike, presence::PresenceLike, storage::StorageLike},
+    message::{ServerMessageType, UserInfo},
+    room::{RoomLike, RoomMetadata, presence::PresenceLike, storage::StorageLike},
 };
 use chrono::{DateTime, Utc};
+use json_patch::{
+    PatchOperation,
+    jsonptr::{Pointer, PointerBuf},
+};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use socketioxide::extract::SocketRef;
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use tracing::{debug, trace};
+use serde_json::{Value, json};
+use std::{collections::HashMap, sync::LazyLock};
+use tracing::trace;
 use ts_rs::TS;
 
 use crate::room::{
-    RoomError, TransactionOutcome, client_id::ClientId, presence::PresenceError, room_id::RoomId,
+    RoomError, TransactionOutcome,
+    client_id::ClientId,
+    json_document::merge_value,
+    room_id::RoomId,
+    schema::StorageSchema,
     storage::StorageError,
+    transaction::{Transaction, TransactionManager, UndoScope},
 };
 
-#[derive(Debug, Clone, TS, Deserialize, Serialize)]
+use polls::{Poll, PollAnswer, PollId, PollQuestion, PollResults, PollView, Polls};
+pub use presence::PresentationPresence;
+use presence::PresenceUser;
+use roles::{PresentationRole, PresentationRoles};
+
+/// How many deck edits can be undone.
+pub const MAX_UNDO_HISTORY: usize = 100;
+
+/// Every deck has to match, so clients can't patch it into a shape they can't render.
+static STORAGE_SCHEMA: LazyLock<StorageSchema> = LazyLock::new(|| {
+    StorageSchema::derive::<PresentationStorage>().expect("PresentationStorage has a valid schema")
+});
+
+#[derive(Debug, Clone, Default, PartialEq, TS, JsonSchema, Deserialize, Serialize)]
 pub struct PresentationStorage {
     current_slide: usize,
+    /// Each slide is an object of whatever the slide type needs.
+    #[schemars(extend("items" = { "type": "object" }))]
     slide_data: Vec<Value>,
 }
 
+impl PresentationStorage {
+    pub fn new(slide_data: Vec<Value>) -> Self {
+        Self {
+            current_slide: 0,
+            slide_data,
+        }
+    }
+
+    fn to_value(&self) -> Result<Value, StorageError> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn check_slide(&self, index: usize) -> Result<(), RoomError> {
+        if index < self.slide_data.len() {
+            Ok(())
+        } else {
+            Err(RoomError::TransactionError(format!(
+                "Slide index {index} out of bounds"
+            )))
+        }
+    }
+
+    /// The patch inserting `slide` so that it ends up at `index`.
+    fn insert_slide(&self, index: usize, slide: Value) -> Result<json_patch::Patch, RoomError> {
+        if index > self.slide_data.len() {
+            return Err(RoomError::TransactionError(format!(
+                "Slide index {index} out of bounds"
+            )));
+        }
+        let current = if !self.slide_data.is_empty() && index <= self.current_slide {
+            self.current_slide + 1
+        } else {
+            self.current_slide
+        };
+        self.deck_patch(
+            json!({ "op": "add", "path": slide_path(index), "value": slide }),
+            current,
+        )
+    }
+
+    /// The patch deleting the slide at `index`. Whoever was on it moves on to the
+    /// slide taking its place, or to the new last slide.
+    fn delete_slide(&self, index: usize) -> Result<json_patch::Patch, RoomError> {
+        self.check_slide(index)?;
+        let last = self.slide_data.len() - 1;
+        let current = if index < self.current_slide || (index == last && self.current_slide == last)
+        {
+            self.current_slide.saturating_sub(1)
+        } else {
+            self.current_slide
+        };
+        self.deck_patch(
+            json!({ "op": "remove", "path": slide_path(index) }),
+            current,
+        )
+    }
+
+    /// The patch moving the slide at `from` so that it ends up at `to`.
+    fn move_slide(&self, from: usize, to: usize) -> Result<json_patch::Patch, RoomError> {
+        self.check_slide(from)?;
+        self.check_slide(to)?;
+        let current = self.current_slide;
+        let current = if current == from {
+            to
+        } else if from < current && current <= to {
+            current - 1
+        } else if to <= current && current < from {
+            current + 1
+        } else {
+            current
+        };
+        self.deck_patch(
+            json!({ "op": "move", "from": slide_path(from), "path": slide_path(to) }),
+            current,
+        )
+    }
+
+    /// Applies a patch that only changes slides, such as an undo, moving the current
+    /// slide along with the slides it inserts and removes the way
+    /// [`Self::insert_slide`] and [`Self::delete_slide`] do. Returns the patch applied.
+    /// Fails, changing nothing, if the result doesn't match the storage schema.
+    fn apply_deck_patch(
+        &mut self,
+        patch: &json_patch::Patch,
+    ) -> Result<json_patch::Patch, RoomError> {
+        let (mut len, mut current) = (self.slide_data.len(), self.current_slide);
+        for op in patch.iter() {
+            match op {
+                PatchOperation::Add(op) => {
+                    if let Some(index) = slide_index(&op.path, len) {
+                        if len > 0 && index <= current {
+                            current += 1;
+                        }
+                        len += 1;
+                    }
+                }
+                PatchOperation::Remove(op) => {
+                    if let Some(index) = slide_index(&op.path, len) {
+                        let last = len.saturating_sub(1);
+                        if index < current || (index == last && current == last) {
+                            current = current.saturating_sub(1);
+                        }
+                        len = last;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut patch = patch.clone();
+        if current != self.current_slide {
+            patch.0.push(serde_json::from_value(
+                json!({ "op": "replace", "path": "/current_slide", "value": current }),
+            )?);
+        }
+        self.apply_diff_checked(patch.clone(), Some(&STORAGE_SCHEMA))?;
+        Ok(patch)
+    }
+
+    /// `op`, followed by moving the current slide to `current` if it changes.
+    fn deck_patch(&self, op: Value, current: usize) -> Result<json_patch::Patch, RoomError> {
+        let mut ops = vec![op];
+        if current != self.current_slide {
+            ops.push(json!({ "op": "replace", "path": "/current_slide", "value": current }));
+        }
+        Ok(serde_json::from_value(Value::Array(ops))?)
+    }
+
+    fn validate(&self) -> Result<(), StorageError> {
+        if self.current_slide > 0 && self.current_slide >= self.slide_data.len() {
+            return Err(StorageError::ApplyDiffError(format!(
+                "Current slide {} is out of bounds",
+                self.current_slide
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn slide_path(index: usize) -> String {
+    format!("/slide_data/{index}")
+}
+
+/// The index of the slide `path` points at, if it points at a whole slide of a deck
+/// with `len` slides.
+fn slide_index(path: &Pointer, len: usize) -> Option<usize> {
+    let (parent, token) = path.split_back()?;
+    if parent.as_str() != "/slide_data" {
+        return None;
+    }
+    match token.encoded() {
+        "-" => Some(len),
+        index => index.parse().ok(),
+    }
+}
+
+/// The part of a patch that edits the deck. Which slide is current isn't part of the
+/// deck, so undoing an edit never moves anyone to another slide.
+fn deck_ops(patch: &json_patch::Patch) -> json_patch::Patch {
+    json_patch::Patch(
+        patch
+            .iter()
+            .filter(|op| op.path().as_str() != "/current_slide")
+            .cloned()
+            .collect(),
+    )
+}
+
+/// Points every path of a patch written against a single slide into `slide_data[index]`.
+fn scope_to_slide(patch: json_patch::Patch, index: usize) -> json_patch::Patch {
+    let slide = PointerBuf::from_tokens(["slide_data".to_string(), index.to_string()]);
+    let scope = |path: &PointerBuf| slide.concat(path);
+    json_patch::Patch(
+        patch
+            .0
+            .into_iter()
+            .map(|op| match op {
+                PatchOperation::Add(mut op) => {
+                    op.path = scope(&op.path);
+                    PatchOperation::Add(op)
+                }
+                PatchOperation::Remove(mut op) => {
+                    op.path = scope(&op.path);
+                    PatchOperation::Remove(op)
+                }
+                PatchOperation::Replace(mut op) => {
+                    op.path = scope(&op.path);
+                    PatchOperation::Replace(op)
+                }
+                PatchOperation::Move(mut op) => {
+                    op.from = scope(&op.from);
+                    op.path = scope(&op.path);
+                    PatchOperation::Move(op)
+                }
+                PatchOperation::Copy(mut op) => {
+                    op.from = scope(&op.from);
+                    op.path = scope(&op.path);
+                    PatchOperation::Copy(op)
+                }
+                PatchOperation::Test(mut op) => {
+                    op.path = scope(&op.path);
+                    PatchOperation::Test(op)
+                }
+            })
+            .collect(),
+    )
+}
+
+fn collect_file_keys(value: &Value, keys: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::String(file_key) if key == "file_key" => {
+                        if !keys.contains(file_key) {
+                            keys.push(file_key.clone());
+                        }
+                    }
+                    value => collect_file_keys(value, keys),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| collect_file_keys(item, keys)),
+        _ => {}
+    }
+}
+
 impl StorageLike for PresentationStorage {
     type ApplyResult = Self;
     type Diff = json_patch::Patch;
 
+    // Bump along with an `upgrade_snapshot` step whenever the fields above change
+    const SNAPSHOT_VERSION: u32 = 1;
+
     fn storage_type_id(&self) -> &'static str {
         "presentation"
     }
+
+    /// Merges `other` into `self`, treating `other` as the more recent state.
+    ///
+    /// Slides present in both decks are deep-merged (object keys from `other` win,
+    /// other values are replaced); slides only one side has are kept. The current
+    /// slide is taken from `other`, clamped to the merged deck.
     fn merge(&mut self, other: &Self) -> Result<Self::ApplyResult, StorageError> {
-        todo!()
+        for (index, slide) in other.slide_data.iter().enumerate() {
+            match self.slide_data.get_mut(index) {
+                Some(existing) => merge_value(existing, slide),
+                None => self.slide_data.push(slide.clone()),
+            }
+        }
+        self.current_slide = other
+            .current_slide
+            .min(self.slide_data.len().saturating_sub(1));
+        Ok(self.clone())
     }
+
+    /// Applies an RFC 6902 patch. Either the whole patch applies and the result is a
+    /// valid presentation, or `self` is left untouched.
     fn apply_diff(&mut self, diff: Self::Diff) -> Result<Self::ApplyResult, StorageError> {
-        todo!()
+        let mut doc = self.to_value()?;
+        json_patch::patch(&mut doc, &diff)
+            .map_err(|e| StorageError::ApplyDiffError(e.to_string()))?;
+        let next: Self = serde_json::from_value(doc)
+            .map_err(|e| StorageError::ApplyDiffError(format!("Invalid presentation: {e}")))?;
+        next.validate()?;
+
+        *self = next;
+        Ok(self.clone())
     }
+
+    /// RFC 6902 patch turning `self` into `other`.
     fn diff(&self, other: &Self) -> Result<Self::Diff, StorageError> {
-        todo!()
+        Ok(json_patch::diff(&self.to_value()?, &other.to_value()?))
     }
+
     fn snapshot(&self) -> Result<serde_json::Value, StorageError> {
-        todo!()
+        self.to_value()
     }
+
     fn from_snapshot(snapshot: serde_json::Value) -> Result<Self, StorageError>
     where
         Self: Sized,
     {
-        todo!()
-    }
-}
-
-#[derive(Debug, Clone, TS, Deserialize, Serialize)]
-pub struct PresentationPresence {
-    last_updated: DateTime<Utc>,
-}
-
-impl PresenceLike for PresentationPresence {
-    fn presence_type_id(&self) -> &'static str {
-        "presentation"
-    }
-    fn update(&mut self, data: serde_json::Value) -> Result<bool, PresenceError> {
-        todo!()
-    }
-    fn merge(&mut self, other: &Self) -> Result<bool, PresenceError> {
-        todo!()
-    }
-    fn default_state() -> Self {
-        todo!()
-    }
-    fn last_updated(&self) -> DateTime<Utc> {
-        todo!()
+        Ok(serde_json::from_value(snapshot)?)
     }
 
-    fn to_network_format(&self) -> Result<Value, PresenceError> {
-        todo!()
+    /// Every distinct `file_key` string in the slides, in order of appearance.
+    fn file_keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        for slide in &self.slide_data {
+            collect_file_keys(slide, &mut keys);
+        }
+        keys
     }
 }
 
-#[derive(Debug, Clone)] // Add necessary derives
+#[derive(Debug, Clone, Serialize, Deserialize)] // Add necessary derives
 pub struct PresentationClientData {
     pub user_id: String,
     pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
     // other metadata
 }
 
 #[derive(Debug, Clone)]
 pub struct Presentation {
     id: RoomId,
+    metadata: RoomMetadata,
     created_at: DateTime<Utc>,
     last_activity: DateTime<Utc>,
     storage: PresentationStorage,
+    version: u64,
     presence: HashMap<ClientId, PresentationPresence>,
     clients: HashMap<ClientId, PresentationClientData>, // Store metadata here
-                                                        // No communicator field
+    // No communicator field
+    roles: PresentationRoles,
+    /// Deck edits that can be undone; slide changes aren't recorded.
+    transactions: TransactionManager<json_patch::Patch>,
+    /// Kept out of storage so answers and unrevealed results stay with the presenters.
+    polls: Polls,
+}
+
+/// What a presentation persists besides its storage.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PrivateState {
+    #[serde(default)]
+    polls: Vec<Poll>,
 }
 
 impl Presentation {
-    pub fn new(
-        id: RoomId,
-        created_at: DateTime<Utc>,
-        last_activity: DateTime<Utc>,
-        storage: PresentationStorage,
-    ) -> Self {
+    pub fn new(id: RoomId, metadata: RoomMetadata, storage: PresentationStorage) -> Self {
+        let now = Utc::now();
         Self {
             id,
-            created_at,
-            last_activity,
+            metadata,
+            created_at: now,
+            last_activity: now,
             storage,
+            version: 0,
             presence: HashMap::new(),
             clients: HashMap::new(),
+            roles: PresentationRoles::default(),
+            transactions: TransactionManager::new(MAX_UNDO_HISTORY, UndoScope::Room),
+            polls: Polls::default(),
         }
     }
+
+    /// Sets whether undo and redo apply to the latest edit in the room or the
+    /// latest edit by whoever asks.
+    #[must_use]
+    pub fn with_undo_scope(mut self, scope: UndoScope) -> Self {
+        self.transactions = TransactionManager::new(MAX_UNDO_HISTORY, scope);
+        self
+    }
+
+    pub fn roles(&self) -> &PresentationRoles {
+        &self.roles
+    }
+
+    pub fn polls(&self) -> &Polls {
+        &self.polls
+    }
+
+    fn server_message(&self, payload: ServerMessageType) -> Message<ServerMessageType> {
+        Message {
+            room_id: self.id.clone(),
+            payload,
+            datetime: Utc::now(),
+            sender_id: None,
+            request_id: None,
+            broadcast: Some(true),
+            base_version: None,
+        }
+    }
+
+    /// A presentation event triggered by `client_id`'s request.
+    fn reply(
+        &self,
+        client_id: &ClientId,
+        request_id: Option<String>,
+        payload: PresentationServerMessage,
+    ) -> Message<ServerMessageType> {
+        let mut msg = self.server_message(ServerMessageType::Presentation(payload));
+        msg.sender_id = Some(client_id.clone());
+        msg.request_id = request_id;
+        msg
+    }
+
+    /// Records that storage changed by `diff` and broadcasts it to everyone,
+    /// the sender included so it learns the new version.
+    fn storage_changed(
+        &mut self,
+        diff: json_patch::Patch,
+    ) -> TransactionOutcome<ServerMessageType, json_patch::Patch> {
+        self.version += 1;
+        TransactionOutcome::BroadcastStorageUpdate {
+            diff,
+            exclude_sender: false,
+        }
+    }
+
+    /// Applies a deck edit by `client_id`, recording it so it can be undone.
+    fn edit_deck(
+        &mut self,
+        client_id: &ClientId,
+        diff: json_patch::Patch,
+    ) -> Result<TransactionOutcome<ServerMessageType, json_patch::Patch>, RoomError> {
+        // Built up front, so an edit that can't be undone fails before changing anything
+        let deck_diff = deck_ops(&diff);
+        let transaction = if deck_diff.is_empty() {
+            None
+        } else {
+            let before = self.storage.to_value()?;
+            Some(Transaction::from_patch(
+                client_id.clone(),
+                &before,
+                &deck_diff,
+            )?)
+        };
+        self.storage
+            .apply_diff_checked(diff.clone(), Some(&STORAGE_SCHEMA))?;
+        if let Some(transaction) = transaction {
+            self.transactions.add_transaction(transaction);
+        }
+        Ok(self.storage_changed(diff))
+    }
+
+    fn ensure_can_edit(&self, client_id: &ClientId) -> Result<(), RoomError> {
+        if self.roles.can_present(client_id) {
+            Ok(())
+        } else {
+            Err(RoomError::PermissionDenied(
+                "Only presenters can edit the deck".to_string(),
+            ))
+        }
+    }
+
+    fn ensure_connected(&self, client_id: &ClientId) -> Result<(), RoomError> {
+        if self.clients.contains_key(client_id) {
+            Ok(())
+        } else {
+            Err(RoomError::ClientNotFound(client_id.clone()))
+        }
+    }
+
+    /// Opening, closing and revealing polls is up to presenters and co-presenters.
+    fn ensure_can_run_polls(&self, client_id: &ClientId) -> Result<(), RoomError> {
+        if self.roles.can_present(client_id) {
+            Ok(())
+        } else {
+            Err(RoomError::PermissionDenied(
+                "Only presenters can run polls".to_string(),
+            ))
+        }
+    }
+
+    /// Sends `presenting` to the connected presenters and co-presenters, and
+    /// `audience`, if any, to everyone else.
+    fn send_by_role(
+        &self,
+        sender: &ClientId,
+        request_id: Option<String>,
+        presenting: PresentationServerMessage,
+        audience: Option<PresentationServerMessage>,
+    ) -> TransactionOutcome<ServerMessageType, json_patch::Patch> {
+        let (presenters, others): (Vec<ClientId>, Vec<ClientId>) = self
+            .clients
+            .keys()
+            .cloned()
+            .partition(|client_id| self.roles.can_present(client_id));
+        let mut outcomes = vec![TransactionOutcome::SendTo {
+            clients: presenters,
+            message: self.reply(sender, request_id.clone(), presenting),
+        }];
+        if let Some(audience) = audience {
+            outcomes.push(TransactionOutcome::SendTo {
+                clients: others,
+                message: self.reply(sender, request_id, audience),
+            });
+        }
+        TransactionOutcome::Multiple(outcomes)
+    }
+
+    /// Broadcasts the current role of each of `clients`.
+    fn roles_changed(
+        &self,
+        sender: &ClientId,
+        request_id: Option<String>,
+        clients: &[&ClientId],
+    ) -> TransactionOutcome<ServerMessageType, json_patch::Patch> {
+        TransactionOutcome::Multiple(
+            clients
+                .iter()
+                .map(|client_id| TransactionOutcome::Broadcast {
+                    message: self.reply(
+                        sender,
+                        request_id.clone(),
+                        PresentationServerMessage::RoleChanged {
+                            client_id: (*client_id).clone(),
+                            role: self.roles.role_of(client_id),
+                        },
+                    ),
+                    exclude_sender: false,
+                })
+                .collect(),
+        )
+    }
 }
 
 impl RoomLike for Presentation {
@@ -122,6 +569,10 @@ impl RoomLike for Presentation {
     type ServerMessageType = ServerMessageType;
     type ClientMetadata = PresentationClientData;
 
+    fn create(id: RoomId, metadata: RoomMetadata, storage: Self::Storage) -> Self {
+        Self::new(id, metadata, storage)
+    }
+
     fn room_type(&self) -> &'static str {
         "presentation"
     }
@@ -132,13 +583,43 @@ impl RoomLike for Presentation {
         &self.id
     }
 
+    fn metadata(&self) -> &RoomMetadata {
+        &self.metadata
+    }
+
     fn storage(&self) -> &Self::Storage {
         &self.storage
     }
     fn storage_mut(&mut self) -> &mut Self::Storage {
+        // Callers may change anything, so count every mutable borrow as a new version
+        self.version += 1;
         &mut self.storage
     }
 
+    fn storage_schema(&self) -> Option<&StorageSchema> {
+        Some(&STORAGE_SCHEMA)
+    }
+
+    fn private_state(&self) -> Result<Option<Value>, RoomError> {
+        if self.polls.all().is_empty() {
+            return Ok(None);
+        }
+        let state = PrivateState {
+            polls: self.polls.all().to_vec(),
+        };
+        Ok(Some(serde_json::to_value(state)?))
+    }
+
+    fn restore_private_state(&mut self, state: Value) -> Result<(), RoomError> {
+        let state: PrivateState = serde_json::from_value(state)?;
+        self.polls = Polls::from_polls(state.polls);
+        Ok(())
+    }
+
+    fn private_state_revision(&self) -> u64 {
+        self.polls.revision()
+    }
+
     fn get_presence(&self, client_id: &ClientId) -> Option<&Self::Presence> {
         self.presence.get(client_id)
     }
@@ -155,19 +636,51 @@ impl RoomLike for Presentation {
         self.clients.keys().cloned().collect()
     }
 
+    fn get_user_info(&self, client_id: &ClientId) -> Option<UserInfo> {
+        let client = self.clients.get(client_id)?;
+        Some(UserInfo {
+            user_id: client.user_id.clone(),
+            user_name: client.name.clone(),
+            user_email: client.email.clone().unwrap_or_default(),
+            user_avatar: client.avatar.clone().unwrap_or_default(),
+        })
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+
     fn add_client(
         &mut self,
         client_id: ClientId, // Use the actual client identifier
         metadata: Self::ClientMetadata,
         // Add socket ref or similar if needed for direct communication setup
     ) -> Result<(), RoomError> {
+        self.last_activity = Utc::now();
+        let presence = PresentationPresence::for_user(PresenceUser {
+            user_id: metadata.user_id.clone(),
+            name: metadata.name.clone(),
+            avatar: metadata.avatar.clone(),
+            color: None,
+        });
+        self.presence.insert(client_id.clone(), presence);
         self.clients.insert(client_id, metadata);
         Ok(())
     }
 
-
     fn remove_client(&mut self, client_id: &ClientId) -> Result<Self::ClientMetadata, RoomError> {
-        self.clients.remove(client_id).ok_or(RoomError::ClientNotFound(client_id.clone()))
+        let metadata = self
+            .clients
+            .remove(client_id)
+            .ok_or(RoomError::ClientNotFound(client_id.clone()))?;
+        self.presence.remove(client_id);
+        self.roles.remove(client_id);
+        self.last_activity = Utc::now();
+        Ok(metadata)
     }
 
     fn is_empty(&self) -> bool {
@@ -191,64 +704,281 @@ impl RoomLike for Presentation {
     > {
         self.last_activity = Utc::now();
 
+        let request_id = message.request_id;
+
         match message.payload {
-            ClientMessageType::Presentation(msg) => match msg {
-    
-                PresentationClientMessage::UpdateSlideContent {
-                    slide_id,
-                    content_diff,
-                } => {
-                    // 1. Get mutable access to storage
-                    let storage = self.storage_mut();
-    
-                    // 2. Apply the change (assuming StorageLike has a method or internal logic)
-                    // This is where you'd use CRDT apply functions or similar.
-                    // Let's assume apply_diff returns the actual diff applied (or the input diff)
-                    // This might involve mapping PresentationClientMessage::UpdateSlideContent
-                    // to a specific Storage operation/diff format.
-                    // let storage_diff = storage.apply_specific_update(slide_id, content_diff)?;
-                    let storage_diff: <Self::Storage as StorageLike>::Diff = content_diff; // Placeholder
-                    let apply_result = storage.apply_diff(storage_diff)?; // Assume Diff type matches
-    
-                    // Check apply_result if needed.
-    
-                    // 3. If successful, create outcome to broadcast storage update
-                    // Use the actual diff that was applied/generated by the storage operation
-                    Ok(TransactionOutcome::BroadcastStorageUpdate {
-                        diff: storage_diff,
-                        exclude_sender: true,
-                    })
+            PresentationClientMessage::ChangeSlide { slide_index } => {
+                if !self.roles.can_present(client_id) {
+                    return Err(RoomError::PermissionDenied(
+                        "Only presenters can change slides".to_string(),
+                    ));
+                }
+                if slide_index >= self.storage.slide_data.len() {
+                    return Err(RoomError::TransactionError(format!(
+                        "Slide index {slide_index} out of bounds"
+                    )));
+                }
+                let slide_changed = TransactionOutcome::Broadcast {
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::SlideChanged { slide_index },
+                    ),
+                    exclude_sender: false,
+                };
+                if self.storage.current_slide == slide_index {
+                    return Ok(slide_changed);
+                }
+
+                // An absolute change, so it applies on top of whatever version is current
+                self.storage.current_slide = slide_index;
+                let diff = serde_json::from_value(serde_json::json!([
+                    { "op": "replace", "path": "/current_slide", "value": slide_index }
+                ]))?;
+                Ok(TransactionOutcome::Multiple(vec![
+                    self.storage_changed(diff),
+                    slide_changed,
+                ]))
+            }
+            PresentationClientMessage::UpdateStorage { diff } => {
+                self.ensure_can_edit(client_id)?;
+                self.edit_deck(client_id, diff)
+            }
+            PresentationClientMessage::InsertSlide { index, slide } => {
+                self.ensure_can_edit(client_id)?;
+                let diff = self.storage.insert_slide(index, slide)?;
+                self.edit_deck(client_id, diff)
+            }
+            PresentationClientMessage::DeleteSlide { index } => {
+                self.ensure_can_edit(client_id)?;
+                let diff = self.storage.delete_slide(index)?;
+                self.edit_deck(client_id, diff)
+            }
+            PresentationClientMessage::DuplicateSlide { index } => {
+                self.ensure_can_edit(client_id)?;
+                self.storage.check_slide(index)?;
+                let slide = self.storage.slide_data[index].clone();
+                let diff = self.storage.insert_slide(index + 1, slide)?;
+                self.edit_deck(client_id, diff)
+            }
+            PresentationClientMessage::MoveSlide { from, to } => {
+                self.ensure_can_edit(client_id)?;
+                let diff = self.storage.move_slide(from, to)?;
+                if from == to {
+                    return Ok(TransactionOutcome::None);
+                }
+                self.edit_deck(client_id, diff)
+            }
+            PresentationClientMessage::PatchSlide { index, patch } => {
+                self.ensure_can_edit(client_id)?;
+                self.storage.check_slide(index)?;
+                self.edit_deck(client_id, scope_to_slide(patch, index))
+            }
+            PresentationClientMessage::Undo => {
+                self.ensure_can_edit(client_id)?;
+                let storage = &mut self.storage;
+                let diff = self
+                    .transactions
+                    .undo(client_id, |undo| storage.apply_deck_patch(undo))?
+                    .ok_or_else(|| RoomError::TransactionError("Nothing to undo".to_string()))?;
+                Ok(self.storage_changed(diff))
+            }
+            PresentationClientMessage::Redo => {
+                self.ensure_can_edit(client_id)?;
+                let storage = &mut self.storage;
+                let diff = self
+                    .transactions
+                    .redo(client_id, |redo| storage.apply_deck_patch(redo))?
+                    .ok_or_else(|| RoomError::TransactionError("Nothing to redo".to_string()))?;
+                Ok(self.storage_changed(diff))
+            }
+            PresentationClientMessage::ClaimPresenter => {
+                self.ensure_connected(client_id)?;
+                let previous = self.roles.presenter().cloned();
+                self.roles.claim(client_id)?;
+                if previous.as_ref() == Some(client_id) {
+                    return Ok(TransactionOutcome::None);
+                }
+                Ok(self.roles_changed(client_id, request_id, &[client_id]))
+            }
+            PresentationClientMessage::RequestPresenter => {
+                self.ensure_connected(client_id)?;
+                let presenter = self.roles.request_handoff(client_id)?;
+                Ok(TransactionOutcome::SendTo {
+                    clients: vec![presenter],
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::PresenterRequested {
+                            client_id: client_id.clone(),
+                        },
+                    ),
+                })
+            }
+            PresentationClientMessage::AcceptPresenterRequest {
+                client_id: requester,
+            } => {
+                self.ensure_connected(&requester)?;
+                self.roles.accept_handoff(client_id, &requester)?;
+                Ok(self.roles_changed(client_id, request_id, &[&requester, client_id]))
+            }
+            PresentationClientMessage::DeclinePresenterRequest {
+                client_id: requester,
+            } => {
+                self.roles.decline_handoff(client_id, &requester)?;
+                Ok(TransactionOutcome::SendTo {
+                    clients: vec![requester],
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::PresenterRequestDeclined,
+                    ),
+                })
+            }
+            PresentationClientMessage::SetCoPresenter {
+                client_id: target,
+                enabled,
+            } => {
+                self.ensure_connected(&target)?;
+                if !self.roles.set_co_presenter(client_id, &target, enabled)? {
+                    return Ok(TransactionOutcome::None);
+                }
+                Ok(self.roles_changed(client_id, request_id, &[&target]))
+            }
+            PresentationClientMessage::SetFollowing { following } => {
+                self.ensure_connected(client_id)?;
+                if !self.roles.set_following(client_id, following) {
+                    return Ok(TransactionOutcome::None);
+                }
+
+                let changed = TransactionOutcome::Broadcast {
+                    message: self.reply(
+                        client_id,
+                        request_id.clone(),
+                        PresentationServerMessage::FollowingChanged {
+                            client_id: client_id.clone(),
+                            following,
+                        },
+                    ),
+                    exclude_sender: false,
+                };
+                if !following {
+                    return Ok(changed);
                 }
+
+                // Snap a client that starts following back to the presenter's slide
+                let catch_up = TransactionOutcome::SendTo {
+                    clients: vec![client_id.clone()],
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::SlideChanged {
+                            slide_index: self.storage.current_slide,
+                        },
+                    ),
+                };
+                Ok(TransactionOutcome::Multiple(vec![changed, catch_up]))
             }
-            ClientMessageType::UpdateMyPresence { data } => {
-                let presence_entry = self
+            PresentationClientMessage::UpdateMyPresence { presence } => {
+                self.ensure_connected(client_id)?;
+                let current = self
                     .presence
                     .entry(client_id.clone())
-                    .or_insert_with(Self::Presence::default_state); // Ensure presence exists
-
-                if presence_entry.update(data)? {
-                    // Use the boolean result from update()
-                    // Presence changed, create notification
-                    let updated_presence_payload = presence_entry.to_network_format()?;
-                    let msg = Self::ServerMessage::PresenceUpdated {
-                        client_id: client_id.clone(),
-                        presence: updated_presence_payload,
-                    };
-                    // Broadcast change to others
-                    Ok(TransactionOutcome::Broadcast {
-                        message: msg,
-                        exclude_sender: true,
-                    })
-                } else {
-                    // No change, no broadcast needed
-                    Ok(TransactionOutcome::None)
+                    .or_insert_with(PresentationPresence::default_state);
+                if !current.update(presence)? {
+                    return Ok(TransactionOutcome::None);
                 }
-            }
-           
 
-            // Handle other client message types...
-            _ => {
-                println!("Unhandled message type: {:?}", message.name());
+                Ok(TransactionOutcome::PresenceChanged {
+                    client_id: client_id.clone(),
+                    presence: current.to_network_format()?,
+                })
+            }
+            PresentationClientMessage::OpenPoll { poll } => {
+                self.ensure_can_run_polls(client_id)?;
+                let poll = self.polls.open(poll)?;
+                let (presenting, audience) = (poll.view(None, true), poll.view(None, false));
+                Ok(self.send_by_role(
+                    client_id,
+                    request_id,
+                    PresentationServerMessage::PollOpened { poll: presenting },
+                    Some(PresentationServerMessage::PollOpened { poll: audience }),
+                ))
+            }
+            PresentationClientMessage::SubmitPollAnswer { poll_id, answer } => {
+                let user_id = self
+                    .clients
+                    .get(client_id)
+                    .map(|client| client.user_id.clone())
+                    .ok_or_else(|| RoomError::ClientNotFound(client_id.clone()))?;
+                let poll = self.polls.answer(&poll_id, &user_id, answer)?;
+                let presenting = poll.results(true);
+                let audience = poll.question.live_results.then(|| poll.results(false));
+                Ok(self.send_by_role(
+                    client_id,
+                    request_id,
+                    PresentationServerMessage::PollResultsUpdated {
+                        results: presenting,
+                    },
+                    audience
+                        .map(|results| PresentationServerMessage::PollResultsUpdated { results }),
+                ))
+            }
+            PresentationClientMessage::ClosePoll { poll_id } => {
+                self.ensure_can_run_polls(client_id)?;
+                if !self.polls.close(&poll_id)? {
+                    return Ok(TransactionOutcome::None);
+                }
+                Ok(TransactionOutcome::Broadcast {
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::PollClosed { poll_id },
+                    ),
+                    exclude_sender: false,
+                })
+            }
+            PresentationClientMessage::RevealPollResults { poll_id } => {
+                self.ensure_can_run_polls(client_id)?;
+                if !self.polls.reveal(&poll_id)? {
+                    return Ok(TransactionOutcome::None);
+                }
+                let results = self.polls.get(&poll_id)?.results(true);
+                Ok(TransactionOutcome::Broadcast {
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::PollResultsUpdated { results },
+                    ),
+                    exclude_sender: false,
+                })
+            }
+            PresentationClientMessage::GetPolls => {
+                let user_id = self
+                    .clients
+                    .get(client_id)
+                    .map(|client| client.user_id.as_str())
+                    .ok_or_else(|| RoomError::ClientNotFound(client_id.clone()))?;
+                let presenting = self.roles.can_present(client_id);
+                let polls = self
+                    .polls
+                    .all()
+                    .iter()
+                    .map(|poll| poll.view(Some(user_id), presenting))
+                    .collect();
+                Ok(TransactionOutcome::SendTo {
+                    clients: vec![client_id.clone()],
+                    message: self.reply(
+                        client_id,
+                        request_id,
+                        PresentationServerMessage::Polls { polls },
+                    ),
+                })
+            }
+            PresentationClientMessage::JoinPresentation
+            | PresentationClientMessage::LeavePresentation => {
+                // Room membership is handled by the room manager
+                trace!(client_id = %client_id, "Ignoring membership message");
                 Ok(TransactionOutcome::None)
             }
         }
@@ -266,7 +996,64 @@ impl RoomLike for Presentation {
 pub enum PresentationClientMessage {
     JoinPresentation,
     LeavePresentation,
+    /// Presenters and co-presenters only.
     ChangeSlide { slide_index: usize },
+    /// Become presenter while nobody is presenting.
+    ClaimPresenter,
+    /// Ask the presenter to hand the deck over.
+    RequestPresenter,
+    /// Presenter only: hand the deck to a client that asked for it.
+    AcceptPresenterRequest { client_id: ClientId },
+    /// Presenter only.
+    DeclinePresenterRequest { client_id: ClientId },
+    /// Presenter only: grant or revoke co-presenter rights.
+    SetCoPresenter { client_id: ClientId, enabled: bool },
+    /// Opt in or out of following the presenter's slide.
+    SetFollowing { following: bool },
+    /// Partial presence update, merged into the sender's presence.
+    UpdateMyPresence { presence: Value },
+    /// Presenters and co-presenters only: apply an RFC 6902 patch to the deck.
+    /// Versioned: must be based on the current storage version.
+    UpdateStorage {
+        #[ts(type = "Array<Record<string, unknown>>")]
+        diff: json_patch::Patch,
+    },
+    /// Presenters and co-presenters only: insert a slide so that it ends up at `index`,
+    /// which may be the number of slides to append it.
+    InsertSlide {
+        index: usize,
+        #[ts(type = "unknown")]
+        slide: Value,
+    },
+    /// Presenters and co-presenters only.
+    DeleteSlide { index: usize },
+    /// Presenters and co-presenters only: insert a copy right after the slide.
+    DuplicateSlide { index: usize },
+    /// Presenters and co-presenters only: move the slide at `from` so that it ends up at `to`.
+    MoveSlide { from: usize, to: usize },
+    /// Presenters and co-presenters only: apply an RFC 6902 patch to a single slide,
+    /// with paths relative to the slide. Versioned like `UpdateStorage`.
+    PatchSlide {
+        index: usize,
+        #[ts(type = "Array<Record<string, unknown>>")]
+        patch: json_patch::Patch,
+    },
+    /// Presenters and co-presenters only: revert the latest deck edit, which is the
+    /// room's or the sender's own depending on the room's [`UndoScope`].
+    Undo,
+    /// Presenters and co-presenters only: re-apply the latest undone deck edit.
+    Redo,
+    /// Presenters and co-presenters only: start taking answers to a new poll.
+    OpenPoll { poll: PollQuestion },
+    /// Answer an open poll; every user gets one answer per poll.
+    SubmitPollAnswer { poll_id: PollId, answer: PollAnswer },
+    /// Presenters and co-presenters only: stop taking answers.
+    ClosePoll { poll_id: PollId },
+    /// Presenters and co-presenters only: close the poll if it's still open and
+    /// show its results, along with a quiz's right options, to everyone.
+    RevealPollResults { poll_id: PollId },
+    /// Every poll run so far, as the sender gets to see them; e.g. after reconnecting.
+    GetPolls,
 }
 
 impl ClientMessageTypeLike for PresentationClientMessage {
@@ -275,8 +1062,35 @@ impl ClientMessageTypeLike for PresentationClientMessage {
             Self::JoinPresentation => "JoinPresentation",
             Self::LeavePresentation => "LeavePresentation",
             Self::ChangeSlide { .. } => "ChangeSlide",
+            Self::ClaimPresenter => "ClaimPresenter",
+            Self::RequestPresenter => "RequestPresenter",
+            Self::AcceptPresenterRequest { .. } => "AcceptPresenterRequest",
+            Self::DeclinePresenterRequest { .. } => "DeclinePresenterRequest",
+            Self::SetCoPresenter { .. } => "SetCoPresenter",
+            Self::SetFollowing { .. } => "SetFollowing",
+            Self::UpdateMyPresence { .. } => "UpdateMyPresence",
+            Self::UpdateStorage { .. } => "UpdateStorage",
+            Self::InsertSlide { .. } => "InsertSlide",
+            Self::DeleteSlide { .. } => "DeleteSlide",
+            Self::DuplicateSlide { .. } => "DuplicateSlide",
+            Self::MoveSlide { .. } => "MoveSlide",
+            Self::PatchSlide { .. } => "PatchSlide",
+            Self::Undo => "Undo",
+            Self::Redo => "Redo",
+            Self::OpenPoll { .. } => "OpenPoll",
+            Self::SubmitPollAnswer { .. } => "SubmitPollAnswer",
+            Self::ClosePoll { .. } => "ClosePoll",
+            Self::RevealPollResults { .. } => "RevealPollResults",
+            Self::GetPolls => "GetPolls",
         }
     }
+
+    fn is_versioned(&self) -> bool {
+        matches!(
+            self,
+            Self::UpdateStorage { .. } | Self::PatchSlide { .. } | Self::Undo | Self::Redo
+        )
+    }
 }
 
 // #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -288,13 +1102,440 @@ impl ClientMessageTypeLike for PresentationClientMessage {
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum PresentationServerMessage {
-    SlideChanged { slide_index: usize },
+    SlideChanged {
+        slide_index: usize,
+    },
+    RoleChanged {
+        client_id: ClientId,
+        role: PresentationRole,
+    },
+    /// Sent to the presenter only.
+    PresenterRequested {
+        client_id: ClientId,
+    },
+    /// Sent to the client whose request was declined.
+    PresenterRequestDeclined,
+    FollowingChanged {
+        client_id: ClientId,
+        following: bool,
+    },
+    /// Presenters get the poll in full; the audience without a quiz's right options.
+    PollOpened {
+        poll: PollView,
+    },
+    /// Sent to presenters as answers come in, to the audience too if the poll has
+    /// live results, and to everyone once the results are revealed.
+    PollResultsUpdated {
+        results: PollResults,
+    },
+    PollClosed {
+        poll_id: PollId,
+    },
+    /// Reply to `GetPolls`.
+    Polls {
+        polls: Vec<PollView>,
+    },
 }
 
 impl ServerMessageTypeLike for PresentationServerMessage {
     fn name(&self) -> &'static str {
         match self {
             Self::SlideChanged { .. } => "SlideChanged",
+            Self::RoleChanged { .. } => "RoleChanged",
+            Self::PresenterRequested { .. } => "PresenterRequested",
+            Self::PresenterRequestDeclined => "PresenterRequestDeclined",
+            Self::FollowingChanged { .. } => "FollowingChanged",
+            Self::PollOpened { .. } => "PollOpened",
+            Self::PollResultsUpdated { .. } => "PollResultsUpdated",
+            Self::PollClosed { .. } => "PollClosed",
+            Self::Polls { .. } => "Polls",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::persistence::PersistedRoom;
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+    use serde_json::json;
+
+    fn random_value(rng: &mut SmallRng, depth: u32) -> Value {
+        let kind = if depth == 0 {
+            rng.gen_range(0..4)
+        } else {
+            rng.gen_range(0..6)
+        };
+        match kind {
+            0 => Value::Null,
+            1 => Value::Bool(rng.r#gen()),
+            2 => json!(rng.gen_range(-100..100)),
+            3 => json!(format!("s{}", rng.gen_range(0..10))),
+            4 => Value::Array(
+                (0..rng.gen_range(0..4))
+                    .map(|_| random_value(rng, depth - 1))
+                    .collect(),
+            ),
+            _ => Value::Object(
+                (0..rng.gen_range(0..4))
+                    .map(|_| {
+                        (
+                            format!("k{}", rng.gen_range(0..5)),
+                            random_value(rng, depth - 1),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn random_storage(rng: &mut SmallRng) -> PresentationStorage {
+        let slides: Vec<Value> = (0..rng.gen_range(0..6))
+            .map(|_| random_value(rng, 3))
+            .collect();
+        PresentationStorage {
+            current_slide: rng.gen_range(0..slides.len().max(1)),
+            slide_data: slides,
+        }
+    }
+
+    #[test]
+    fn applying_a_diff_reaches_the_target() {
+        let mut rng = SmallRng::seed_from_u64(0x5eed);
+        for _ in 0..500 {
+            let a = random_storage(&mut rng);
+            let b = random_storage(&mut rng);
+
+            let mut patched = a.clone();
+            patched.apply_diff(a.diff(&b).unwrap()).unwrap();
+            assert_eq!(patched, b, "diff from {a:?}");
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let storage = random_storage(&mut rng);
+            let restored =
+                PresentationStorage::from_snapshot(storage.snapshot().unwrap()).unwrap();
+            assert_eq!(restored, storage);
         }
     }
+
+    #[test]
+    fn failed_patch_leaves_storage_untouched() {
+        let mut storage = PresentationStorage::new(vec![json!({ "title": "a" })]);
+        let before = storage.clone();
+
+        // The first op succeeds, the second fails, so nothing may stick
+        let patch: json_patch::Patch = serde_json::from_value(json!([
+            { "op": "replace", "path": "/current_slide", "value": 0 },
+            { "op": "replace", "path": "/slide_data/0/title", "value": "b" },
+            { "op": "remove", "path": "/slide_data/3" },
+        ]))
+        .unwrap();
+        assert!(storage.apply_diff(patch).is_err());
+        assert_eq!(storage, before);
+
+        // Well-formed patches that break the storage shape are rejected too
+        let patch: json_patch::Patch = serde_json::from_value(json!([
+            { "op": "replace", "path": "/current_slide", "value": "first" },
+        ]))
+        .unwrap();
+        assert!(storage.apply_diff(patch).is_err());
+        assert_eq!(storage, before);
+    }
+
+    #[test]
+    fn merge_prefers_other_and_keeps_extra_slides() {
+        let mut ours = PresentationStorage {
+            current_slide: 0,
+            slide_data: vec![
+                json!({ "title": "ours", "notes": "keep" }),
+                json!({ "title": "only ours" }),
+            ],
+        };
+        let theirs = PresentationStorage {
+            current_slide: 5,
+            slide_data: vec![json!({ "title": "theirs" })],
+        };
+
+        ours.merge(&theirs).unwrap();
+        assert_eq!(
+            ours,
+            PresentationStorage {
+                current_slide: 1,
+                slide_data: vec![
+                    json!({ "title": "theirs", "notes": "keep" }),
+                    json!({ "title": "only ours" }),
+                ],
+            }
+        );
+    }
+
+    fn message(payload: PresentationClientMessage) -> Message<PresentationClientMessage> {
+        Message {
+            room_id: RoomId::from_string("room_test"),
+            payload,
+            datetime: Utc::now(),
+            sender_id: None,
+            request_id: None,
+            broadcast: None,
+            base_version: None,
+        }
+    }
+
+    /// A one-slide deck edited by presenter `alice` and co-presenter `bob`.
+    fn edited_deck(scope: UndoScope) -> Presentation {
+        let storage = PresentationStorage::new(vec![json!({})]);
+        let room_id = RoomId::from_string("room_test");
+        let mut room =
+            Presentation::new(room_id, RoomMetadata::default(), storage).with_undo_scope(scope);
+        let (alice, bob) = ("alice".to_string(), "bob".to_string());
+        for client in [&alice, &bob] {
+            let data = PresentationClientData {
+                user_id: client.clone(),
+                name: client.clone(),
+                email: None,
+                avatar: None,
+            };
+            room.add_client(client.clone(), data).unwrap();
+        }
+        for (client, payload) in [
+            (&alice, PresentationClientMessage::ClaimPresenter),
+            (
+                &alice,
+                PresentationClientMessage::SetCoPresenter {
+                    client_id: bob.clone(),
+                    enabled: true,
+                },
+            ),
+        ] {
+            room.apply_client_message(client, message(payload)).unwrap();
+        }
+
+        for (client, field) in [(&alice, "title"), (&bob, "notes")] {
+            let diff = serde_json::from_value(json!([
+                { "op": "add", "path": format!("/slide_data/0/{field}"), "value": client }
+            ]))
+            .unwrap();
+            let payload = PresentationClientMessage::UpdateStorage { diff };
+            room.apply_client_message(client, message(payload)).unwrap();
+        }
+        room
+    }
+
+    #[test]
+    fn undo_and_redo_follow_the_scope() {
+        let alice = "alice".to_string();
+        let undo = || message(PresentationClientMessage::Undo);
+        let redo = || message(PresentationClientMessage::Redo);
+
+        // Room scope: alice undoes bob's edit, the latest one
+        let mut room = edited_deck(UndoScope::Room);
+        room.apply_client_message(&alice, undo()).unwrap();
+        assert_eq!(room.storage.slide_data[0], json!({ "title": "alice" }));
+
+        // Client scope: alice only undoes her own edit
+        let mut room = edited_deck(UndoScope::Client);
+        let version = room.version();
+        let outcome = room.apply_client_message(&alice, undo()).unwrap();
+        assert!(matches!(outcome, TransactionOutcome::BroadcastStorageUpdate { .. }));
+        assert_eq!(room.version(), version + 1);
+        assert_eq!(room.storage.slide_data[0], json!({ "notes": "bob" }));
+        assert!(room.apply_client_message(&alice, undo()).is_err());
+
+        room.apply_client_message(&alice, redo()).unwrap();
+        assert_eq!(room.storage.slide_data[0], json!({ "title": "alice", "notes": "bob" }));
+        assert!(room.apply_client_message(&alice, redo()).is_err());
+    }
+
+    #[test]
+    fn slide_operations_keep_the_current_slide() {
+        let alice = "alice".to_string();
+        let mut room = edited_deck(UndoScope::Room);
+        let mut edit = |payload| {
+            room.apply_client_message(&alice, message(payload))
+                .map(|outcome| match outcome {
+                    TransactionOutcome::BroadcastStorageUpdate { diff, .. } => {
+                        serde_json::to_value(diff).unwrap()
+                    }
+                    _ => panic!("expected a storage update"),
+                })
+        };
+
+        // Inserting before the current slide shifts it along
+        let diff = edit(PresentationClientMessage::InsertSlide {
+            index: 0,
+            slide: json!({ "n": 0 }),
+        });
+        assert_eq!(
+            diff.unwrap(),
+            json!([
+                { "op": "add", "path": "/slide_data/0", "value": { "n": 0 } },
+                { "op": "replace", "path": "/current_slide", "value": 1 },
+            ])
+        );
+        edit(PresentationClientMessage::DuplicateSlide { index: 0 }).unwrap();
+        edit(PresentationClientMessage::MoveSlide { from: 2, to: 0 }).unwrap();
+        let diff = edit(PresentationClientMessage::PatchSlide {
+            index: 2,
+            patch: serde_json::from_value(json!([{ "op": "add", "path": "/n", "value": 2 }]))
+                .unwrap(),
+        });
+        assert_eq!(
+            diff.unwrap(),
+            json!([{ "op": "add", "path": "/slide_data/2/n", "value": 2 }])
+        );
+        let deck = json!([{ "title": "alice", "notes": "bob" }, { "n": 0 }, { "n": 2 }]);
+        assert_eq!(json!(room.storage.slide_data), deck);
+        assert_eq!(room.storage.current_slide, 0);
+
+        let mut edit = |payload| room.apply_client_message(&alice, message(payload));
+        for payload in [
+            PresentationClientMessage::InsertSlide {
+                index: 4,
+                slide: json!({}),
+            },
+            PresentationClientMessage::DeleteSlide { index: 3 },
+            PresentationClientMessage::DuplicateSlide { index: 3 },
+            PresentationClientMessage::MoveSlide { from: 0, to: 3 },
+            PresentationClientMessage::PatchSlide {
+                index: 0,
+                patch: serde_json::from_value(json!([{ "op": "remove", "path": "/missing" }]))
+                    .unwrap(),
+            },
+        ] {
+            assert!(edit(payload).is_err());
+        }
+        assert!(matches!(
+            edit(PresentationClientMessage::MoveSlide { from: 1, to: 1 }),
+            Ok(TransactionOutcome::None)
+        ));
+
+        // Deleting the last slide while on it moves back to the new last one, and
+        // undoing the delete brings the slide back without moving anyone to it
+        room.storage.current_slide = 2;
+        let delete = message(PresentationClientMessage::DeleteSlide { index: 2 });
+        room.apply_client_message(&alice, delete).unwrap();
+        assert_eq!(room.storage.current_slide, 1);
+        let undo = message(PresentationClientMessage::Undo);
+        room.apply_client_message(&alice, undo).unwrap();
+        assert_eq!(json!(room.storage.slide_data), deck);
+        assert_eq!(room.storage.current_slide, 1);
+    }
+
+    #[test]
+    fn undo_is_refused_once_its_slide_has_moved() {
+        let (alice, bob) = ("alice".to_string(), "bob".to_string());
+        let mut room = edited_deck(UndoScope::Client);
+        let patch = serde_json::from_value(json!([
+            { "op": "replace", "path": "/title", "value": "changed" }
+        ]))
+        .unwrap();
+        let edits = [
+            (
+                &alice,
+                PresentationClientMessage::PatchSlide { index: 0, patch },
+            ),
+            (
+                &bob,
+                PresentationClientMessage::InsertSlide {
+                    index: 0,
+                    slide: json!({ "title": "new" }),
+                },
+            ),
+        ];
+        for (client, payload) in edits {
+            room.apply_client_message(client, message(payload)).unwrap();
+        }
+        assert_eq!(room.storage.current_slide, 1);
+
+        // Alice's edit is on slide 1 now, so undoing it at slide 0 would hit bob's slide
+        let undo = || message(PresentationClientMessage::Undo);
+        let deck = json!(room.storage.slide_data);
+        assert!(room.apply_client_message(&alice, undo()).is_err());
+        assert_eq!(json!(room.storage.slide_data), deck);
+
+        // Once bob takes his slide back, alice's undo applies again
+        room.apply_client_message(&bob, undo()).unwrap();
+        assert_eq!(room.storage.current_slide, 0);
+        room.apply_client_message(&alice, undo()).unwrap();
+        assert_eq!(
+            room.storage.slide_data,
+            vec![json!({ "title": "alice", "notes": "bob" })]
+        );
+        assert!(PresentationClientMessage::Undo.is_versioned());
+    }
+
+    #[test]
+    fn polls_keep_answers_from_the_audience_and_persist() {
+        let (alice, carol) = ("alice".to_string(), "carol".to_string());
+        let mut room = edited_deck(UndoScope::Room);
+        let data = PresentationClientData {
+            user_id: "carol".to_string(),
+            name: "Carol".to_string(),
+            email: None,
+            avatar: None,
+        };
+        room.add_client(carol.clone(), data).unwrap();
+
+        let poll: PollQuestion = serde_json::from_value(json!({
+            "question": "Best slide?",
+            "kind": { "type": "single_choice", "options": ["first", "last"] },
+            "correct_options": [0],
+        }))
+        .unwrap();
+        let open = || message(PresentationClientMessage::OpenPoll { poll: poll.clone() });
+        assert!(room.apply_client_message(&carol, open()).is_err());
+
+        // Everyone hears about the poll; only presenters learn the right option
+        let version = room.version();
+        let TransactionOutcome::Multiple(sends) = room.apply_client_message(&alice, open()).unwrap()
+        else {
+            panic!("expected a send per role");
+        };
+        let opened: Vec<(Vec<ClientId>, Value)> = sends
+            .into_iter()
+            .map(|send| match send {
+                TransactionOutcome::SendTo {
+                    mut clients,
+                    message,
+                } => {
+                    clients.sort();
+                    (clients, serde_json::to_value(message.payload).unwrap())
+                }
+                _ => panic!("expected targeted sends"),
+            })
+            .collect();
+        assert_eq!(opened[0].0, vec!["alice".to_string(), "bob".to_string()]);
+        assert!(opened[0].1.to_string().contains("correct_options"));
+        assert_eq!(opened[1].0, vec![carol.clone()]);
+        assert!(!opened[1].1.to_string().contains("correct_options"));
+
+        let poll_id = room.polls().all()[0].id.clone();
+        let answer = || {
+            message(PresentationClientMessage::SubmitPollAnswer {
+                poll_id: poll_id.clone(),
+                answer: PollAnswer::Choice { options: vec![1] },
+            })
+        };
+        let revision = room.private_state_revision();
+        room.apply_client_message(&carol, answer()).unwrap();
+        assert!(room.apply_client_message(&carol, answer()).is_err());
+        assert_eq!(room.private_state_revision(), revision + 1);
+        assert_eq!(room.version(), version);
+
+        let persisted = PersistedRoom::from_room(&room).unwrap();
+        let restored: Presentation = persisted.into_room().unwrap();
+        assert_eq!(restored.polls().all(), room.polls().all());
+
+        let reveal = message(PresentationClientMessage::RevealPollResults {
+            poll_id: poll_id.clone(),
+        });
+        let outcome = room.apply_client_message(&alice, reveal).unwrap();
+        assert!(matches!(outcome, TransactionOutcome::Broadcast { .. }));
+        assert!(room.apply_client_message(&carol, answer()).is_err());
+    }
 }
@@ -0,0 +1,572 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use uuid::Uuid;
+
+use crate::room::RoomError;
+
+pub type PollId = String;
+
+/// Longest free text answer accepted, in characters.
+pub const MAX_TEXT_ANSWER_LENGTH: usize = 1000;
+/// Highest top of a rating scale.
+pub const MAX_RATING: u8 = 10;
+
+/// What a poll asks the audience for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PollKind {
+    /// Pick exactly one of the options.
+    SingleChoice {
+        options: Vec<String>,
+    },
+    /// Pick one or more of the options.
+    MultipleChoice {
+        options: Vec<String>,
+    },
+    FreeText,
+    /// A rating from 1 to `max`.
+    Rating {
+        max: u8,
+    },
+}
+
+/// A poll as the presenter opens it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PollQuestion {
+    pub question: String,
+    pub kind: PollKind,
+    /// Turns a choice poll into a quiz: the indices of the right options. Kept from
+    /// the audience until the results are revealed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correct_options: Option<Vec<usize>>,
+    /// Whether the audience sees results as answers come in, rather than only once
+    /// they are revealed.
+    #[serde(default)]
+    pub live_results: bool,
+}
+
+impl PollQuestion {
+    fn validate(&self) -> Result<(), RoomError> {
+        if self.question.trim().is_empty() {
+            return Err(invalid("The question is empty"));
+        }
+        match &self.kind {
+            PollKind::SingleChoice { options } | PollKind::MultipleChoice { options } => {
+                if options.len() < 2 {
+                    return Err(invalid("A choice poll needs at least two options"));
+                }
+            }
+            PollKind::FreeText => {}
+            PollKind::Rating { max } => {
+                if !(2..=MAX_RATING).contains(max) {
+                    return Err(invalid(format!(
+                        "Ratings go up to between 2 and {MAX_RATING}, not {max}"
+                    )));
+                }
+            }
+        }
+
+        if let Some(correct) = &self.correct_options {
+            match &self.kind {
+                PollKind::SingleChoice { .. } if correct.len() != 1 => {
+                    return Err(invalid("A single choice quiz has one right option"));
+                }
+                PollKind::SingleChoice { options } | PollKind::MultipleChoice { options } => {
+                    check_options(correct, options.len())?;
+                }
+                _ => return Err(invalid("Only choice polls can be quizzes")),
+            }
+        }
+        Ok(())
+    }
+
+    /// The question without its right options, for clients that can't see them yet.
+    fn without_answer(&self) -> Self {
+        Self {
+            correct_options: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// An answer to a poll; it has to match the poll's [`PollKind`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PollAnswer {
+    /// Indices of the picked options.
+    Choice {
+        options: Vec<usize>,
+    },
+    Text {
+        text: String,
+    },
+    Rating {
+        rating: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "snake_case")]
+pub enum PollStatus {
+    /// Taking answers.
+    Open,
+    /// No longer taking answers; results are still kept from the audience unless the
+    /// poll has live results.
+    Closed,
+    /// No longer taking answers, and everyone sees the results.
+    Revealed,
+}
+
+/// Who answered what, and when.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PollResponse {
+    pub user_id: String,
+    pub answer: PollAnswer,
+    pub answered_at: DateTime<Utc>,
+}
+
+/// A poll with every answer it got, as persisted with the room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Poll {
+    pub id: PollId,
+    pub question: PollQuestion,
+    pub status: PollStatus,
+    pub opened_at: DateTime<Utc>,
+    #[serde(default)]
+    pub closed_at: Option<DateTime<Utc>>,
+    /// Oldest first, at most one per user, so reconnecting doesn't allow a second answer.
+    #[serde(default)]
+    pub responses: Vec<PollResponse>,
+}
+
+impl Poll {
+    pub fn answer_of(&self, user_id: &str) -> Option<&PollAnswer> {
+        self.responses
+            .iter()
+            .find(|response| response.user_id == user_id)
+            .map(|response| &response.answer)
+    }
+
+    /// Whether someone who isn't presenting may see the results, and the right options.
+    pub fn is_public(&self) -> bool {
+        self.status == PollStatus::Revealed
+    }
+
+    /// Aggregated answers; the right options are included if `with_answer` is set.
+    pub fn results(&self, with_answer: bool) -> PollResults {
+        let answers = self.responses.iter().map(|response| &response.answer);
+        let tally = match &self.question.kind {
+            PollKind::SingleChoice { options } | PollKind::MultipleChoice { options } => {
+                let mut counts = vec![0; options.len()];
+                for answer in answers {
+                    if let PollAnswer::Choice { options } = answer {
+                        for option in options {
+                            if let Some(count) = counts.get_mut(*option) {
+                                *count += 1;
+                            }
+                        }
+                    }
+                }
+                PollTally::Choices { counts }
+            }
+            PollKind::FreeText => PollTally::Texts {
+                answers: answers
+                    .filter_map(|answer| match answer {
+                        PollAnswer::Text { text } => Some(text.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+            },
+            PollKind::Rating { max } => {
+                let mut counts = vec![0; usize::from(*max)];
+                let mut total = 0;
+                for answer in answers {
+                    if let PollAnswer::Rating { rating } = answer {
+                        if let Some(count) = usize::from(*rating)
+                            .checked_sub(1)
+                            .and_then(|index| counts.get_mut(index))
+                        {
+                            *count += 1;
+                        }
+                        total += u64::from(*rating);
+                    }
+                }
+                let average = (!self.responses.is_empty())
+                    .then(|| total as f64 / self.responses.len() as f64);
+                PollTally::Ratings { counts, average }
+            }
+        };
+
+        PollResults {
+            poll_id: self.id.clone(),
+            status: self.status,
+            responses: self.responses.len(),
+            tally,
+            correct_options: self
+                .question
+                .correct_options
+                .clone()
+                .filter(|_| with_answer),
+        }
+    }
+
+    /// The poll as `user_id` gets to see it. Presenters see everything; the audience
+    /// sees results once they're revealed, or all along if the poll has live results.
+    pub fn view(&self, user_id: Option<&str>, presenting: bool) -> PollView {
+        let with_answer = presenting || self.is_public();
+        PollView {
+            poll_id: self.id.clone(),
+            question: if with_answer {
+                self.question.clone()
+            } else {
+                self.question.without_answer()
+            },
+            status: self.status,
+            opened_at: self.opened_at,
+            results: (with_answer || self.question.live_results).then(|| self.results(with_answer)),
+            own_answer: user_id.and_then(|user_id| self.answer_of(user_id).cloned()),
+        }
+    }
+
+    fn check_answer(&self, answer: &PollAnswer) -> Result<(), RoomError> {
+        match (&self.question.kind, answer) {
+            (PollKind::SingleChoice { options }, PollAnswer::Choice { options: picked }) => {
+                if picked.len() != 1 {
+                    return Err(invalid("Pick exactly one option"));
+                }
+                check_options(picked, options.len())
+            }
+            (PollKind::MultipleChoice { options }, PollAnswer::Choice { options: picked }) => {
+                if picked.is_empty() {
+                    return Err(invalid("Pick at least one option"));
+                }
+                check_options(picked, options.len())
+            }
+            (PollKind::FreeText, PollAnswer::Text { text }) => {
+                if text.trim().is_empty() {
+                    return Err(invalid("The answer is empty"));
+                }
+                if text.chars().count() > MAX_TEXT_ANSWER_LENGTH {
+                    return Err(invalid(format!(
+                        "Answers are at most {MAX_TEXT_ANSWER_LENGTH} characters"
+                    )));
+                }
+                Ok(())
+            }
+            (PollKind::Rating { max }, PollAnswer::Rating { rating }) => {
+                if !(1..=*max).contains(rating) {
+                    return Err(invalid(format!("Ratings go from 1 to {max}")));
+                }
+                Ok(())
+            }
+            _ => Err(invalid("The answer doesn't fit the poll")),
+        }
+    }
+}
+
+/// A poll's answers, aggregated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PollResults {
+    pub poll_id: PollId,
+    pub status: PollStatus,
+    pub responses: usize,
+    pub tally: PollTally,
+    /// A quiz's right options, for clients that may see them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correct_options: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PollTally {
+    /// How often each option was picked.
+    Choices { counts: Vec<usize> },
+    /// Every answer, oldest first.
+    Texts { answers: Vec<String> },
+    /// How often each rating was given, from 1 up, and the mean rating.
+    Ratings {
+        counts: Vec<usize>,
+        average: Option<f64>,
+    },
+}
+
+/// A poll as one client gets to see it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PollView {
+    pub poll_id: PollId,
+    pub question: PollQuestion,
+    pub status: PollStatus,
+    pub opened_at: DateTime<Utc>,
+    /// `None` while the results are kept from the client.
+    pub results: Option<PollResults>,
+    /// The client's own answer, if it gave one.
+    pub own_answer: Option<PollAnswer>,
+}
+
+/// Every poll run in a presentation, oldest first.
+///
+/// Permissions aren't checked here; callers are expected to only let presenters
+/// open, close and reveal polls.
+#[derive(Debug, Clone, Default)]
+pub struct Polls {
+    polls: Vec<Poll>,
+    /// Bumped on every change, so the room can tell when its polls need saving.
+    revision: u64,
+}
+
+impl Polls {
+    pub fn from_polls(polls: Vec<Poll>) -> Self {
+        Self { polls, revision: 0 }
+    }
+
+    pub fn all(&self) -> &[Poll] {
+        &self.polls
+    }
+
+    pub fn get(&self, poll_id: &str) -> Result<&Poll, RoomError> {
+        self.polls
+            .iter()
+            .find(|poll| poll.id == poll_id)
+            .ok_or_else(|| RoomError::TransactionError(format!("Poll {poll_id} not found")))
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn open(&mut self, question: PollQuestion) -> Result<&Poll, RoomError> {
+        question.validate()?;
+        self.revision += 1;
+        self.polls.push(Poll {
+            id: format!("poll_{}", Uuid::new_v4()),
+            question,
+            status: PollStatus::Open,
+            opened_at: Utc::now(),
+            closed_at: None,
+            responses: Vec::new(),
+        });
+        Ok(self.polls.last().expect("just pushed"))
+    }
+
+    /// Records a user's answer; everyone gets to answer an open poll once.
+    pub fn answer(
+        &mut self,
+        poll_id: &str,
+        user_id: &str,
+        answer: PollAnswer,
+    ) -> Result<&Poll, RoomError> {
+        let poll = self.get_mut(poll_id)?;
+        if poll.status != PollStatus::Open {
+            return Err(RoomError::TransactionError(format!(
+                "Poll {poll_id} is closed"
+            )));
+        }
+        if poll.answer_of(user_id).is_some() {
+            return Err(RoomError::TransactionError(format!(
+                "Poll {poll_id} was already answered"
+            )));
+        }
+        poll.check_answer(&answer)?;
+
+        poll.responses.push(PollResponse {
+            user_id: user_id.to_string(),
+            answer,
+            answered_at: Utc::now(),
+        });
+        self.revision += 1;
+        self.get(poll_id)
+    }
+
+    /// Stops taking answers. Returns `false` if the poll already had.
+    pub fn close(&mut self, poll_id: &str) -> Result<bool, RoomError> {
+        let poll = self.get_mut(poll_id)?;
+        if poll.status != PollStatus::Open {
+            return Ok(false);
+        }
+        poll.status = PollStatus::Closed;
+        poll.closed_at = Some(Utc::now());
+        self.revision += 1;
+        Ok(true)
+    }
+
+    /// Shows the results to everyone, closing the poll first if it's still open.
+    /// Returns `false` if they already were.
+    pub fn reveal(&mut self, poll_id: &str) -> Result<bool, RoomError> {
+        self.close(poll_id)?;
+        let poll = self.get_mut(poll_id)?;
+        if poll.status == PollStatus::Revealed {
+            return Ok(false);
+        }
+        poll.status = PollStatus::Revealed;
+        self.revision += 1;
+        Ok(true)
+    }
+
+    fn get_mut(&mut self, poll_id: &str) -> Result<&mut Poll, RoomError> {
+        self.polls
+            .iter_mut()
+            .find(|poll| poll.id == poll_id)
+            .ok_or_else(|| RoomError::TransactionError(format!("Poll {poll_id} not found")))
+    }
+}
+
+/// Checks that option indices are in bounds and distinct.
+fn check_options(picked: &[usize], options: usize) -> Result<(), RoomError> {
+    let mut seen = HashSet::new();
+    for option in picked {
+        if *option >= options {
+            return Err(invalid(format!("Option {option} out of bounds")));
+        }
+        if !seen.insert(option) {
+            return Err(invalid(format!("Option {option} picked twice")));
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: impl Into<String>) -> RoomError {
+    RoomError::TransactionError(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn question(value: serde_json::Value) -> PollQuestion {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn quiz_answer_stays_hidden_until_revealed() {
+        let mut polls = Polls::default();
+        let poll_id = polls
+            .open(question(json!({
+                "question": "2 + 2?",
+                "kind": { "type": "single_choice", "options": ["3", "4", "5"] },
+                "correct_options": [1],
+            })))
+            .unwrap()
+            .id
+            .clone();
+
+        let pick = |option| PollAnswer::Choice {
+            options: vec![option],
+        };
+        polls.answer(&poll_id, "ann", pick(1)).unwrap();
+        polls.answer(&poll_id, "ben", pick(0)).unwrap();
+        assert!(polls.answer(&poll_id, "ann", pick(2)).is_err());
+        assert!(polls.answer(&poll_id, "cat", pick(3)).is_err());
+        assert!(
+            polls
+                .answer(&poll_id, "cat", PollAnswer::Rating { rating: 1 })
+                .is_err()
+        );
+
+        let poll = polls.get(&poll_id).unwrap();
+        let audience = poll.view(Some("ann"), false);
+        assert_eq!(audience.question.correct_options, None);
+        assert_eq!(audience.results, None);
+        assert_eq!(audience.own_answer, Some(pick(1)));
+        let presenter = poll.view(None, true).results.unwrap();
+        assert_eq!(
+            presenter.tally,
+            PollTally::Choices {
+                counts: vec![1, 1, 0]
+            }
+        );
+        assert_eq!(presenter.correct_options, Some(vec![1]));
+
+        assert!(polls.close(&poll_id).unwrap());
+        assert!(!polls.close(&poll_id).unwrap());
+        assert!(polls.answer(&poll_id, "cat", pick(1)).is_err());
+        assert!(polls.reveal(&poll_id).unwrap());
+        let audience = polls.get(&poll_id).unwrap().view(Some("cat"), false);
+        assert_eq!(audience.results.unwrap().correct_options, Some(vec![1]));
+        assert_eq!(audience.own_answer, None);
+    }
+
+    #[test]
+    fn ratings_and_texts_are_tallied() {
+        let mut polls = Polls::default();
+        let rating = polls
+            .open(question(json!({
+                "question": "How was it?",
+                "kind": { "type": "rating", "max": 5 },
+                "live_results": true,
+            })))
+            .unwrap()
+            .id
+            .clone();
+        for (user, rating_given) in [("ann", 5), ("ben", 4), ("cat", 4)] {
+            let answer = PollAnswer::Rating {
+                rating: rating_given,
+            };
+            polls.answer(&rating, user, answer).unwrap();
+        }
+        assert!(
+            polls
+                .answer(&rating, "dan", PollAnswer::Rating { rating: 6 })
+                .is_err()
+        );
+        let results = polls
+            .get(&rating)
+            .unwrap()
+            .view(None, false)
+            .results
+            .unwrap();
+        assert_eq!(
+            results.tally,
+            PollTally::Ratings {
+                counts: vec![0, 0, 0, 2, 1],
+                average: Some(13.0 / 3.0),
+            }
+        );
+
+        let text = polls
+            .open(question(
+                json!({ "question": "Questions?", "kind": { "type": "free_text" } }),
+            ))
+            .unwrap()
+            .id
+            .clone();
+        let answer = |text: &str| PollAnswer::Text {
+            text: text.to_string(),
+        };
+        polls.answer(&text, "ann", answer("Why?")).unwrap();
+        assert!(polls.answer(&text, "ben", answer("  ")).is_err());
+        assert_eq!(
+            polls.get(&text).unwrap().results(false).tally,
+            PollTally::Texts {
+                answers: vec!["Why?".to_string()]
+            }
+        );
+        assert_eq!(polls.revision(), 6);
+
+        for bad in [
+            json!({ "question": "", "kind": { "type": "free_text" } }),
+            json!({ "question": "?", "kind": { "type": "single_choice", "options": ["a"] } }),
+            json!({ "question": "?", "kind": { "type": "rating", "max": 11 } }),
+            json!({
+                "question": "?",
+                "kind": { "type": "multiple_choice", "options": ["a", "b"] },
+                "correct_options": [0, 0],
+            }),
+            json!({ "question": "?", "kind": { "type": "free_text" }, "correct_options": [0] }),
+        ] {
+            assert!(polls.open(question(bad)).is_err());
+        }
+    }
+}
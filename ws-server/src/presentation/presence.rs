@@ -0,0 +1,226 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use crate::room::presence::{PresenceError, PresenceLike};
+
+/// Who a presence belongs to, as shown next to cursors and avatars.
+#[derive(Debug, Clone, PartialEq, TS, Deserialize, Serialize)]
+#[ts(export)]
+pub struct PresenceUser {
+    pub user_id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+/// Pointer position relative to the slide, with both coordinates in `0.0..=1.0`
+/// so it maps onto any viewport size.
+#[derive(Debug, Clone, Copy, PartialEq, TS, Deserialize, Serialize)]
+#[ts(export)]
+pub struct Pointer {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Pointer {
+    fn validate(&self) -> Result<(), PresenceError> {
+        let in_range = |v: f64| v.is_finite() && (0.0..=1.0).contains(&v);
+        if in_range(self.x) && in_range(self.y) {
+            Ok(())
+        } else {
+            Err(PresenceError::InvalidUpdate(format!(
+                "Pointer ({}, {}) is outside the slide",
+                self.x, self.y
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, TS, Deserialize, Serialize)]
+#[ts(export)]
+#[serde(deny_unknown_fields)]
+pub struct PresentationPresence {
+    #[serde(default)]
+    pub user: Option<PresenceUser>,
+    /// Slide the client is looking at, which may differ from the presenter's.
+    #[serde(default)]
+    pub current_slide: Option<usize>,
+    #[serde(default)]
+    pub pointer: Option<Pointer>,
+    #[serde(default)]
+    pub laser: bool,
+    /// Ids of the slide elements the client has selected.
+    #[serde(default)]
+    pub selection: Vec<String>,
+    last_updated: DateTime<Utc>,
+}
+
+impl PresentationPresence {
+    pub fn for_user(user: PresenceUser) -> Self {
+        Self {
+            user: Some(user),
+            ..Self::default_state()
+        }
+    }
+
+    /// Equal ignoring `last_updated`.
+    fn same_state(&self, other: &Self) -> bool {
+        self.user == other.user
+            && self.current_slide == other.current_slide
+            && self.pointer == other.pointer
+            && self.laser == other.laser
+            && self.selection == other.selection
+    }
+}
+
+impl PresenceLike for PresentationPresence {
+    fn presence_type_id(&self) -> &'static str {
+        "presentation"
+    }
+
+    /// Applies a partial update as a JSON merge patch (RFC 7396): fields that are
+    /// present replace the current ones, `null` clears a field, and anything
+    /// missing is left as is. Unknown fields, and the ones the server sets, are rejected.
+    fn update(&mut self, data: Value) -> Result<bool, PresenceError> {
+        let Value::Object(fields) = &data else {
+            return Err(PresenceError::InvalidUpdate(
+                "Presence updates must be JSON objects".to_string(),
+            ));
+        };
+        // Who the client is comes from its join, so it can't pose as someone else
+        if let Some(field) = ["user", "last_updated"]
+            .into_iter()
+            .find(|field| fields.contains_key(*field))
+        {
+            return Err(PresenceError::InvalidUpdate(format!(
+                "{field} is set by the server"
+            )));
+        }
+
+        let mut doc = serde_json::to_value(&*self)?;
+        json_patch::merge(&mut doc, &data);
+        let next: Self = serde_json::from_value(doc)
+            .map_err(|e| PresenceError::InvalidUpdate(e.to_string()))?;
+        if let Some(pointer) = &next.pointer {
+            pointer.validate()?;
+        }
+
+        if next.same_state(self) {
+            return Ok(false);
+        }
+        *self = Self {
+            last_updated: Utc::now(),
+            ..next
+        };
+        Ok(true)
+    }
+
+    /// Takes `other`'s state if it is newer. User info is kept when `other` has none,
+    /// since who a client is doesn't go stale.
+    fn merge(&mut self, other: &Self) -> Result<bool, PresenceError> {
+        if other.last_updated <= self.last_updated || other.same_state(self) {
+            return Ok(false);
+        }
+
+        let user = other.user.clone().or_else(|| self.user.take());
+        *self = Self {
+            user,
+            ..other.clone()
+        };
+        Ok(true)
+    }
+
+    fn default_state() -> Self {
+        Self {
+            user: None,
+            current_slide: None,
+            pointer: None,
+            laser: false,
+            selection: Vec::new(),
+            last_updated: Utc::now(),
+        }
+    }
+
+    fn last_updated(&self) -> DateTime<Utc> {
+        self.last_updated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::json;
+
+    #[test]
+    fn update_merges_partial_json() {
+        let mut presence = PresentationPresence::default_state();
+
+        assert!(
+            presence
+                .update(json!({ "current_slide": 2, "pointer": { "x": 0.5, "y": 0.25 } }))
+                .unwrap()
+        );
+        assert!(presence.update(json!({ "laser": true })).unwrap());
+        assert_eq!(presence.current_slide, Some(2));
+        assert_eq!(presence.pointer, Some(Pointer { x: 0.5, y: 0.25 }));
+        assert!(presence.laser);
+
+        // Repeating a value is not a change; null clears a field
+        assert!(!presence.update(json!({ "laser": true })).unwrap());
+        assert!(presence.update(json!({ "pointer": null })).unwrap());
+        assert_eq!(presence.pointer, None);
+    }
+
+    #[test]
+    fn update_rejects_invalid_data() {
+        let mut presence = PresentationPresence::for_user(PresenceUser {
+            user_id: "u1".to_string(),
+            name: "Ada".to_string(),
+            avatar: None,
+            color: None,
+        });
+        let before = presence.clone();
+
+        for data in [
+            json!([1, 2]),
+            json!({ "pointer": { "x": 1.5, "y": 0.0 } }),
+            json!({ "unknown": true }),
+            json!({ "last_updated": "2020-01-01T00:00:00Z" }),
+            json!({ "user": { "user_id": "admin", "name": "Admin" } }),
+            json!({ "user": null }),
+        ] {
+            assert!(presence.update(data).is_err());
+        }
+        assert_eq!(presence, before);
+    }
+
+    #[test]
+    fn merge_prefers_newest_and_keeps_user() {
+        let user = PresenceUser {
+            user_id: "u1".to_string(),
+            name: "Ada".to_string(),
+            avatar: None,
+            color: None,
+        };
+        let mut ours = PresentationPresence::for_user(user.clone());
+        let mut theirs = PresentationPresence {
+            current_slide: Some(3),
+            last_updated: ours.last_updated + Duration::seconds(1),
+            ..PresentationPresence::default_state()
+        };
+
+        assert!(ours.merge(&theirs).unwrap());
+        assert_eq!(ours.current_slide, Some(3));
+        assert_eq!(ours.user, Some(user));
+
+        theirs.current_slide = Some(1);
+        theirs.last_updated = ours.last_updated - Duration::seconds(1);
+        assert!(!ours.merge(&theirs).unwrap());
+        assert_eq!(ours.current_slide, Some(3));
+    }
+}
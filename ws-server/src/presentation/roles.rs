@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::room::{RoomError, client_id::ClientId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum PresentationRole {
+    /// Drives the deck and decides on handoff requests. At most one per room.
+    Presenter,
+    /// Can change slides alongside the presenter.
+    CoPresenter,
+    /// Watches, optionally following the presenter's slide.
+    Audience,
+}
+
+/// Who controls a presentation's deck, and who in the audience follows it.
+///
+/// Membership isn't tracked here; callers are expected to only pass ids of
+/// connected clients and to call [`PresentationRoles::remove`] when one leaves.
+#[derive(Debug, Clone, Default)]
+pub struct PresentationRoles {
+    presenter: Option<ClientId>,
+    co_presenters: HashSet<ClientId>,
+    /// Audience members who opted out of following; everyone else follows.
+    not_following: HashSet<ClientId>,
+    /// Clients waiting for the presenter to hand the deck over to them.
+    handoff_requests: HashSet<ClientId>,
+}
+
+impl PresentationRoles {
+    pub fn presenter(&self) -> Option<&ClientId> {
+        self.presenter.as_ref()
+    }
+
+    pub fn role_of(&self, client_id: &ClientId) -> PresentationRole {
+        if self.presenter.as_ref() == Some(client_id) {
+            PresentationRole::Presenter
+        } else if self.co_presenters.contains(client_id) {
+            PresentationRole::CoPresenter
+        } else {
+            PresentationRole::Audience
+        }
+    }
+
+    /// `true` if the client may change the current slide.
+    pub fn can_present(&self, client_id: &ClientId) -> bool {
+        self.role_of(client_id) != PresentationRole::Audience
+    }
+
+    pub fn is_following(&self, client_id: &ClientId) -> bool {
+        !self.not_following.contains(client_id)
+    }
+
+    /// Returns `true` if the client's follow mode changed.
+    pub fn set_following(&mut self, client_id: &ClientId, following: bool) -> bool {
+        if following {
+            self.not_following.remove(client_id)
+        } else {
+            self.not_following.insert(client_id.clone())
+        }
+    }
+
+    /// Makes the client presenter, as long as nobody else is.
+    pub fn claim(&mut self, client_id: &ClientId) -> Result<(), RoomError> {
+        match &self.presenter {
+            Some(presenter) if presenter != client_id => Err(RoomError::PermissionDenied(
+                format!("{presenter} is already presenting"),
+            )),
+            _ => {
+                self.co_presenters.remove(client_id);
+                self.handoff_requests.remove(client_id);
+                self.presenter = Some(client_id.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Records a request to take over from the presenter and returns who has to answer it.
+    pub fn request_handoff(&mut self, client_id: &ClientId) -> Result<ClientId, RoomError> {
+        let presenter = self.presenter.clone().ok_or_else(|| {
+            RoomError::TransactionError("Nobody is presenting; claim the deck instead".to_string())
+        })?;
+        if presenter == *client_id {
+            return Err(RoomError::TransactionError(
+                "Already the presenter".to_string(),
+            ));
+        }
+
+        self.handoff_requests.insert(client_id.clone());
+        Ok(presenter)
+    }
+
+    /// Hands the deck to `requester`. The outgoing presenter stays on as co-presenter.
+    pub fn accept_handoff(
+        &mut self,
+        presenter: &ClientId,
+        requester: &ClientId,
+    ) -> Result<(), RoomError> {
+        self.ensure_presenter(presenter)?;
+        if !self.handoff_requests.remove(requester) {
+            return Err(RoomError::TransactionError(format!(
+                "{requester} has not asked to present"
+            )));
+        }
+
+        self.co_presenters.remove(requester);
+        self.co_presenters.insert(presenter.clone());
+        self.presenter = Some(requester.clone());
+        Ok(())
+    }
+
+    pub fn decline_handoff(
+        &mut self,
+        presenter: &ClientId,
+        requester: &ClientId,
+    ) -> Result<(), RoomError> {
+        self.ensure_presenter(presenter)?;
+        if !self.handoff_requests.remove(requester) {
+            return Err(RoomError::TransactionError(format!(
+                "{requester} has not asked to present"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Grants or revokes co-presenter rights. Returns `true` if the target's role changed.
+    pub fn set_co_presenter(
+        &mut self,
+        presenter: &ClientId,
+        target: &ClientId,
+        enabled: bool,
+    ) -> Result<bool, RoomError> {
+        self.ensure_presenter(presenter)?;
+        if target == presenter {
+            return Err(RoomError::TransactionError(
+                "The presenter can't change their own role".to_string(),
+            ));
+        }
+
+        Ok(if enabled {
+            self.co_presenters.insert(target.clone())
+        } else {
+            self.co_presenters.remove(target)
+        })
+    }
+
+    /// Forgets a client that left. A departing presenter leaves the deck unclaimed.
+    pub fn remove(&mut self, client_id: &ClientId) {
+        if self.presenter.as_ref() == Some(client_id) {
+            self.presenter = None;
+        }
+        self.co_presenters.remove(client_id);
+        self.not_following.remove(client_id);
+        self.handoff_requests.remove(client_id);
+    }
+
+    fn ensure_presenter(&self, client_id: &ClientId) -> Result<(), RoomError> {
+        if self.presenter.as_ref() == Some(client_id) {
+            Ok(())
+        } else {
+            Err(RoomError::PermissionDenied(
+                "Only the presenter can do this".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_broker::memory::InMemoryMessageBroker,
+        presentation::PresentationClientMessage,
+        room::test_support::{client_message, presentation_with_clients},
+    };
+
+    #[tokio::test]
+    async fn only_presenters_change_slides_until_handoff() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let _bob = broker.connect("bob");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice", "bob"]).await;
+        let (alice, bob) = ("alice".to_string(), "bob".to_string());
+
+        let change_slide = || {
+            client_message(
+                &room_id,
+                PresentationClientMessage::ChangeSlide { slide_index: 1 },
+            )
+        };
+        assert!(matches!(
+            manager
+                .handle_client_message(&room_id, &bob, change_slide())
+                .await,
+            Err(RoomError::PermissionDenied(_))
+        ));
+
+        manager
+            .handle_client_message(
+                &room_id,
+                &bob,
+                client_message(&room_id, PresentationClientMessage::RequestPresenter),
+            )
+            .await
+            .unwrap();
+        let request = broker.take_deliveries();
+        assert_eq!(request.len(), 1);
+        assert_eq!(request[0].client_id, alice);
+
+        manager
+            .handle_client_message(
+                &room_id,
+                &alice,
+                client_message(
+                    &room_id,
+                    PresentationClientMessage::AcceptPresenterRequest {
+                        client_id: bob.clone(),
+                    },
+                ),
+            )
+            .await
+            .unwrap();
+        manager
+            .handle_client_message(&room_id, &bob, change_slide())
+            .await
+            .unwrap();
+
+        let roles = manager
+            .with_room(&room_id, |room| {
+                (room.roles().role_of(&alice), room.roles().role_of(&bob))
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            roles,
+            (PresentationRole::CoPresenter, PresentationRole::Presenter)
+        );
+    }
+}
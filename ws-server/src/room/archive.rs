@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use super::{
+    RoomError, RoomLike, RoomMetadata,
+    history::HistoryEntry,
+    room_id::RoomId,
+    storage::{StorageError, StorageLike},
+};
+
+/// Version of the [`RoomArchive`] format. Archives of any other version are rejected.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// A room packed up to be moved to another environment.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RoomArchive {
+    pub archive_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub room_id: RoomId,
+    pub room_type: String,
+    pub metadata: RoomMetadata,
+    /// The storage's [`StorageLike::SNAPSHOT_VERSION`] when it was exported.
+    pub snapshot_version: u32,
+    pub version: u64,
+    #[ts(type = "unknown")]
+    pub storage: Value,
+    /// The room's [`RoomLike::private_state`], if it has any.
+    #[serde(default)]
+    #[ts(type = "unknown")]
+    pub private_state: Option<Value>,
+    /// The room's history, oldest first, if it was exported along with the room.
+    #[serde(default)]
+    pub history: Option<Vec<HistoryEntry>>,
+    /// Keys of files the storage refers to, which have to be copied over separately.
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+impl RoomArchive {
+    pub fn from_room<R: RoomLike>(
+        room: &R,
+        history: Option<Vec<HistoryEntry>>,
+    ) -> Result<Self, RoomError> {
+        Ok(Self {
+            archive_version: ARCHIVE_VERSION,
+            exported_at: Utc::now(),
+            room_id: room.id().clone(),
+            room_type: room.room_type().to_string(),
+            metadata: room.metadata().clone(),
+            snapshot_version: R::Storage::SNAPSHOT_VERSION,
+            version: room.version(),
+            storage: room.storage().snapshot()?,
+            private_state: room.private_state()?,
+            history,
+            files: room.storage().file_keys(),
+        })
+    }
+
+    /// Rebuilds the archived room, upgrading its storage snapshot to the current
+    /// snapshot version first. Returns the history along with it, unless the snapshot
+    /// had to be upgraded: older history can't be replayed on the current shape.
+    pub fn into_room<R: RoomLike>(self) -> Result<(R, Option<Vec<HistoryEntry>>), RoomError> {
+        if self.archive_version != ARCHIVE_VERSION {
+            return Err(RoomError::TransactionError(format!(
+                "Unsupported archive version {}; expected {ARCHIVE_VERSION}",
+                self.archive_version
+            )));
+        }
+
+        let current = R::Storage::SNAPSHOT_VERSION;
+        if self.snapshot_version > current {
+            return Err(StorageError::UnsupportedSnapshotVersion {
+                version: self.snapshot_version,
+                current,
+            }
+            .into());
+        }
+        let mut snapshot = self.storage;
+        for version in self.snapshot_version..current {
+            snapshot = R::Storage::upgrade_snapshot(snapshot, version)?;
+        }
+        let history = self.history.filter(|_| self.snapshot_version == current);
+
+        let storage = R::Storage::from_snapshot(snapshot)?;
+        let mut room = R::create(self.room_id, self.metadata, storage);
+        if room.room_type() != self.room_type {
+            return Err(RoomError::TransactionError(format!(
+                "Archive holds a {}, not a {}",
+                self.room_type,
+                room.room_type()
+            )));
+        }
+        room.set_version(self.version);
+        if let Some(state) = self.private_state {
+            room.restore_private_state(state)?;
+        }
+        Ok((room, history))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_broker::memory::InMemoryMessageBroker,
+        presentation::PresentationClientMessage,
+        room::{
+            history::HistoryPoint,
+            persistence::InMemoryRoomStore,
+            room_manager::RoomManager,
+            test_support::{TestManager, create_presentation, join_as_presenter, send_all},
+        },
+    };
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn archives_move_rooms_with_history_between_stores() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let source = RoomManager::new(broker.clone()).with_store(InMemoryRoomStore::new());
+        let slides = vec![
+            json!({ "image": { "file_key": "decks/cover.png" } }),
+            json!({ "file_key": "decks/chart.png" }),
+        ];
+        let room_id = create_presentation(&source, "room_archived", slides).await;
+
+        join_as_presenter(&source, &room_id, "alice").await;
+        let change = PresentationClientMessage::ChangeSlide { slide_index: 1 };
+        send_all(&source, &room_id, "alice", [change]).await;
+
+        let archive = source.export_room(&room_id, true).await.unwrap();
+        assert_eq!(archive.version, 1);
+        assert_eq!(archive.files, vec!["decks/cover.png", "decks/chart.png"]);
+        assert_eq!(archive.history.as_ref().map(Vec::len), Some(2));
+
+        // Travels as JSON, e.g. between two deployments
+        let archive: RoomArchive =
+            serde_json::from_value(serde_json::to_value(&archive).unwrap()).unwrap();
+        let target = TestManager::new(broker.clone()).with_store(InMemoryRoomStore::new());
+        assert_eq!(target.import_room(archive.clone()).await.unwrap(), room_id);
+        assert!(matches!(
+            target.import_room(archive.clone()).await,
+            Err(RoomError::RoomAlreadyExists(_))
+        ));
+
+        let version = target.with_room(&room_id, |room| room.version()).await;
+        assert_eq!(version.unwrap(), 1);
+        let (_, storage) = target
+            .storage_at(&room_id, HistoryPoint::Version(0))
+            .await
+            .unwrap();
+        assert_eq!(storage.snapshot().unwrap()["current_slide"], json!(0));
+
+        let future = RoomArchive {
+            archive_version: 2,
+            ..archive
+        };
+        assert!(target.import_room(future).await.is_err());
+    }
+}
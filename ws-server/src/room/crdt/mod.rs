@@ -0,0 +1,606 @@
+//! A generic CRDT document: a tree of maps, lists, texts, counters and registers,
+//! changed through [`CrdtOp`]s. Ops commute and are idempotent, so replicas that
+//! saw the same ops hold the same document no matter who applied what first, and
+//! clients can edit offline and sync whenever they are back.
+
+pub mod object;
+pub mod op;
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use super::storage::{StorageError, StorageLike};
+pub use object::CrdtObject;
+pub use op::{CrdtOp, CrdtValue, ObjectKind, OpId};
+
+/// The document's objects by the id of the op that created them, starting from the
+/// root map at [`OpId::root`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct CrdtStorage {
+    objects: BTreeMap<OpId, CrdtObject>,
+    /// Objects whose `Create` hasn't arrived yet, built from the ops on them as every
+    /// kind they could still turn out to be. Stored as those ops.
+    #[serde(default, with = "pending_ops")]
+    #[ts(as = "Vec<CrdtOp>")]
+    pending: Pending,
+    /// The greatest op counter seen, so [`CrdtStorage::next_id`] stays ahead of it.
+    #[serde(default)]
+    clock: u64,
+}
+
+/// Candidate objects by the id their `Create` will have.
+type Pending = BTreeMap<OpId, Vec<CrdtObject>>;
+
+const KINDS: [ObjectKind; 5] = [
+    ObjectKind::Map,
+    ObjectKind::List,
+    ObjectKind::Text,
+    ObjectKind::Counter,
+    ObjectKind::Register,
+];
+
+impl Default for CrdtStorage {
+    fn default() -> Self {
+        Self {
+            objects: BTreeMap::from([(OpId::root(), CrdtObject::new(ObjectKind::Map))]),
+            pending: Pending::new(),
+            clock: 0,
+        }
+    }
+}
+
+impl CrdtStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A fresh id for an op made by `replica`, greater than any op applied so far.
+    pub fn next_id(&mut self, replica: &str) -> OpId {
+        self.clock += 1;
+        OpId::new(self.clock, replica)
+    }
+
+    pub fn object(&self, id: &OpId) -> Option<&CrdtObject> {
+        self.objects.get(id)
+    }
+
+    /// Ids of the visible elements of a list or text, in order; empty for anything else.
+    pub fn elements(&self, obj: &OpId) -> Vec<OpId> {
+        match self.objects.get(obj) {
+            Some(CrdtObject::List(sequence) | CrdtObject::Text(sequence)) => {
+                sequence.visible().into_iter().cloned().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Applies a single op. Returns whether it changed anything; applying an op again,
+    /// or one that lost to a newer write, doesn't.
+    pub fn apply_op(&mut self, op: CrdtOp) -> Result<bool, StorageError> {
+        if let Some(id) = op.id() {
+            self.clock = self.clock.max(id.counter);
+        }
+        if let CrdtOp::Create { id, kind } = &op {
+            return self.create(id, *kind);
+        }
+
+        match op.target().and_then(|obj| self.objects.get_mut(obj)) {
+            Some(object) => object.apply(&op),
+            None => buffer(&mut self.pending, &op),
+        }
+    }
+
+    fn create(&mut self, id: &OpId, kind: ObjectKind) -> Result<bool, StorageError> {
+        if let Some(existing) = self.objects.get(id) {
+            if existing.kind() != kind {
+                return Err(StorageError::ApplyDiffError(format!(
+                    "Object {id} is a {:?}, not a {kind:?}",
+                    existing.kind()
+                )));
+            }
+            return Ok(false);
+        }
+
+        // The ops already on the object rule some kinds out, as they would if it existed
+        let fits = |candidates: &Vec<CrdtObject>| candidates.iter().any(|c| c.kind() == kind);
+        if self
+            .pending
+            .get(id)
+            .is_some_and(|candidates| !fits(candidates))
+        {
+            return Err(StorageError::ApplyDiffError(format!(
+                "Ops on object {id} don't fit a {kind:?}"
+            )));
+        }
+
+        let object = self
+            .pending
+            .remove(id)
+            .into_iter()
+            .flatten()
+            .find(|candidate| candidate.kind() == kind)
+            .unwrap_or_else(|| CrdtObject::new(kind));
+        self.objects.insert(id.clone(), object);
+        Ok(true)
+    }
+
+    /// Ops that rebuild the whole document on an empty replica.
+    pub fn ops(&self) -> Vec<CrdtOp> {
+        let creates = self
+            .objects
+            .iter()
+            .filter(|(id, _)| **id != OpId::root())
+            .map(|(id, object)| CrdtOp::Create {
+                id: id.clone(),
+                kind: object.kind(),
+            });
+        let changes = self.objects.iter().flat_map(|(id, object)| object.ops(id));
+        creates
+            .chain(changes)
+            .chain(waiting(&self.pending))
+            .collect()
+    }
+
+    /// The document as plain JSON: maps become objects, lists arrays, texts strings,
+    /// counters numbers and registers their value.
+    pub fn to_json(&self) -> Value {
+        self.object_json(&OpId::root(), &mut Vec::new())
+    }
+
+    fn object_json(&self, id: &OpId, path: &mut Vec<OpId>) -> Value {
+        // Ops can make an object contain itself; cut the cycle instead of recursing forever
+        if path.contains(id) {
+            return Value::Null;
+        }
+        let Some(object) = self.objects.get(id) else {
+            return Value::Null;
+        };
+
+        path.push(id.clone());
+        let json = match object {
+            CrdtObject::Map(entries) => Value::Object(
+                entries
+                    .iter()
+                    .filter_map(|(key, entry)| {
+                        let value = entry.value.as_ref()?;
+                        Some((key.clone(), self.value_json(value, path)))
+                    })
+                    .collect(),
+            ),
+            CrdtObject::List(sequence) => Value::Array(
+                sequence
+                    .visible()
+                    .into_iter()
+                    .map(|id| self.value_json(&sequence.elements[id].value, path))
+                    .collect(),
+            ),
+            CrdtObject::Text(sequence) => Value::String(
+                sequence
+                    .visible()
+                    .into_iter()
+                    .filter_map(|id| match &sequence.elements[id].value {
+                        CrdtValue::Scalar(Value::String(chunk)) => Some(chunk.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            CrdtObject::Counter(increments) => Value::from(
+                increments
+                    .values()
+                    .fold(0i64, |total, by| total.saturating_add(*by)),
+            ),
+            CrdtObject::Register(current) => current
+                .as_ref()
+                .map(|current| self.value_json(&current.value, path))
+                .unwrap_or(Value::Null),
+        };
+        path.pop();
+        json
+    }
+
+    fn value_json(&self, value: &CrdtValue, path: &mut Vec<OpId>) -> Value {
+        match value {
+            CrdtValue::Scalar(value) => value.clone(),
+            CrdtValue::Object(id) => self.object_json(id, path),
+        }
+    }
+}
+
+/// Applies an op on an object that hasn't been created to each kind of object it could
+/// still be, ruling out the kinds it doesn't fit. Fails if it fits none of them, just
+/// as it fails on an object of the wrong kind.
+fn buffer(pending: &mut Pending, op: &CrdtOp) -> Result<bool, StorageError> {
+    let Some(obj) = op.target() else {
+        return Ok(false);
+    };
+    // Whether an op fits depends only on the object's kind, not on its state
+    let fits = |kind: ObjectKind| CrdtObject::new(kind).apply(op).is_ok();
+    let fitting: Vec<ObjectKind> = match pending.get(obj) {
+        Some(candidates) => candidates
+            .iter()
+            .map(CrdtObject::kind)
+            .filter(|kind| fits(*kind))
+            .collect(),
+        None => KINDS.into_iter().filter(|kind| fits(*kind)).collect(),
+    };
+    if fitting.is_empty() {
+        return Err(StorageError::ApplyDiffError(format!(
+            "Can't apply {op:?} along with the other ops on object {obj}"
+        )));
+    }
+
+    let candidates = pending
+        .entry(obj.clone())
+        .or_insert_with(|| fitting.iter().map(|kind| CrdtObject::new(*kind)).collect());
+    candidates.retain(|candidate| fitting.contains(&candidate.kind()));
+    let mut changed = false;
+    for candidate in candidates {
+        changed |= candidate.apply(op)?;
+    }
+    Ok(changed)
+}
+
+/// The ops that built the pending objects. The candidates for an object all went
+/// through the same ops, so any one of them tells what they were.
+fn waiting(pending: &Pending) -> impl Iterator<Item = CrdtOp> + '_ {
+    pending.iter().flat_map(|(id, candidates)| {
+        candidates
+            .first()
+            .map(|candidate| candidate.ops(id))
+            .unwrap_or_default()
+    })
+}
+
+/// Stores pending objects as the ops on them, so snapshots don't depend on how they
+/// are kept in memory.
+mod pending_ops {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    use super::{CrdtOp, Pending, buffer, waiting};
+
+    pub fn serialize<S: Serializer>(pending: &Pending, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(waiting(pending))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pending, D::Error> {
+        let mut pending = Pending::new();
+        for op in Vec::<CrdtOp>::deserialize(deserializer)? {
+            buffer(&mut pending, &op).map_err(D::Error::custom)?;
+        }
+        Ok(pending)
+    }
+}
+
+impl StorageLike for CrdtStorage {
+    /// The ops that changed the document.
+    type ApplyResult = Vec<CrdtOp>;
+    /// A batch of ops. Batches commute, so the server can apply them in any order.
+    type Diff = Vec<CrdtOp>;
+
+    fn storage_type_id(&self) -> &'static str {
+        "crdt"
+    }
+
+    /// A true state merge: afterwards `self` holds every op either side had, exactly
+    /// as if it had applied all of them. Merging again, or merging `self` into
+    /// `other` instead, ends in the same document.
+    fn merge(&mut self, other: &Self) -> Result<Self::ApplyResult, StorageError> {
+        self.apply_diff(other.ops())
+    }
+
+    /// The ops `other` has and `self` lacks. State only ever grows, so this can't
+    /// express taking ops away: diffing towards an older state yields nothing.
+    fn diff(&self, other: &Self) -> Result<Self::Diff, StorageError> {
+        let mut probe = self.clone();
+        Ok(other
+            .ops()
+            .into_iter()
+            .filter(|op| matches!(probe.apply_op(op.clone()), Ok(true)))
+            .collect())
+    }
+
+    /// Applies the whole batch, or nothing if any op doesn't fit the document.
+    fn apply_diff(&mut self, diff: Self::Diff) -> Result<Self::ApplyResult, StorageError> {
+        let mut next = self.clone();
+        let mut applied = Vec::new();
+        for op in diff {
+            if next.apply_op(op.clone())? {
+                applied.push(op);
+            }
+        }
+        *self = next;
+        Ok(applied)
+    }
+
+    fn snapshot(&self) -> Result<serde_json::Value, StorageError> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn from_snapshot(snapshot: serde_json::Value) -> Result<Self, StorageError>
+    where
+        Self: Sized,
+    {
+        let storage: Self = serde_json::from_value(snapshot)?;
+        match storage.objects.get(&OpId::root()) {
+            Some(CrdtObject::Map(_)) => Ok(storage),
+            _ => Err(StorageError::ApplyDiffError(
+                "CRDT snapshot has no root map".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scalar(value: Value) -> CrdtValue {
+        CrdtValue::Scalar(value)
+    }
+
+    /// Creates an object under `key` of the root map.
+    fn create(doc: &mut CrdtStorage, replica: &str, key: &str, kind: ObjectKind) -> Vec<CrdtOp> {
+        let id = doc.next_id(replica);
+        let ops = vec![
+            CrdtOp::Create {
+                id: id.clone(),
+                kind,
+            },
+            CrdtOp::Put {
+                id: doc.next_id(replica),
+                obj: OpId::root(),
+                key: key.to_string(),
+                value: Some(CrdtValue::Object(id)),
+            },
+        ];
+        doc.apply_diff(ops.clone()).unwrap();
+        ops
+    }
+
+    fn edit(doc: &mut CrdtStorage, ops: Vec<CrdtOp>) -> Vec<CrdtOp> {
+        doc.apply_diff(ops.clone()).unwrap();
+        ops
+    }
+
+    fn put_theme(counter: u64, replica: &str, theme: &str) -> CrdtOp {
+        CrdtOp::Put {
+            id: OpId::new(counter, replica),
+            obj: OpId::root(),
+            key: "theme".to_string(),
+            value: Some(scalar(json!(theme))),
+        }
+    }
+
+    fn object_at(doc: &CrdtStorage, key: &str) -> OpId {
+        match doc.object(&OpId::root()) {
+            Some(CrdtObject::Map(entries)) => match &entries[key].value {
+                Some(CrdtValue::Object(id)) => id.clone(),
+                other => panic!("{key} holds {other:?}"),
+            },
+            other => panic!("No root map: {other:?}"),
+        }
+    }
+
+    fn insert_text(
+        doc: &mut CrdtStorage,
+        replica: &str,
+        obj: &OpId,
+        index: usize,
+        text: &str,
+    ) -> Vec<CrdtOp> {
+        let mut after = index
+            .checked_sub(1)
+            .map(|index| doc.elements(obj)[index].clone());
+        let mut ops = Vec::new();
+        for chunk in text.chars() {
+            let id = doc.next_id(replica);
+            ops.push(CrdtOp::Insert {
+                id: id.clone(),
+                obj: obj.clone(),
+                after: after.replace(id),
+                value: scalar(json!(chunk.to_string())),
+            });
+        }
+        doc.apply_diff(ops.clone()).unwrap();
+        ops
+    }
+
+    #[test]
+    fn concurrent_edits_converge_in_any_order() {
+        let mut base = CrdtStorage::new();
+        let setup = [
+            create(&mut base, "base", "title", ObjectKind::Text),
+            create(&mut base, "base", "likes", ObjectKind::Counter),
+            create(&mut base, "base", "tags", ObjectKind::List),
+            create(&mut base, "base", "owner", ObjectKind::Register),
+        ]
+        .concat();
+        let title = object_at(&base, "title");
+        let likes = object_at(&base, "likes");
+        let owner = object_at(&base, "owner");
+        insert_text(&mut base, "base", &title, 0, "ac");
+
+        let mut alice = base.clone();
+        let mut bob = base.clone();
+
+        let mut alice_ops = insert_text(&mut alice, "alice", &title, 1, "b");
+        alice_ops.extend(edit(
+            &mut alice,
+            vec![
+                CrdtOp::Increment {
+                    id: OpId::new(12, "alice"),
+                    obj: likes.clone(),
+                    by: 2,
+                },
+                CrdtOp::Assign {
+                    id: OpId::new(13, "alice"),
+                    obj: owner.clone(),
+                    value: scalar(json!("alice")),
+                },
+                put_theme(14, "alice", "dark"),
+            ],
+        ));
+
+        let mut bob_ops = insert_text(&mut bob, "bob", &title, 1, "B");
+        let first = bob.elements(&title)[0].clone();
+        bob_ops.extend(edit(
+            &mut bob,
+            vec![
+                CrdtOp::Remove {
+                    obj: title.clone(),
+                    target: first,
+                },
+                CrdtOp::Increment {
+                    id: OpId::new(12, "bob"),
+                    obj: likes.clone(),
+                    by: -1,
+                },
+                CrdtOp::Assign {
+                    id: OpId::new(13, "bob"),
+                    obj: owner.clone(),
+                    value: scalar(json!("bob")),
+                },
+                put_theme(14, "bob", "light"),
+            ],
+        ));
+
+        // The server takes the batches in whichever order they arrive
+        let mut first_alice = base.clone();
+        first_alice.apply_diff(alice_ops.clone()).unwrap();
+        first_alice.apply_diff(bob_ops.clone()).unwrap();
+        let mut first_bob = base.clone();
+        first_bob.apply_diff(bob_ops.clone()).unwrap();
+        first_bob.apply_diff(alice_ops.clone()).unwrap();
+
+        assert_eq!(first_alice, first_bob);
+        assert_eq!(
+            first_alice.to_json(),
+            json!({
+                // Concurrent inserts at the same place: the greater id comes first
+                "title": "Bbc",
+                "likes": 1,
+                "tags": [],
+                // Concurrent writes with the same counter: the greater replica wins
+                "owner": "bob",
+                "theme": "light",
+            })
+        );
+
+        // Replaying a batch, or the setup, changes nothing
+        assert!(first_alice.apply_diff(alice_ops).unwrap().is_empty());
+        assert!(first_alice.apply_diff(setup).unwrap().is_empty());
+        assert_eq!(first_alice, first_bob);
+    }
+
+    #[test]
+    fn merge_and_diff_reconcile_replicas() {
+        let mut server = CrdtStorage::new();
+        create(&mut server, "server", "notes", ObjectKind::List);
+        let notes = object_at(&server, "notes");
+
+        let mut offline = server.clone();
+        let id = offline.next_id("offline");
+        offline
+            .apply_op(CrdtOp::Insert {
+                id,
+                obj: notes.clone(),
+                after: None,
+                value: scalar(json!("from offline")),
+            })
+            .unwrap();
+        let id = server.next_id("server");
+        server
+            .apply_op(CrdtOp::Insert {
+                id,
+                obj: notes.clone(),
+                after: None,
+                value: scalar(json!("from server")),
+            })
+            .unwrap();
+
+        let missing = server.diff(&offline).unwrap();
+        assert_eq!(missing.len(), 1);
+        let mut merged = server.clone();
+        assert_eq!(merged.merge(&offline).unwrap(), missing);
+
+        let mut other_way = offline.clone();
+        other_way.merge(&server).unwrap();
+        assert_eq!(merged.to_json(), other_way.to_json());
+        assert!(merged.diff(&other_way).unwrap().is_empty());
+        assert!(merged.merge(&offline).unwrap().is_empty());
+
+        let restored = CrdtStorage::from_snapshot(merged.snapshot().unwrap()).unwrap();
+        assert_eq!(restored, merged);
+    }
+
+    #[test]
+    fn ops_wait_for_the_object_they_target() {
+        let mut doc = CrdtStorage::new();
+        let list = OpId::new(1, "a");
+        let insert = CrdtOp::Insert {
+            id: OpId::new(2, "a"),
+            obj: list.clone(),
+            after: None,
+            value: scalar(json!(1)),
+        };
+        let ops = vec![
+            CrdtOp::Create {
+                id: list.clone(),
+                kind: ObjectKind::List,
+            },
+            CrdtOp::Put {
+                id: OpId::new(3, "a"),
+                obj: OpId::root(),
+                key: "list".to_string(),
+                value: Some(CrdtValue::Object(list)),
+            },
+        ];
+
+        doc.apply_diff(vec![insert.clone()]).unwrap();
+        assert_eq!(doc.to_json(), json!({}));
+        doc.apply_diff(ops).unwrap();
+        assert_eq!(doc.to_json(), json!({ "list": [1] }));
+        assert_eq!(doc.next_id("b"), OpId::new(4, "b"));
+
+        let wrong_kind = CrdtOp::Increment {
+            id: OpId::new(5, "b"),
+            obj: object_at(&doc, "list"),
+            by: 1,
+        };
+        assert!(doc.apply_diff(vec![insert, wrong_kind]).is_err());
+        assert_eq!(doc.to_json(), json!({ "list": [1] }));
+
+        // An op that doesn't fit is refused whether it comes before or after the Create
+        let counter = OpId::new(6, "b");
+        let create = CrdtOp::Create {
+            id: counter.clone(),
+            kind: ObjectKind::Counter,
+        };
+        let increment = CrdtOp::Increment {
+            id: OpId::new(7, "b"),
+            obj: counter.clone(),
+            by: 1,
+        };
+        let assign = CrdtOp::Assign {
+            id: OpId::new(8, "b"),
+            obj: counter,
+            value: scalar(json!(1)),
+        };
+        let mut created_first = doc.clone();
+        created_first.apply_diff(vec![create.clone()]).unwrap();
+        assert!(created_first.apply_diff(vec![assign.clone()]).is_err());
+
+        let mut waiting = doc.clone();
+        waiting.apply_diff(vec![increment.clone()]).unwrap();
+        assert!(waiting.apply_diff(vec![assign]).is_err());
+        let restored = CrdtStorage::from_snapshot(waiting.snapshot().unwrap()).unwrap();
+        assert_eq!(restored, waiting);
+        waiting.apply_diff(vec![create]).unwrap();
+        created_first.apply_diff(vec![increment]).unwrap();
+        assert_eq!(waiting, created_first);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::op::{CrdtOp, CrdtValue, ObjectKind, OpId};
+use crate::room::storage::StorageError;
+
+/// A value along with the id of the op that wrote it. Of two writes, the one with
+/// the greater id wins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct Stamped<T> {
+    pub id: OpId,
+    pub value: T,
+}
+
+/// An element of a list or text, inserted right after its origin `after`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct Element {
+    pub after: Option<OpId>,
+    pub value: CrdtValue,
+}
+
+/// A replicated growable array: every element ever inserted, plus the ids of the
+/// removed ones. Removed elements stay around as origins for later inserts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+pub struct Sequence {
+    pub elements: BTreeMap<OpId, Element>,
+    pub removed: BTreeSet<OpId>,
+}
+
+impl Sequence {
+    /// Ids of the elements that aren't removed, in order.
+    ///
+    /// Every element directly follows its origin, and elements sharing an origin are
+    /// ordered newest first, so concurrent inserts at the same place end up in the
+    /// same order on every replica. Elements whose origin hasn't arrived yet are left
+    /// out until it does.
+    pub fn visible(&self) -> Vec<&OpId> {
+        let mut children: HashMap<Option<&OpId>, Vec<&OpId>> = HashMap::new();
+        for (id, element) in &self.elements {
+            children.entry(element.after.as_ref()).or_default().push(id);
+        }
+
+        let mut visible = Vec::new();
+        // Ids come out of the BTreeMap ascending, so the newest sibling is popped first
+        let mut stack = children.remove(&None).unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if !self.removed.contains(id) {
+                visible.push(id);
+            }
+            stack.extend(children.remove(&Some(id)).unwrap_or_default());
+        }
+        visible
+    }
+}
+
+/// The replicated state of one object of a [`CrdtStorage`](super::CrdtStorage).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "kind", content = "state", rename_all = "snake_case")]
+pub enum CrdtObject {
+    /// Deleted keys are kept with a `None` value so that older writes stay overridden.
+    Map(BTreeMap<String, Stamped<Option<CrdtValue>>>),
+    List(Sequence),
+    Text(Sequence),
+    /// Every increment, by the id of its op.
+    Counter(BTreeMap<OpId, i64>),
+    Register(Option<Stamped<CrdtValue>>),
+}
+
+impl CrdtObject {
+    pub fn new(kind: ObjectKind) -> Self {
+        match kind {
+            ObjectKind::Map => Self::Map(BTreeMap::new()),
+            ObjectKind::List => Self::List(Sequence::default()),
+            ObjectKind::Text => Self::Text(Sequence::default()),
+            ObjectKind::Counter => Self::Counter(BTreeMap::new()),
+            ObjectKind::Register => Self::Register(None),
+        }
+    }
+
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Self::Map(_) => ObjectKind::Map,
+            Self::List(_) => ObjectKind::List,
+            Self::Text(_) => ObjectKind::Text,
+            Self::Counter(_) => ObjectKind::Counter,
+            Self::Register(_) => ObjectKind::Register,
+        }
+    }
+
+    /// Applies an op targeting this object. Returns whether anything changed, which it
+    /// doesn't when the op was applied before or lost to a newer write.
+    pub fn apply(&mut self, op: &CrdtOp) -> Result<bool, StorageError> {
+        match (self, op) {
+            (Self::Map(entries), CrdtOp::Put { id, key, value, .. }) => {
+                if entries.get(key).is_some_and(|entry| entry.id >= *id) {
+                    return Ok(false);
+                }
+                entries.insert(
+                    key.clone(),
+                    Stamped {
+                        id: id.clone(),
+                        value: value.clone(),
+                    },
+                );
+                Ok(true)
+            }
+            (Self::Text(_), CrdtOp::Insert { value, .. })
+                if !matches!(value, CrdtValue::Scalar(serde_json::Value::String(_))) =>
+            {
+                Err(StorageError::ApplyDiffError(
+                    "Text can only hold strings".to_string(),
+                ))
+            }
+            (
+                Self::List(sequence) | Self::Text(sequence),
+                CrdtOp::Insert {
+                    id, after, value, ..
+                },
+            ) => {
+                if sequence.elements.contains_key(id) {
+                    return Ok(false);
+                }
+                sequence.elements.insert(
+                    id.clone(),
+                    Element {
+                        after: after.clone(),
+                        value: value.clone(),
+                    },
+                );
+                Ok(true)
+            }
+            (Self::List(sequence) | Self::Text(sequence), CrdtOp::Remove { target, .. }) => {
+                Ok(sequence.removed.insert(target.clone()))
+            }
+            (Self::Counter(increments), CrdtOp::Increment { id, by, .. }) => {
+                if increments.contains_key(id) {
+                    return Ok(false);
+                }
+                increments.insert(id.clone(), *by);
+                Ok(true)
+            }
+            (Self::Register(current), CrdtOp::Assign { id, value, .. }) => {
+                if current.as_ref().is_some_and(|current| current.id >= *id) {
+                    return Ok(false);
+                }
+                *current = Some(Stamped {
+                    id: id.clone(),
+                    value: value.clone(),
+                });
+                Ok(true)
+            }
+            (object, op) => Err(StorageError::ApplyDiffError(format!(
+                "Can't apply {op:?} to a {:?}",
+                object.kind()
+            ))),
+        }
+    }
+
+    /// Ops that rebuild this object's state on a replica that has created it.
+    pub fn ops(&self, obj: &OpId) -> Vec<CrdtOp> {
+        match self {
+            Self::Map(entries) => entries
+                .iter()
+                .map(|(key, entry)| CrdtOp::Put {
+                    id: entry.id.clone(),
+                    obj: obj.clone(),
+                    key: key.clone(),
+                    value: entry.value.clone(),
+                })
+                .collect(),
+            Self::List(sequence) | Self::Text(sequence) => {
+                let inserts = sequence
+                    .elements
+                    .iter()
+                    .map(|(id, element)| CrdtOp::Insert {
+                        id: id.clone(),
+                        obj: obj.clone(),
+                        after: element.after.clone(),
+                        value: element.value.clone(),
+                    });
+                let removes = sequence.removed.iter().map(|target| CrdtOp::Remove {
+                    obj: obj.clone(),
+                    target: target.clone(),
+                });
+                inserts.chain(removes).collect()
+            }
+            Self::Counter(increments) => increments
+                .iter()
+                .map(|(id, by)| CrdtOp::Increment {
+                    id: id.clone(),
+                    obj: obj.clone(),
+                    by: *by,
+                })
+                .collect(),
+            Self::Register(current) => current
+                .iter()
+                .map(|current| CrdtOp::Assign {
+                    id: current.id.clone(),
+                    obj: obj.clone(),
+                    value: current.value.clone(),
+                })
+                .collect(),
+        }
+    }
+}
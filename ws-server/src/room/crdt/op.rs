@@ -0,0 +1,151 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use ts_rs::TS;
+
+/// Identifies an operation, and the object or element it created: a Lamport
+/// timestamp plus the replica that made it. Ids are totally ordered, so whichever
+/// of two concurrent writes has the greater id wins on every replica.
+///
+/// Serialized as `"{counter}@{replica}"`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, TS)]
+#[ts(type = "string")]
+pub struct OpId {
+    pub counter: u64,
+    pub replica: String,
+}
+
+impl OpId {
+    pub fn new(counter: u64, replica: impl Into<String>) -> Self {
+        Self {
+            counter,
+            replica: replica.into(),
+        }
+    }
+
+    /// The document's root map, which exists from the start.
+    pub fn root() -> Self {
+        Self::new(0, "")
+    }
+}
+
+impl fmt::Display for OpId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.counter, self.replica)
+    }
+}
+
+impl FromStr for OpId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (counter, replica) = s
+            .split_once('@')
+            .ok_or_else(|| format!("Invalid op id {s:?}, expected counter@replica"))?;
+        let counter = counter
+            .parse()
+            .map_err(|_| format!("Invalid op id counter in {s:?}"))?;
+        Ok(Self::new(counter, replica))
+    }
+}
+
+impl Serialize for OpId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for OpId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The kinds of objects a document is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    /// String keys to values; concurrent writes to a key are last-writer-wins.
+    Map,
+    /// An ordered sequence of values.
+    List,
+    /// An ordered sequence of string chunks, usually one character each.
+    Text,
+    /// An integer that replicas add to and subtract from.
+    Counter,
+    /// A single last-writer-wins value.
+    Register,
+}
+
+/// What a map entry, list element or register holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum CrdtValue {
+    Scalar(#[ts(type = "unknown")] Value),
+    /// Another object of the document, by the id of the op that created it.
+    Object(OpId),
+}
+
+/// A single change to a document. Ops commute and are idempotent: replicas that
+/// have applied the same set of ops hold the same document, in whatever order and
+/// however often the ops arrived.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum CrdtOp {
+    /// Creates an empty object, to be referenced with [`CrdtValue::Object`].
+    Create { id: OpId, kind: ObjectKind },
+    /// Sets a map key; `None` deletes it.
+    Put {
+        id: OpId,
+        obj: OpId,
+        key: String,
+        value: Option<CrdtValue>,
+    },
+    /// Inserts into a list or text right after the element `after`, or at the start.
+    Insert {
+        id: OpId,
+        obj: OpId,
+        after: Option<OpId>,
+        value: CrdtValue,
+    },
+    /// Removes the list or text element `target`.
+    Remove { obj: OpId, target: OpId },
+    /// Adds `by` to a counter.
+    Increment { id: OpId, obj: OpId, by: i64 },
+    /// Sets a register.
+    Assign {
+        id: OpId,
+        obj: OpId,
+        value: CrdtValue,
+    },
+}
+
+impl CrdtOp {
+    /// The object the op changes; `None` for [`CrdtOp::Create`].
+    pub fn target(&self) -> Option<&OpId> {
+        match self {
+            Self::Create { .. } => None,
+            Self::Put { obj, .. }
+            | Self::Insert { obj, .. }
+            | Self::Remove { obj, .. }
+            | Self::Increment { obj, .. }
+            | Self::Assign { obj, .. } => Some(obj),
+        }
+    }
+
+    /// The op's own id; `None` for [`CrdtOp::Remove`], which is identified by its target.
+    pub fn id(&self) -> Option<&OpId> {
+        match self {
+            Self::Remove { .. } => None,
+            Self::Create { id, .. }
+            | Self::Put { id, .. }
+            | Self::Insert { id, .. }
+            | Self::Increment { id, .. }
+            | Self::Assign { id, .. } => Some(id),
+        }
+    }
+}
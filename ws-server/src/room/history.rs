@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use super::{RoomError, client_id::ClientId, room_id::RoomId, storage::StorageLike};
+
+/// Table room history is persisted to.
+pub const HISTORY_TABLE: &str = "room_history";
+
+/// How a room's storage reached a version.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HistoryChange {
+    /// A `StorageLike::Diff` applied on top of the previous version.
+    Diff {
+        #[ts(type = "unknown")]
+        diff: Value,
+    },
+    /// The whole storage, recorded when a room is created or its storage replaced.
+    Snapshot {
+        #[ts(type = "unknown")]
+        storage: Value,
+    },
+}
+
+/// One entry of a room's append-only history; there is one per storage version.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct HistoryEntry {
+    pub room_id: RoomId,
+    pub version: u64,
+    /// Client whose message made the change; `None` for changes made through the API.
+    pub client_id: Option<ClientId>,
+    pub timestamp: DateTime<Utc>,
+    pub change: HistoryChange,
+}
+
+/// A point in a room's history.
+#[derive(Debug, Clone, Copy)]
+pub enum HistoryPoint {
+    Version(u64),
+    /// The latest version at or before this time.
+    Time(DateTime<Utc>),
+}
+
+/// Rebuilds storage at `version` from `entries`, which must start with a snapshot and
+/// be followed by every version after it, oldest first.
+pub fn rebuild_storage<S: StorageLike>(
+    entries: Vec<HistoryEntry>,
+    version: u64,
+) -> Result<S, RoomError> {
+    let mut entries = entries.into_iter();
+    let (mut storage, mut at) = match entries.next() {
+        Some(HistoryEntry {
+            version,
+            change: HistoryChange::Snapshot { storage },
+            ..
+        }) => (S::from_snapshot(storage)?, version),
+        _ => {
+            return Err(RoomError::PersistenceError(format!(
+                "History doesn't reach back to version {version}"
+            )));
+        }
+    };
+
+    for entry in entries {
+        if entry.version != at + 1 {
+            return Err(RoomError::PersistenceError(format!(
+                "History is missing version {}",
+                at + 1
+            )));
+        }
+        match entry.change {
+            HistoryChange::Diff { diff } => {
+                storage.apply_diff(serde_json::from_value(diff)?)?;
+            }
+            HistoryChange::Snapshot { storage: snapshot } => {
+                storage = S::from_snapshot(snapshot)?;
+            }
+        }
+        at = entry.version;
+    }
+
+    if at != version {
+        return Err(RoomError::PersistenceError(format!(
+            "History only goes up to version {at}, not {version}"
+        )));
+    }
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{Message, StorageUpdate},
+        message_broker::{STORAGE_EVENT, memory::InMemoryMessageBroker},
+        presentation::PresentationClientMessage,
+        room::{
+            RoomLike,
+            persistence::InMemoryRoomStore,
+            room_manager::RoomManager,
+            test_support::{create_presentation, join_as_presenter, send_all},
+        },
+    };
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn history_rebuilds_and_restores_earlier_versions() {
+        let store = InMemoryRoomStore::new();
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let manager = RoomManager::new(broker.clone()).with_store(store.clone());
+        let room_id = create_presentation(&manager, "room_history", vec![json!({}); 3]).await;
+
+        let alice = "alice".to_string();
+        join_as_presenter(&manager, &room_id, &alice).await;
+        let changes =
+            [1, 2].map(|slide_index| PresentationClientMessage::ChangeSlide { slide_index });
+        send_all(&manager, &room_id, &alice, changes).await;
+
+        // Created as a snapshot, then one diff per change, attributed to its client
+        let history = manager.history(&room_id, 0, 10).await.unwrap();
+        let versions: Vec<u64> = history.iter().map(|entry| entry.version).collect();
+        assert_eq!(versions, vec![0, 1, 2]);
+        assert!(matches!(history[0].change, HistoryChange::Snapshot { .. }));
+        assert!(history[0].client_id.is_none());
+        assert!(matches!(history[2].change, HistoryChange::Diff { .. }));
+        assert_eq!(history[2].client_id.as_ref(), Some(&alice));
+        assert_eq!(manager.history(&room_id, 2, 10).await.unwrap().len(), 1);
+
+        let (version, storage) = manager
+            .storage_at(&room_id, HistoryPoint::Time(history[2].timestamp))
+            .await
+            .unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(storage.snapshot().unwrap()["current_slide"], json!(2));
+
+        // Restoring is a new change that clients receive like any other
+        broker.take_deliveries();
+        let restored = manager
+            .restore(&room_id, HistoryPoint::Version(1))
+            .await
+            .unwrap();
+        assert_eq!(restored, 3);
+        let current_slide = manager
+            .with_room(&room_id, |room| room.storage().snapshot().unwrap())
+            .await
+            .unwrap()["current_slide"]
+            .clone();
+        assert_eq!(current_slide, json!(1));
+
+        let update: Message<StorageUpdate<serde_json::Value>> = broker
+            .deliveries_to(&alice)
+            .iter()
+            .find(|delivery| delivery.msg_name == STORAGE_EVENT)
+            .unwrap()
+            .decode()
+            .unwrap();
+        assert_eq!(update.payload.version, 3);
+        let history = manager.history(&room_id, 0, 10).await.unwrap();
+        assert_eq!(history.last().unwrap().version, 3);
+    }
+}
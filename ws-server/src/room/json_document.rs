@@ -0,0 +1,281 @@
+use json_patch::{
+    AddOperation, MoveOperation, Patch, PatchOperation, RemoveOperation, ReplaceOperation,
+    jsonptr::PointerBuf,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use ts_rs::TS;
+
+use super::storage::{StorageError, StorageLike};
+
+/// A plain JSON document everyone in the room edits, for room types that don't need
+/// storage of their own. Diffs are RFC 6902 patches; clients can also send
+/// [`JsonDocumentOp`]s, which [`JsonDocumentStorage::apply_ops`] turns into patches.
+#[derive(Debug, Clone, PartialEq, TS, Deserialize, Serialize)]
+pub struct JsonDocumentStorage(#[ts(type = "unknown")] Value);
+
+impl Default for JsonDocumentStorage {
+    fn default() -> Self {
+        Self(Value::Object(Default::default()))
+    }
+}
+
+impl JsonDocumentStorage {
+    pub fn new(document: Value) -> Self {
+        Self(document)
+    }
+
+    pub fn document(&self) -> &Value {
+        &self.0
+    }
+
+    /// Applies `ops` in order, each against the document the previous ones left.
+    /// Either all of them apply or none do. Returns the patch they amounted to, to be
+    /// broadcast as the storage update.
+    pub fn apply_ops(&mut self, ops: &[JsonDocumentOp]) -> Result<Patch, StorageError> {
+        let mut document = self.0.clone();
+        let mut patch = Vec::with_capacity(ops.len());
+        for op in ops {
+            let operation = op.to_patch_operation(&document)?;
+            json_patch::patch(&mut document, std::slice::from_ref(&operation))
+                .map_err(|e| StorageError::ApplyDiffError(e.to_string()))?;
+            patch.push(operation);
+        }
+
+        self.0 = document;
+        Ok(Patch(patch))
+    }
+}
+
+/// Recursively merges `other` into `target`: objects are merged key by key,
+/// anything else in `other` replaces what is in `target`.
+pub fn merge_value(target: &mut Value, other: &Value) {
+    match (target, other) {
+        (Value::Object(target), Value::Object(other)) => {
+            for (key, value) in other {
+                match target.get_mut(key) {
+                    Some(existing) => merge_value(existing, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, other) => *target = other.clone(),
+    }
+}
+
+impl StorageLike for JsonDocumentStorage {
+    type ApplyResult = Self;
+    type Diff = Patch;
+
+    fn storage_type_id(&self) -> &'static str {
+        "json-document"
+    }
+
+    /// Deep-merges `other` into `self`, treating `other` as the more recent state.
+    fn merge(&mut self, other: &Self) -> Result<Self::ApplyResult, StorageError> {
+        merge_value(&mut self.0, &other.0);
+        Ok(self.clone())
+    }
+
+    /// RFC 6902 patch turning `self` into `other`.
+    fn diff(&self, other: &Self) -> Result<Self::Diff, StorageError> {
+        Ok(json_patch::diff(&self.0, &other.0))
+    }
+
+    /// Applies an RFC 6902 patch; if any operation fails, `self` is left untouched.
+    fn apply_diff(&mut self, diff: Self::Diff) -> Result<Self::ApplyResult, StorageError> {
+        json_patch::patch(&mut self.0, &diff)
+            .map_err(|e| StorageError::ApplyDiffError(e.to_string()))?;
+        Ok(self.clone())
+    }
+
+    fn snapshot(&self) -> Result<serde_json::Value, StorageError> {
+        Ok(self.0.clone())
+    }
+
+    fn from_snapshot(snapshot: serde_json::Value) -> Result<Self, StorageError>
+    where
+        Self: Sized,
+    {
+        Ok(Self(snapshot))
+    }
+}
+
+/// An edit to a [`JsonDocumentStorage`], addressed by JSON Pointer (RFC 6901).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JsonDocumentOp {
+    /// Sets the value at `path`, adding it if it isn't there. Its parent must exist.
+    Set {
+        #[ts(type = "string")]
+        path: PointerBuf,
+        #[ts(type = "unknown")]
+        value: Value,
+    },
+    /// Inserts into the array at `path`, before `index` or at the end.
+    Insert {
+        #[ts(type = "string")]
+        path: PointerBuf,
+        #[serde(default)]
+        index: Option<usize>,
+        #[ts(type = "unknown")]
+        value: Value,
+    },
+    Remove {
+        #[ts(type = "string")]
+        path: PointerBuf,
+    },
+    /// Moves the value at `from` to `path`, replacing whatever is there.
+    Move {
+        #[ts(type = "string")]
+        from: PointerBuf,
+        #[ts(type = "string")]
+        path: PointerBuf,
+    },
+    /// Adds `by` to the number at `path`, which counts as 0 if it isn't there.
+    Increment {
+        #[ts(type = "string")]
+        path: PointerBuf,
+        #[ts(type = "number")]
+        by: Number,
+    },
+}
+
+impl JsonDocumentOp {
+    /// The patch operation doing this to `document`. Increments become a plain
+    /// replace with the new total, so clients applying the patch need no special case.
+    pub fn to_patch_operation(&self, document: &Value) -> Result<PatchOperation, StorageError> {
+        Ok(match self {
+            Self::Set { path, value } => set(document, path, value.clone()),
+            Self::Insert { path, index, value } => {
+                let Ok(Value::Array(items)) = path.resolve(document) else {
+                    return Err(StorageError::ApplyDiffError(format!(
+                        "{path} is not an array"
+                    )));
+                };
+                let token = match index {
+                    Some(index) if *index > items.len() => {
+                        return Err(StorageError::ApplyDiffError(format!(
+                            "Index {index} is out of bounds for {path}"
+                        )));
+                    }
+                    Some(index) => index.to_string(),
+                    None => "-".to_string(),
+                };
+                PatchOperation::Add(AddOperation {
+                    path: path.with_trailing_token(token),
+                    value: value.clone(),
+                })
+            }
+            Self::Remove { path } => PatchOperation::Remove(RemoveOperation { path: path.clone() }),
+            Self::Move { from, path } => PatchOperation::Move(MoveOperation {
+                from: from.clone(),
+                path: path.clone(),
+            }),
+            Self::Increment { path, by } => {
+                let total = match path.resolve(document) {
+                    Err(_) => by.clone(),
+                    Ok(Value::Number(current)) => add_numbers(current, by).ok_or_else(|| {
+                        StorageError::ApplyDiffError(format!("Incrementing {path} overflows"))
+                    })?,
+                    Ok(_) => {
+                        return Err(StorageError::ApplyDiffError(format!(
+                            "{path} is not a number"
+                        )));
+                    }
+                };
+                set(document, path, Value::Number(total))
+            }
+        })
+    }
+}
+
+/// Replaces the value at `path` if there is one, adds it otherwise.
+fn set(document: &Value, path: &PointerBuf, value: Value) -> PatchOperation {
+    let path = path.clone();
+    if path.resolve(document).is_ok() {
+        PatchOperation::Replace(ReplaceOperation { path, value })
+    } else {
+        PatchOperation::Add(AddOperation { path, value })
+    }
+}
+
+/// Integer addition while both are integers, floating point otherwise.
+fn add_numbers(a: &Number, b: &Number) -> Option<Number> {
+    match (a.as_i64(), b.as_i64()) {
+        (Some(a), Some(b)) => a.checked_add(b).map(Number::from),
+        _ => Number::from_f64(a.as_f64()? + b.as_f64()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ops(ops: Value) -> Vec<JsonDocumentOp> {
+        serde_json::from_value(ops).unwrap()
+    }
+
+    #[test]
+    fn ops_apply_as_one_patch() {
+        let mut storage = JsonDocumentStorage::new(json!({ "items": ["a", "c"], "votes": 1 }));
+        let patch = storage
+            .apply_ops(&ops(json!([
+                { "op": "insert", "path": "/items", "index": 1, "value": "b" },
+                { "op": "insert", "path": "/items", "value": "d" },
+                { "op": "set", "path": "/title", "value": "Agenda" },
+                { "op": "set", "path": "/items/0", "value": "A" },
+                { "op": "increment", "path": "/votes", "by": 2 },
+                { "op": "increment", "path": "/score", "by": 0.5 },
+                { "op": "move", "from": "/items/3", "path": "/last" },
+                { "op": "remove", "path": "/items/2" },
+            ])))
+            .unwrap();
+
+        let expected = json!({
+            "items": ["A", "b"],
+            "votes": 3,
+            "score": 0.5,
+            "title": "Agenda",
+            "last": "d",
+        });
+        assert_eq!(storage.document(), &expected);
+
+        // Other clients get there from the broadcast patch alone
+        let mut replica = JsonDocumentStorage::new(json!({ "items": ["a", "c"], "votes": 1 }));
+        replica.apply_diff(patch.clone()).unwrap();
+        assert_eq!(replica, storage);
+        assert_eq!(
+            serde_json::to_value(&patch.0[4]).unwrap(),
+            json!({ "op": "replace", "path": "/votes", "value": 3 })
+        );
+    }
+
+    #[test]
+    fn failing_ops_leave_the_document_untouched() {
+        let mut storage = JsonDocumentStorage::new(json!({ "items": [], "name": "x" }));
+        for bad in [
+            json!([{ "op": "insert", "path": "/name", "value": 1 }]),
+            json!([{ "op": "insert", "path": "/items", "index": 1, "value": 1 }]),
+            json!([{ "op": "increment", "path": "/name", "by": 1 }]),
+            json!([
+                { "op": "set", "path": "/name", "value": "y" },
+                { "op": "remove", "path": "/missing" },
+            ]),
+        ] {
+            assert!(storage.apply_ops(&ops(bad)).is_err());
+        }
+        assert_eq!(storage.document(), &json!({ "items": [], "name": "x" }));
+
+        assert!(
+            serde_json::from_value::<JsonDocumentOp>(
+                json!({ "op": "remove", "path": "no-leading-slash" })
+            )
+            .is_err()
+        );
+    }
+}
@@ -1,16 +1,25 @@
+pub mod archive;
+pub mod crdt;
+pub mod history;
+pub mod json_document;
+pub mod persistence;
 pub mod presence;
+pub mod presence_sweeper;
+pub mod recording;
 pub mod room_manager;
+pub mod schema;
 pub mod storage;
+#[cfg(test)]
+pub mod test_support;
 pub mod transaction;
 use std::collections::HashMap;
 
-use crate::message::{ClientMessageTypeLike, Message, ServerMessageTypeLike};
-use crate::message_broker::MessageBroker;
-use crate::presentation::Presentation;
+use crate::message::{ClientMessageTypeLike, Message, ServerMessageTypeLike, UserInfo};
 use chrono::{DateTime, Utc};
 use client_id::ClientId;
 use presence::PresenceLike;
 use room_id::RoomId;
+use schema::StorageSchema;
 use serde::{Deserialize, Serialize};
 use storage::{StorageError, StorageLike};
 use ts_rs::TS;
@@ -39,6 +48,50 @@ pub enum RoomError {
     PersistenceError(String),
     #[error("Room not foind: {0}")]
     RoomNotFound(RoomId), // Add other specific room errors
+    #[error("Room already exists: {0}")]
+    RoomAlreadyExists(RoomId),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Message changes storage but has no base version")]
+    MissingBaseVersion,
+    #[error("Stale base version {base}; storage is at version {current}")]
+    StaleVersion { base: u64, current: u64 },
+}
+
+/// Descriptive information about a room that isn't part of its collaborative storage.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RoomMetadata {
+    pub name: String,
+    pub organisation_id: Option<String>,
+    pub project_id: Option<String>,
+}
+
+/// A serializable view of a room, used by the HTTP API.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RoomSnapshot {
+    pub room_id: RoomId,
+    pub room_type: String,
+    pub metadata: RoomMetadata,
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: DateTime<Utc>,
+    pub connected_clients: Vec<ClientId>,
+    pub storage: serde_json::Value,
+}
+
+impl RoomSnapshot {
+    pub fn from_room<R: RoomLike>(room: &R) -> Result<Self, StorageError> {
+        Ok(Self {
+            room_id: room.id().clone(),
+            room_type: room.room_type().to_string(),
+            metadata: room.metadata().clone(),
+            created_at: room.created_at(),
+            last_activity_at: room.last_activity_at(),
+            connected_clients: room.get_connected_clients(),
+            storage: room.storage().snapshot()?,
+        })
+    }
 }
 
 /// Describes the outcome of processing a client message (transaction).
@@ -47,12 +100,12 @@ pub enum RoomError {
 pub enum TransactionOutcome<ServerMsg: ServerMessageTypeLike, StorageDiff> {
     /// No action needed, or handled internally (e.g., direct response to sender).
     None,
-    /// Broadcast a message to all clients (optionally excluding the sender).
+    /// Broadcast a message to all clients in the room (optionally excluding the sender).
     Broadcast {
         message: Message<ServerMsg>,
         exclude_sender: bool,
     },
-    /// Broadcast a storage diff/update to all clients (optionally excluding sender).
+    /// Broadcast a storage diff/update to all clients in the room (optionally excluding sender).
     /// The exact content depends on the StorageLike implementation.
     BroadcastStorageUpdate {
         diff: StorageDiff,
@@ -63,7 +116,14 @@ pub enum TransactionOutcome<ServerMsg: ServerMessageTypeLike, StorageDiff> {
         clients: Vec<ClientId>,
         message: Message<ServerMsg>,
     },
-    /// Multiple actions required.
+    /// A client's presence changed. Presence is high-frequency, so the room manager
+    /// coalesces these per client and broadcasts them in batches; `presence` is the
+    /// client's latest state in network format.
+    PresenceChanged {
+        client_id: ClientId,
+        presence: serde_json::Value,
+    },
+    /// Multiple actions required. Executed in order; may be nested.
     Multiple(Vec<TransactionOutcome<ServerMsg, StorageDiff>>),
 }
 
@@ -74,14 +134,24 @@ pub trait RoomLike: Send + Sync + 'static {
     type Presence: PresenceLike;
     type ClientMessageType: ClientMessageTypeLike;
     type ServerMessageType: ServerMessageTypeLike;
-    type ClientMetadata: Send + Sync + Clone + Debug + 'static; // Data per connection
+    type ClientMetadata: Serialize
+        + for<'de> Deserialize<'de>
+        + Send
+        + Sync
+        + Clone
+        + Debug
+        + 'static; // Data per connection
+
+    /// Creates a fresh room instance with the given initial storage.
+    fn create(id: RoomId, metadata: RoomMetadata, storage: Self::Storage) -> Self
+    where
+        Self: Sized;
 
     // --- Basic Properties ---
     fn id(&self) -> &RoomId;
     fn room_type(&self) -> &'static str;
-    // Optional context IDs
-    // fn organisation_id(&self) -> Option<&str>;
-    // fn project_id(&self) -> Option<&str>;
+    /// Name and optional organisation/project context of the room.
+    fn metadata(&self) -> &RoomMetadata;
 
     // --- Core State Access ---
 
@@ -93,6 +163,33 @@ pub trait RoomLike: Send + Sync + 'static {
     /// Primarily intended for internal use (e.g., loading state, applying CRDT merges).
     fn storage_mut(&mut self) -> &mut Self::Storage;
 
+    /// JSON Schema the storage has to match after every change, if the room declares
+    /// one. The room manager checks it after each client message, rolling back those
+    /// that break it, and whenever storage is created or replaced wholesale.
+    fn storage_schema(&self) -> Option<&StorageSchema> {
+        None
+    }
+
+    /// State the room keeps from clients rather than sharing it through storage, such
+    /// as a presentation's poll answers, but that is persisted and exported along with
+    /// it. `None` if there is nothing to keep.
+    fn private_state(&self) -> Result<Option<serde_json::Value>, RoomError> {
+        Ok(None)
+    }
+
+    /// Brings back what [`RoomLike::private_state`] returned, once the room has been
+    /// rebuilt from its storage.
+    fn restore_private_state(&mut self, state: serde_json::Value) -> Result<(), RoomError> {
+        let _ = state;
+        Ok(())
+    }
+
+    /// Increases whenever the private state changes, which doesn't bump the storage
+    /// version, so the room manager knows the room needs saving.
+    fn private_state_revision(&self) -> u64 {
+        0
+    }
+
     /// Gets the presence data for a specific client.
     fn get_presence(&self, client_id: &ClientId) -> Option<&Self::Presence>;
 
@@ -105,6 +202,16 @@ pub trait RoomLike: Send + Sync + 'static {
     /// Gets IDs of all currently connected clients.
     fn get_connected_clients(&self) -> Vec<ClientId>;
 
+    /// Who a connected client is, as announced to the rest of the room when it joins.
+    fn get_user_info(&self, client_id: &ClientId) -> Option<UserInfo>;
+
+    /// Version of the room's storage. Starts at 0 and increases with every change.
+    fn version(&self) -> u64;
+
+    /// Sets the storage version, e.g. when restoring a persisted room so its versions
+    /// carry on where they left off.
+    fn set_version(&mut self, version: u64);
+
     // --- Lifecycle and Client Management ---
 
     /// Called when a client successfully connects and joins this room instance.
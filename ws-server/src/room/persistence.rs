@@ -0,0 +1,427 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use super::{
+    RoomError, RoomLike, RoomMetadata,
+    history::{HISTORY_TABLE, HistoryChange, HistoryEntry},
+    room_id::RoomId,
+    storage::StorageLike,
+};
+use crate::database::{Database, UpsertCondition, surrealdb::SurrealDatabase};
+
+/// Table rooms are persisted to.
+pub const ROOMS_TABLE: &str = "rooms";
+
+/// Everything needed to bring a room back after it was unloaded or the server restarted.
+/// Clients and presence are deliberately left out; they come back when clients rejoin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedRoom {
+    pub room_id: RoomId,
+    pub room_type: String,
+    pub metadata: RoomMetadata,
+    pub version: u64,
+    /// The storage's [`StorageLike::snapshot`].
+    pub storage: Value,
+    /// The room's [`RoomLike::private_state`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_state: Option<Value>,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl PersistedRoom {
+    pub fn from_room<R: RoomLike>(room: &R) -> Result<Self, RoomError> {
+        Ok(Self {
+            room_id: room.id().clone(),
+            room_type: room.room_type().to_string(),
+            metadata: room.metadata().clone(),
+            version: room.version(),
+            storage: room.storage().snapshot()?,
+            private_state: room.private_state()?,
+            saved_at: Utc::now(),
+        })
+    }
+
+    /// Rebuilds the room through [`RoomLike::create`] and [`StorageLike::from_snapshot`].
+    pub fn into_room<R: RoomLike>(self) -> Result<R, RoomError> {
+        let storage = R::Storage::from_snapshot(self.storage)?;
+        let mut room = R::create(self.room_id, self.metadata, storage);
+        if room.room_type() != self.room_type {
+            return Err(RoomError::PersistenceError(format!(
+                "Persisted room {} is a {}, not a {}",
+                room.id(),
+                self.room_type,
+                room.room_type()
+            )));
+        }
+        room.set_version(self.version);
+        if let Some(state) = self.private_state {
+            room.restore_private_state(state)?;
+        }
+        Ok(room)
+    }
+}
+
+/// Where the [`RoomManager`](super::room_manager::RoomManager) keeps rooms between loads.
+#[async_trait]
+pub trait RoomStore: Send + Sync + 'static {
+    async fn load(&self, room_id: &RoomId) -> Result<Option<PersistedRoom>, RoomError>;
+
+    /// Every stored room.
+    async fn list(&self) -> Result<Vec<PersistedRoom>, RoomError>;
+
+    /// Creates or replaces the stored room.
+    async fn save(&self, room: PersistedRoom) -> Result<(), RoomError>;
+
+    /// Deletes the stored room along with its history.
+    async fn delete(&self, room_id: &RoomId) -> Result<(), RoomError>;
+
+    /// Appends an entry to the room's history, replacing any entry for the same version.
+    async fn append_history(&self, entry: HistoryEntry) -> Result<(), RoomError>;
+
+    /// A page of the room's history, oldest first.
+    async fn list_history(
+        &self,
+        room_id: &RoomId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, RoomError>;
+
+    /// The entries needed to rebuild the storage at `version`, oldest first: the latest
+    /// snapshot at or before it, then everything after that snapshot up to `version`.
+    async fn history_until(
+        &self,
+        room_id: &RoomId,
+        version: u64,
+    ) -> Result<Vec<HistoryEntry>, RoomError>;
+
+    /// The latest version recorded at or before `at`.
+    async fn version_at(
+        &self,
+        room_id: &RoomId,
+        at: DateTime<Utc>,
+    ) -> Result<Option<u64>, RoomError>;
+}
+
+fn persistence_error(err: impl std::fmt::Display) -> RoomError {
+    RoomError::PersistenceError(err.to_string())
+}
+
+fn record_id(room_id: &RoomId) -> (String, String) {
+    (ROOMS_TABLE.to_string(), room_id.to_string())
+}
+
+fn history_record_id(entry: &HistoryEntry) -> (String, String) {
+    (
+        HISTORY_TABLE.to_string(),
+        format!("{}_{}", entry.room_id, entry.version),
+    )
+}
+
+#[async_trait]
+impl RoomStore for SurrealDatabase {
+    async fn load(&self, room_id: &RoomId) -> Result<Option<PersistedRoom>, RoomError> {
+        self.get(record_id(room_id))
+            .await
+            .map_err(persistence_error)
+    }
+
+    async fn list(&self) -> Result<Vec<PersistedRoom>, RoomError> {
+        Database::list(self, ROOMS_TABLE.to_string())
+            .await
+            .map_err(persistence_error)
+    }
+
+    async fn save(&self, room: PersistedRoom) -> Result<(), RoomError> {
+        self.upsert(record_id(&room.room_id), room, Some(UpsertCondition::ById))
+            .await
+            .map(|_| ())
+            .map_err(persistence_error)
+    }
+
+    async fn delete(&self, room_id: &RoomId) -> Result<(), RoomError> {
+        Database::delete::<PersistedRoom>(self, record_id(room_id))
+            .await
+            .map_err(persistence_error)?;
+        self.query::<Value>(
+            "DELETE type::table($table) WHERE room_id = $room_id",
+            Some(json!({ "table": HISTORY_TABLE, "room_id": room_id })),
+        )
+        .await
+        .map(|_| ())
+        .map_err(persistence_error)
+    }
+
+    async fn append_history(&self, entry: HistoryEntry) -> Result<(), RoomError> {
+        self.upsert(
+            history_record_id(&entry),
+            entry,
+            Some(UpsertCondition::ById),
+        )
+        .await
+        .map(|_| ())
+        .map_err(persistence_error)
+    }
+
+    async fn list_history(
+        &self,
+        room_id: &RoomId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, RoomError> {
+        self.query(
+            "SELECT * FROM type::table($table) WHERE room_id = $room_id \
+             ORDER BY version LIMIT $limit START $offset",
+            Some(json!({
+                "table": HISTORY_TABLE,
+                "room_id": room_id,
+                "limit": limit,
+                "offset": offset,
+            })),
+        )
+        .await
+        .map_err(persistence_error)
+    }
+
+    async fn history_until(
+        &self,
+        room_id: &RoomId,
+        version: u64,
+    ) -> Result<Vec<HistoryEntry>, RoomError> {
+        let snapshots: Vec<u64> = self
+            .query(
+                "SELECT VALUE version FROM type::table($table) \
+                 WHERE room_id = $room_id AND change.kind = 'snapshot' AND version <= $version \
+                 ORDER BY version DESC LIMIT 1",
+                Some(json!({ "table": HISTORY_TABLE, "room_id": room_id, "version": version })),
+            )
+            .await
+            .map_err(persistence_error)?;
+        let Some(from) = snapshots.first() else {
+            return Ok(Vec::new());
+        };
+
+        self.query(
+            "SELECT * FROM type::table($table) \
+             WHERE room_id = $room_id AND version >= $from AND version <= $version \
+             ORDER BY version",
+            Some(json!({
+                "table": HISTORY_TABLE,
+                "room_id": room_id,
+                "from": from,
+                "version": version,
+            })),
+        )
+        .await
+        .map_err(persistence_error)
+    }
+
+    async fn version_at(
+        &self,
+        room_id: &RoomId,
+        at: DateTime<Utc>,
+    ) -> Result<Option<u64>, RoomError> {
+        let versions: Vec<u64> = self
+            .query(
+                "SELECT VALUE version FROM type::table($table) \
+                 WHERE room_id = $room_id AND <datetime> timestamp <= <datetime> $at \
+                 ORDER BY version DESC LIMIT 1",
+                Some(json!({ "table": HISTORY_TABLE, "room_id": room_id, "at": at })),
+            )
+            .await
+            .map_err(persistence_error)?;
+        Ok(versions.first().copied())
+    }
+}
+
+/// Keeps persisted rooms in memory. Clones share the same rooms, so tests can hand
+/// one to several managers to simulate a restart.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRoomStore {
+    rooms: Arc<std::sync::Mutex<HashMap<RoomId, PersistedRoom>>>,
+    history: Arc<std::sync::Mutex<HashMap<RoomId, BTreeMap<u64, HistoryEntry>>>>,
+}
+
+impl InMemoryRoomStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The stored copy of a room, without going through the async trait.
+    pub fn get(&self, room_id: &RoomId) -> Option<PersistedRoom> {
+        self.rooms().get(room_id).cloned()
+    }
+
+    fn rooms(&self) -> std::sync::MutexGuard<'_, HashMap<RoomId, PersistedRoom>> {
+        self.rooms.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn history(&self) -> std::sync::MutexGuard<'_, HashMap<RoomId, BTreeMap<u64, HistoryEntry>>> {
+        self.history.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl RoomStore for InMemoryRoomStore {
+    async fn load(&self, room_id: &RoomId) -> Result<Option<PersistedRoom>, RoomError> {
+        Ok(self.get(room_id))
+    }
+
+    async fn list(&self) -> Result<Vec<PersistedRoom>, RoomError> {
+        Ok(self.rooms().values().cloned().collect())
+    }
+
+    async fn save(&self, room: PersistedRoom) -> Result<(), RoomError> {
+        self.rooms().insert(room.room_id.clone(), room);
+        Ok(())
+    }
+
+    async fn delete(&self, room_id: &RoomId) -> Result<(), RoomError> {
+        self.rooms().remove(room_id);
+        self.history().remove(room_id);
+        Ok(())
+    }
+
+    async fn append_history(&self, entry: HistoryEntry) -> Result<(), RoomError> {
+        self.history()
+            .entry(entry.room_id.clone())
+            .or_default()
+            .insert(entry.version, entry);
+        Ok(())
+    }
+
+    async fn list_history(
+        &self,
+        room_id: &RoomId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, RoomError> {
+        Ok(self
+            .history()
+            .get(room_id)
+            .map(|entries| entries.values().skip(offset).take(limit).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn history_until(
+        &self,
+        room_id: &RoomId,
+        version: u64,
+    ) -> Result<Vec<HistoryEntry>, RoomError> {
+        let history = self.history();
+        let Some(entries) = history.get(room_id) else {
+            return Ok(Vec::new());
+        };
+        let from = entries
+            .range(..=version)
+            .rev()
+            .find(|(_, entry)| matches!(entry.change, HistoryChange::Snapshot { .. }))
+            .map(|(version, _)| *version);
+
+        Ok(from
+            .map(|from| {
+                entries
+                    .range(from..=version)
+                    .map(|(_, e)| e.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn version_at(
+        &self,
+        room_id: &RoomId,
+        at: DateTime<Utc>,
+    ) -> Result<Option<u64>, RoomError> {
+        Ok(self.history().get(room_id).and_then(|entries| {
+            entries
+                .values()
+                .filter(|entry| entry.timestamp <= at)
+                .map(|entry| entry.version)
+                .max()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_broker::memory::InMemoryMessageBroker,
+        presentation::PresentationClientMessage,
+        room::{
+            room_manager::RoomManager,
+            test_support::{TestManager, create_presentation, join_as_presenter, send_all},
+        },
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn rooms_are_saved_and_reloaded_on_join() {
+        let store = InMemoryRoomStore::new();
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let manager = RoomManager::new(broker.clone())
+            .with_store(store.clone())
+            .with_save_delay(Duration::from_millis(20));
+        let room_id = create_presentation(&manager, "room_saved", vec![json!({}); 2]).await;
+
+        // Saved in the background once changes settle
+        join_as_presenter(&manager, &room_id, "alice").await;
+        let change_slide = |slide_index| [PresentationClientMessage::ChangeSlide { slide_index }];
+        send_all(&manager, &room_id, "alice", change_slide(1)).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let saved = store.get(&room_id).unwrap();
+        assert_eq!(
+            (saved.version, saved.storage["current_slide"].clone()),
+            (1, json!(1))
+        );
+
+        // A fresh manager over the same store stands in for a restart
+        let broker = InMemoryMessageBroker::new();
+        let _bob = broker.connect("bob");
+        let restarted = RoomManager::new(broker.clone()).with_store(store.clone());
+        assert!(!restarted.contains_room(&room_id).await);
+
+        join_as_presenter(&restarted, &room_id, "bob").await;
+        send_all(&restarted, &room_id, "bob", change_slide(0)).await;
+        let version = restarted.with_room(&room_id, |room| room.version()).await;
+        assert_eq!(version.unwrap(), 2);
+
+        // Saved right away once the last client leaves, without waiting for the delay
+        restarted
+            .leave_room(&room_id, &"bob".to_string())
+            .await
+            .unwrap();
+        let saved = store.get(&room_id).unwrap();
+        assert_eq!(
+            (saved.version, saved.storage["current_slide"].clone()),
+            (2, json!(0))
+        );
+
+        // Rooms that are only persisted are listed, read and removed like live ones
+        let restarted = TestManager::new(broker.clone()).with_store(store.clone());
+        let listed: Vec<RoomId> = restarted
+            .room_snapshots()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|snapshot| snapshot.room_id)
+            .collect();
+        assert_eq!(listed, vec![room_id.clone()]);
+        assert!(!restarted.contains_room(&room_id).await);
+        let version = restarted.with_room(&room_id, |room| room.version()).await;
+        assert_eq!(version.unwrap(), 2);
+
+        let restarted = TestManager::new(broker).with_store(store.clone());
+        assert!(restarted.remove_room(&room_id).await.unwrap());
+        assert!(store.get(&room_id).is_none());
+        assert!(!restarted.remove_room(&room_id).await.unwrap());
+    }
+}
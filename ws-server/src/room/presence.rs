@@ -1,8 +1,7 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::fmt::Debug;
 use thiserror::Error;
-use ts_rs::TS;
 
 #[derive(Error, Debug)]
 pub enum PresenceError {
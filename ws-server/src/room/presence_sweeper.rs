@@ -0,0 +1,215 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use ts_rs::TS;
+
+use super::{
+    RoomLike, client_id::ClientId, presence::PresenceLike, room_id::RoomId,
+    room_manager::RoomManager,
+};
+use crate::{
+    message::{Message, ServerMessageType},
+    message_broker::{MESSAGE_EVENT, MessageBroker},
+};
+
+/// How recently a client's presence changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum PresenceStatus {
+    #[default]
+    Active,
+    Idle,
+    Away,
+}
+
+#[derive(Debug, Clone)]
+pub struct PresenceSweepConfig {
+    /// Time between sweeps.
+    pub interval: Duration,
+    /// Inactivity after which a client is idle.
+    pub idle_after: Duration,
+    /// Inactivity after which a client is away.
+    pub away_after: Duration,
+}
+
+impl Default for PresenceSweepConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            idle_after: Duration::from_secs(60),
+            away_after: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl PresenceSweepConfig {
+    pub fn status_at(&self, last_updated: DateTime<Utc>, now: DateTime<Utc>) -> PresenceStatus {
+        let inactive = now
+            .signed_duration_since(last_updated)
+            .to_std()
+            .unwrap_or_default();
+
+        if inactive >= self.away_after {
+            PresenceStatus::Away
+        } else if inactive >= self.idle_after {
+            PresenceStatus::Idle
+        } else {
+            PresenceStatus::Active
+        }
+    }
+}
+
+/// Periodically checks every room's presences.
+///
+/// Clients whose connection is gone without a disconnect having been handled are
+/// removed from their rooms, and clients whose presence hasn't changed for a while are
+/// marked idle, then away. Status changes are broadcast to the room as
+/// [`ServerMessageType::PresenceStatusChanged`].
+pub struct PresenceSweeper {
+    config: PresenceSweepConfig,
+    /// Last status broadcast per client; clients not listed are active.
+    statuses: HashMap<RoomId, HashMap<ClientId, PresenceStatus>>,
+}
+
+impl PresenceSweeper {
+    pub fn new(config: PresenceSweepConfig) -> Self {
+        Self {
+            config,
+            statuses: HashMap::new(),
+        }
+    }
+
+    /// Runs a sweep every `interval` until the returned task is aborted.
+    pub fn spawn<B: MessageBroker, R: RoomLike>(
+        mut self,
+        manager: RoomManager<B, R>,
+    ) -> JoinHandle<()> {
+        info!(interval = ?self.config.interval, "Starting presence sweeper");
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                self.sweep(&manager).await;
+            }
+        })
+    }
+
+    /// Sweeps every live room once.
+    pub async fn sweep<B: MessageBroker, R: RoomLike>(&mut self, manager: &RoomManager<B, R>) {
+        let now = Utc::now();
+        let room_ids = manager.list_rooms().await;
+        self.statuses.retain(|room_id, _| room_ids.contains(room_id));
+
+        for room_id in room_ids {
+            let Some(room) = manager.get_room(&room_id).await else {
+                continue;
+            };
+            let known = self.statuses.entry(room_id.clone()).or_default();
+
+            let (vanished, changed) = {
+                let room = room.lock().await;
+                let clients = room.get_connected_clients();
+                known.retain(|client_id, _| clients.contains(client_id));
+
+                let mut vanished = Vec::new();
+                let mut changed = HashMap::new();
+                for client_id in clients {
+                    if !manager.broker().is_connected(&client_id) {
+                        vanished.push(client_id);
+                        continue;
+                    }
+                    let Some(presence) = room.get_presence(&client_id) else {
+                        continue;
+                    };
+
+                    let status = self.config.status_at(presence.last_updated(), now);
+                    let previous = known.insert(client_id.clone(), status).unwrap_or_default();
+                    if previous != status {
+                        changed.insert(client_id, status);
+                    }
+                }
+                (vanished, changed)
+            };
+
+            for client_id in vanished {
+                known.remove(&client_id);
+                debug!(room_id = %room_id, client_id = %client_id, "Evicting vanished client");
+                if let Err(err) = manager.leave_room(&room_id, &client_id).await {
+                    warn!(
+                        room_id = %room_id,
+                        client_id = %client_id,
+                        error = %err,
+                        "Failed to evict vanished client"
+                    );
+                }
+            }
+
+            if changed.is_empty() {
+                continue;
+            }
+            let message = Message {
+                room_id: room_id.clone(),
+                payload: ServerMessageType::PresenceStatusChanged {
+                    room_id: room_id.clone(),
+                    statuses: changed,
+                },
+                datetime: now,
+                sender_id: None,
+                request_id: None,
+                broadcast: Some(true),
+                base_version: None,
+            };
+            if let Err(err) = manager.broadcast(&room_id, MESSAGE_EVENT, &message).await {
+                warn!(room_id = %room_id, error = %err, "Failed to broadcast presence status");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_broker::memory::InMemoryMessageBroker,
+        room::test_support::presentation_with_clients,
+    };
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn sweeper_marks_idle_and_evicts_vanished_clients() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let _bob = broker.connect("bob");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice", "bob"]).await;
+        // Bob's connection drops without the disconnect ever reaching the room
+        broker.disconnect(&"bob".to_string());
+
+        let mut sweeper = PresenceSweeper::new(PresenceSweepConfig {
+            interval: Duration::from_secs(1),
+            idle_after: Duration::ZERO,
+            away_after: Duration::from_secs(3600),
+        });
+        sweeper.sweep(&manager).await;
+
+        let clients = manager
+            .with_room(&room_id, |room| room.get_connected_clients())
+            .await
+            .unwrap();
+        assert_eq!(clients, vec!["alice".to_string()]);
+
+        let statuses: Vec<serde_json::Value> = broker
+            .take_deliveries()
+            .into_iter()
+            .filter_map(|d| d.payload["payload"].get("statuses").cloned())
+            .collect();
+        assert_eq!(statuses, vec![json!({ "alice": "Idle" })]);
+
+        // Only transitions are broadcast
+        sweeper.sweep(&manager).await;
+        assert!(broker.take_deliveries().is_empty());
+    }
+}
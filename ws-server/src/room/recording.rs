@@ -0,0 +1,372 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+use ts_rs::TS;
+
+use super::{RoomError, RoomLike, RoomMetadata, room_id::RoomId, storage::StorageLike};
+use crate::message::msgpack;
+
+/// How many events a recording takes before it stops growing, unless configured.
+pub const DEFAULT_RECORDING_CAPACITY: usize = 100_000;
+
+/// The room as it was when recording started; the first record of every recording.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RecordingHeader {
+    pub room_id: RoomId,
+    pub room_type: String,
+    pub metadata: RoomMetadata,
+    pub started_at: DateTime<Utc>,
+    pub version: u64,
+    #[ts(type = "unknown")]
+    pub storage: Value,
+    /// When the recording filled up; nothing after that was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub truncated_at: Option<DateTime<Utc>>,
+}
+
+/// Something broadcast to the whole room, under the event name clients received it on.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RecordedEvent {
+    /// The broadcast message's `datetime`, or when it was recorded if it has none.
+    pub datetime: DateTime<Utc>,
+    pub event: String,
+    #[ts(type = "unknown")]
+    pub payload: Value,
+}
+
+/// A room session: its starting state and everything broadcast to it afterwards,
+/// i.e. joins, leaves, presence, messages and storage updates.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub header: RecordingHeader,
+    pub events: Vec<RecordedEvent>,
+}
+
+/// File format of an encoded [`Recording`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// JSON Lines: the header, then one event per line.
+    #[default]
+    Jsonl,
+    /// The header, then each event, as consecutive MessagePack values.
+    MsgPack,
+}
+
+impl RecordingFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jsonl => "application/x-ndjson",
+            Self::MsgPack => "application/msgpack",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::MsgPack => "msgpack",
+        }
+    }
+}
+
+impl Recording {
+    /// Starts an empty recording of `room` as it is now.
+    pub fn start<R: RoomLike>(room: &R) -> Result<Self, RoomError> {
+        Ok(Self {
+            header: RecordingHeader {
+                room_id: room.id().clone(),
+                room_type: room.room_type().to_string(),
+                metadata: room.metadata().clone(),
+                started_at: Utc::now(),
+                version: room.version(),
+                storage: room.storage().snapshot()?,
+                truncated_at: None,
+            },
+            events: Vec::new(),
+        })
+    }
+
+    /// How far into the recording `event` happened.
+    pub fn offset(&self, event: &RecordedEvent) -> Duration {
+        (event.datetime - self.header.started_at)
+            .to_std()
+            .unwrap_or_default()
+    }
+
+    pub fn encode(&self, format: RecordingFormat) -> Result<Vec<u8>, RoomError> {
+        let records = std::iter::once(serde_json::to_value(&self.header))
+            .chain(self.events.iter().map(serde_json::to_value));
+
+        let mut buf = Vec::new();
+        for record in records {
+            match format {
+                RecordingFormat::Jsonl => {
+                    serde_json::to_writer(&mut buf, &record?)?;
+                    buf.push(b'\n');
+                }
+                RecordingFormat::MsgPack => {
+                    msgpack::write(&mut buf, record?)
+                        .map_err(|e| RoomError::PersistenceError(e.to_string()))?;
+                }
+            }
+        }
+        Ok(buf)
+    }
+
+    pub fn decode(bytes: &[u8], format: RecordingFormat) -> Result<Self, RoomError> {
+        let records = match format {
+            RecordingFormat::Jsonl => bytes
+                .split(|byte| *byte == b'\n')
+                .filter(|line| !line.trim_ascii().is_empty())
+                .map(|line| serde_json::from_slice(line).map_err(invalid_recording))
+                .collect::<Result<Vec<Value>, _>>()?,
+            RecordingFormat::MsgPack => {
+                let mut rest = bytes;
+                let mut records = Vec::new();
+                while !rest.is_empty() {
+                    records.push(msgpack::read(&mut rest).map_err(invalid_recording)?);
+                }
+                records
+            }
+        };
+
+        let mut records = records.into_iter();
+        let header = records
+            .next()
+            .ok_or_else(|| invalid_recording("it is empty"))?;
+        Ok(Self {
+            header: serde_json::from_value(header).map_err(invalid_recording)?,
+            events: records
+                .map(serde_json::from_value)
+                .collect::<Result<_, _>>()
+                .map_err(invalid_recording)?,
+        })
+    }
+}
+
+fn invalid_recording(err: impl std::fmt::Display) -> RoomError {
+    RoomError::TransactionError(format!("Invalid recording: {err}"))
+}
+
+/// Points every `room_id` in a recorded payload at the room it is replayed into.
+pub fn retarget(payload: &mut Value, from: &RoomId, to: &RoomId) {
+    match payload {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if key == "room_id" && value.as_str() == Some(from.as_str()) {
+                    *value = Value::String(to.to_string());
+                } else {
+                    retarget(value, from, to);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| retarget(item, from, to)),
+        _ => {}
+    }
+}
+
+/// Recordings in progress, per room. Clones share the same recordings.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    recordings: Arc<std::sync::Mutex<HashMap<RoomId, Recording>>>,
+    capacity: usize,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECORDING_CAPACITY)
+    }
+}
+
+impl Recorder {
+    /// A recorder whose recordings take at most `capacity` events each. A full
+    /// recording keeps what it has and notes when it filled up in its header.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            recordings: Arc::default(),
+            capacity,
+        }
+    }
+
+    pub fn start(&self, recording: Recording) -> Result<(), RoomError> {
+        let room_id = recording.header.room_id.clone();
+        let mut recordings = self.recordings();
+        if recordings.contains_key(&room_id) {
+            return Err(RoomError::TransactionError(format!(
+                "Room {room_id} is already being recorded"
+            )));
+        }
+        recordings.insert(room_id, recording);
+        Ok(())
+    }
+
+    pub fn stop(&self, room_id: &RoomId) -> Option<Recording> {
+        self.recordings().remove(room_id)
+    }
+
+    /// Appends a broadcast to the room's recording, if it is being recorded.
+    pub fn record<P: Serialize>(&self, room_id: &RoomId, event: &str, payload: &P) {
+        let mut recordings = self.recordings();
+        let Some(recording) = recordings.get_mut(room_id) else {
+            return;
+        };
+        if recording.events.len() >= self.capacity {
+            if recording.header.truncated_at.is_none() {
+                warn!(room_id = %room_id, events = recording.events.len(), "Recording is full");
+                recording.header.truncated_at = Some(Utc::now());
+            }
+            return;
+        }
+
+        match serde_json::to_value(payload) {
+            Ok(payload) => {
+                let datetime = payload
+                    .get("datetime")
+                    .and_then(|datetime| serde_json::from_value(datetime.clone()).ok())
+                    .unwrap_or_else(Utc::now);
+                recording.events.push(RecordedEvent {
+                    datetime,
+                    event: event.to_string(),
+                    payload,
+                });
+            }
+            Err(err) => warn!(room_id = %room_id, event, error = %err, "Failed to record event"),
+        }
+    }
+
+    fn recordings(&self) -> std::sync::MutexGuard<'_, HashMap<RoomId, Recording>> {
+        self.recordings.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{Message, StorageUpdate},
+        message_broker::{STORAGE_EVENT, memory::InMemoryMessageBroker},
+        presentation::{Presentation, PresentationClientMessage, PresentationStorage},
+        room::{
+            history::HistoryPoint,
+            test_support::{client_data, client_message, presentation_with_clients},
+        },
+    };
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn recorded_sessions_replay_into_read_only_rooms() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let (manager, room_id) = presentation_with_clients(&broker, &[]).await;
+        let alice = "alice".to_string();
+
+        manager.start_recording(&room_id).await.unwrap();
+        manager
+            .join_room(&room_id, alice.clone(), client_data("alice"))
+            .await
+            .unwrap();
+        for payload in [
+            PresentationClientMessage::ClaimPresenter,
+            PresentationClientMessage::ChangeSlide { slide_index: 2 },
+        ] {
+            // Leaves the replay time to get a watcher in before the slide changes
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            manager
+                .handle_client_message(&room_id, &alice, client_message(&room_id, payload))
+                .await
+                .unwrap();
+        }
+        let recording = manager.stop_recording(&room_id).unwrap();
+        assert!(manager.stop_recording(&room_id).is_err());
+        assert_eq!(recording.header.version, 0);
+        assert!(
+            recording
+                .events
+                .iter()
+                .any(|event| event.event == STORAGE_EVENT)
+        );
+
+        // Both formats carry the whole session
+        for format in [RecordingFormat::Jsonl, RecordingFormat::MsgPack] {
+            let decoded = Recording::decode(&recording.encode(format).unwrap(), format).unwrap();
+            assert_eq!(decoded.events.len(), recording.events.len());
+            assert_eq!(decoded.header.started_at, recording.header.started_at);
+        }
+
+        let _bob = broker.connect("bob");
+        let replay = manager.replay(recording, 2.0).await.unwrap();
+        let bob = "bob".to_string();
+        manager
+            .join_room(&replay, bob.clone(), client_data("bob"))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let current_slide = manager
+            .with_room(&replay, |room| room.storage().snapshot().unwrap())
+            .await
+            .unwrap()["current_slide"]
+            .clone();
+        assert_eq!(current_slide, json!(2));
+        let update: Message<StorageUpdate<serde_json::Value>> = broker
+            .deliveries_to(&bob)
+            .iter()
+            .find(|delivery| delivery.msg_name == STORAGE_EVENT)
+            .unwrap()
+            .decode()
+            .unwrap();
+        assert_eq!(update.room_id, replay);
+
+        // Watchers can't change a replay
+        let change = PresentationClientMessage::ChangeSlide { slide_index: 0 };
+        assert!(matches!(
+            manager
+                .handle_client_message(&replay, &bob, client_message(&replay, change))
+                .await,
+            Err(RoomError::PermissionDenied(_))
+        ));
+        // Nor can the API replace the room or its storage, or restore it
+        let storage = PresentationStorage::new(vec![json!({})]);
+        let room = Presentation::new(replay.clone(), RoomMetadata::default(), storage.clone());
+        assert!(matches!(
+            manager.insert_room(room).await,
+            Err(RoomError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            manager.replace_storage(&replay, storage).await,
+            Err(RoomError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            manager.restore(&replay, HistoryPoint::Version(0)).await,
+            Err(RoomError::PermissionDenied(_))
+        ));
+    }
+
+    #[test]
+    fn full_recordings_stop_taking_events() {
+        let storage = PresentationStorage::new(vec![json!({})]);
+        let room = Presentation::new(
+            RoomId::from_string("room_full"),
+            RoomMetadata::default(),
+            storage,
+        );
+        let recorder = Recorder::new(2);
+        recorder.start(Recording::start(&room).unwrap()).unwrap();
+        for n in 0..3 {
+            recorder.record(room.id(), "message", &json!({ "n": n }));
+        }
+
+        let recording = recorder.stop(room.id()).unwrap();
+        assert_eq!(recording.events.len(), 2);
+        assert!(recording.header.truncated_at.is_some());
+        let format = RecordingFormat::Jsonl;
+        let decoded = Recording::decode(&recording.encode(format).unwrap(), format).unwrap();
+        assert_eq!(decoded.header.truncated_at, recording.header.truncated_at);
+    }
+}
@@ -1,80 +1,1983 @@
-use super::{RoomError, RoomLike, TransactionOutcome, client_id::ClientId, room_id::RoomId};
-use crate::{message::Message, message_broker::MessageBroker};
-use std::{collections::HashMap, sync::Arc};
+use super::{
+    RoomError, RoomLike, RoomMetadata, RoomSnapshot, TransactionOutcome,
+    archive::RoomArchive,
+    client_id::ClientId,
+    history::{HistoryChange, HistoryEntry, HistoryPoint, rebuild_storage},
+    persistence::{PersistedRoom, RoomStore},
+    recording::{RecordedEvent, Recorder, Recording, retarget},
+    room_id::RoomId,
+    storage::StorageLike,
+};
+use crate::{
+    message::{
+        ClientMessageTypeLike, Message, PresenceUpdated, RoomState, ServerMessageType,
+        StorageCatchUp, StorageUpdate,
+    },
+    message_broker::{MESSAGE_EVENT, MessageBroker, PRESENCE_EVENT, STATE_EVENT, STORAGE_EVENT},
+};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{Mutex, OwnedMutexGuard, RwLock},
+    task::{AbortHandle, JoinHandle},
+    time::Instant,
+};
+use tracing::{debug, info, warn};
 
-#[derive(Clone)]
+/// A live room guarded by its own lock, so work on one room never blocks another.
+pub type SharedRoom<R> = Arc<Mutex<R>>;
+
+type StorageDiff<R> = <<R as RoomLike>::Storage as StorageLike>::Diff;
+
+type RoomOutcome<R> = TransactionOutcome<<R as RoomLike>::ServerMessageType, StorageDiff<R>>;
+
+/// Presence changes waiting to be broadcast, per room, latest state per client.
+type PendingPresence = HashMap<RoomId, HashMap<ClientId, Value>>;
+
+/// Recent storage updates per room, oldest first, with consecutive versions.
+type DiffLogs = HashMap<RoomId, VecDeque<StorageUpdate<Value>>>;
+
+/// How long presence changes are coalesced before being broadcast, unless configured.
+pub const DEFAULT_PRESENCE_WINDOW: Duration = Duration::from_millis(50);
+
+/// How many storage updates each room keeps for catching clients up, unless configured.
+pub const DEFAULT_DIFF_LOG_CAPACITY: usize = 256;
+
+/// How long a room has to go without changes before it is saved, unless configured.
+pub const DEFAULT_SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// How many history entries are read from the store at a time when exporting a room.
+const HISTORY_EXPORT_PAGE: usize = 500;
+
+/// A room that keeps changing is still saved once its oldest unsaved change is this
+/// many save delays old.
+const MAX_SAVE_DELAY_FACTOR: u32 = 10;
+
+/// When live rooms are unloaded from memory. Eviction only happens with a store
+/// (see [`RoomManager::with_store`]), since an unloaded room must be loadable again.
+#[derive(Debug, Clone)]
+pub struct EvictionConfig {
+    /// Time between checks for idle rooms.
+    pub interval: Duration,
+    /// How long a room has to be empty before it is unloaded.
+    pub empty_ttl: Duration,
+    /// Most rooms kept in memory. Beyond that, the least recently active empty rooms
+    /// are unloaded; rooms with clients are never unloaded.
+    pub max_rooms: Option<usize>,
+}
+
+impl Default for EvictionConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            empty_ttl: Duration::from_secs(5 * 60),
+            max_rooms: None,
+        }
+    }
+}
+
+/// When a room's oldest and newest unsaved changes happened.
+#[derive(Debug, Clone, Copy)]
+struct UnsavedChanges {
+    first: Instant,
+    last: Instant,
+}
+
+/// Who a single delivery attempt was addressed to.
+#[derive(Debug, Clone)]
+pub enum DeliveryTarget {
+    /// Every client in the room, minus the excluded ones.
+    Room { exclude: Vec<ClientId> },
+    /// A specific set of clients.
+    Clients(Vec<ClientId>),
+}
+
+/// A delivery that the broker failed to carry out.
+#[derive(Debug)]
+pub struct DeliveryFailure {
+    pub target: DeliveryTarget,
+    pub error: RoomError,
+}
+
+/// Summary of delivering a [`TransactionOutcome`] to clients.
+#[derive(Debug, Default)]
+pub struct DispatchReport {
+    /// Number of deliveries the broker accepted.
+    pub delivered: usize,
+    /// Deliveries that failed, in the order they were attempted.
+    pub failures: Vec<DeliveryFailure>,
+}
+
+impl DispatchReport {
+    /// `true` if every delivery succeeded (including when there was nothing to deliver).
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn record<E: Into<RoomError>>(&mut self, target: DeliveryTarget, result: Result<(), E>) {
+        match result {
+            Ok(()) => self.delivered += 1,
+            Err(error) => self.failures.push(DeliveryFailure {
+                target,
+                error: error.into(),
+            }),
+        }
+    }
+}
+
+/// Owns every live room on the server and routes client messages through them.
+///
+/// The room map itself is only locked long enough to look up or insert a room;
+/// all room work happens under the per-room [`Mutex`].
 pub struct RoomManager<B: MessageBroker, R: RoomLike> {
-    rooms: HashMap<RoomId, R>,
+    rooms: Arc<RwLock<HashMap<RoomId, SharedRoom<R>>>>,
     msg_broker: Arc<B>,
+    pending_presence: Arc<std::sync::Mutex<PendingPresence>>,
+    presence_window: Duration,
+    diff_logs: Arc<std::sync::Mutex<DiffLogs>>,
+    diff_log_capacity: usize,
+    store: Option<Arc<dyn RoomStore>>,
+    unsaved: Arc<std::sync::Mutex<HashMap<RoomId, UnsavedChanges>>>,
+    save_delay: Duration,
+    eviction: EvictionConfig,
+    recorder: Recorder,
+    /// Rooms replaying a recording, which clients can watch but not change.
+    replays: Arc<std::sync::Mutex<HashMap<RoomId, AbortHandle>>>,
 }
 
-impl<B: MessageBroker, R: RoomLike> RoomManager<B, R>
-where
-    RoomError: std::convert::From<<B as MessageBroker>::Error>,
-{
+impl<B: MessageBroker, R: RoomLike> Clone for RoomManager<B, R> {
+    fn clone(&self) -> Self {
+        Self {
+            rooms: Arc::clone(&self.rooms),
+            msg_broker: Arc::clone(&self.msg_broker),
+            pending_presence: Arc::clone(&self.pending_presence),
+            presence_window: self.presence_window,
+            diff_logs: Arc::clone(&self.diff_logs),
+            diff_log_capacity: self.diff_log_capacity,
+            store: self.store.clone(),
+            unsaved: Arc::clone(&self.unsaved),
+            save_delay: self.save_delay,
+            eviction: self.eviction.clone(),
+            recorder: self.recorder.clone(),
+            replays: Arc::clone(&self.replays),
+        }
+    }
+}
+
+impl<B: MessageBroker, R: RoomLike> RoomManager<B, R> {
     pub fn new(msg_broker: B) -> Self {
         Self {
-            rooms: HashMap::new(),
+            rooms: Arc::new(RwLock::new(HashMap::new())),
             msg_broker: Arc::new(msg_broker),
+            pending_presence: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            presence_window: DEFAULT_PRESENCE_WINDOW,
+            diff_logs: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            diff_log_capacity: DEFAULT_DIFF_LOG_CAPACITY,
+            store: None,
+            unsaved: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            save_delay: DEFAULT_SAVE_DELAY,
+            eviction: EvictionConfig::default(),
+            recorder: Recorder::default(),
+            replays: Arc::new(std::sync::Mutex::new(HashMap::new())),
         }
     }
 
-    fn add_room(&mut self, room_id: RoomId, room: R) {
-        self.rooms.insert(room_id, room);
+    /// Sets how long presence changes are coalesced before being broadcast.
+    /// A zero window broadcasts every change immediately.
+    #[must_use]
+    pub fn with_presence_window(mut self, window: Duration) -> Self {
+        self.presence_window = window;
+        self
+    }
+
+    /// Sets how many storage updates each room keeps for [`RoomManager::catch_up`].
+    /// Clients further behind than that get a full snapshot instead.
+    #[must_use]
+    pub fn with_diff_log_capacity(mut self, capacity: usize) -> Self {
+        self.diff_log_capacity = capacity;
+        self
     }
 
-    fn remove_room(&mut self, room_id: RoomId) {
-        self.rooms.remove(&room_id);
+    /// Sets how many events each recording takes; see [`Recorder::new`].
+    #[must_use]
+    pub fn with_recording_capacity(mut self, capacity: usize) -> Self {
+        self.recorder = Recorder::new(capacity);
+        self
     }
 
-    async fn handle_client_message(
-        &mut self,
+    /// Persists rooms to `store`: changed rooms are saved in the background once they
+    /// settle, and rooms that aren't live are loaded from it when a client joins.
+    #[must_use]
+    pub fn with_store(mut self, store: impl RoomStore) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Sets how long a room has to go without changes before it is saved.
+    #[must_use]
+    pub fn with_save_delay(mut self, delay: Duration) -> Self {
+        self.save_delay = delay;
+        self
+    }
+
+    /// Sets when rooms are unloaded from memory; see [`RoomManager::spawn_eviction`].
+    #[must_use]
+    pub fn with_eviction(mut self, config: EvictionConfig) -> Self {
+        self.eviction = config;
+        self
+    }
+
+    /// The broker used to deliver messages to clients.
+    pub fn broker(&self) -> &B {
+        self.msg_broker.as_ref()
+    }
+
+    /// Adds a new room, failing if a room with the same id is already live.
+    pub async fn create_room(&self, mut room: R) -> Result<SharedRoom<R>, RoomError> {
+        if let Some(schema) = room.storage_schema() {
+            schema.validate_storage(room.storage())?;
+        }
+        let room_id = room.id().clone();
+        self.continue_history(&mut room).await;
+        let version = room.version();
+        let snapshot = Self::snapshot_change(&room);
+
+        let room = {
+            let mut rooms = self.rooms.write().await;
+            if rooms.contains_key(&room_id) {
+                return Err(RoomError::RoomAlreadyExists(room_id));
+            }
+
+            let room = Arc::new(Mutex::new(room));
+            rooms.insert(room_id.clone(), Arc::clone(&room));
+            info!(room_id = %room_id, "Room created");
+            self.mark_unsaved(&room_id);
+            self.check_capacity(rooms.len());
+            room
+        };
+
+        self.record_history(&room_id, None, version, snapshot).await;
+        Ok(room)
+    }
+
+    /// Inserts a room, replacing any live room with the same id, unless that one is a
+    /// replay. Returns the replaced room, if there was one.
+    pub async fn insert_room(&self, mut room: R) -> Result<Option<SharedRoom<R>>, RoomError> {
+        self.ensure_writable(room.id())?;
+        if let Some(schema) = room.storage_schema() {
+            schema.validate_storage(room.storage())?;
+        }
+        let room_id = room.id().clone();
+        self.continue_history(&mut room).await;
+        let version = room.version();
+        let snapshot = Self::snapshot_change(&room);
+
+        let replaced = {
+            let mut rooms = self.rooms.write().await;
+            // The new room's versions have nothing to do with the old one's
+            self.diff_logs().remove(&room_id);
+            self.mark_unsaved(&room_id);
+            let replaced = rooms.insert(room_id.clone(), Arc::new(Mutex::new(room)));
+            self.check_capacity(rooms.len());
+            replaced
+        };
+
+        self.record_history(&room_id, None, version, snapshot).await;
+        Ok(replaced)
+    }
+
+    /// Returns a handle to a live room.
+    pub async fn get_room(&self, room_id: &RoomId) -> Option<SharedRoom<R>> {
+        self.rooms.read().await.get(room_id).cloned()
+    }
+
+    /// Removes a room from the manager and from the store. Returns whether there was
+    /// anything to remove, live or persisted.
+    pub async fn remove_room(&self, room_id: &RoomId) -> Result<bool, RoomError> {
+        // Deleted from the store first, so a failure leaves the room as it was
+        let mut persisted = false;
+        if let Some(store) = &self.store {
+            persisted = store.load(room_id).await?.is_some();
+            store.delete(room_id).await?;
+        }
+
+        let live = self.rooms.write().await.remove(room_id).is_some();
+        self.diff_logs().remove(room_id);
+        self.unsaved().remove(room_id);
+        self.end_replay(room_id);
+        let removed = live || persisted;
+        if removed {
+            info!(room_id = %room_id, "Room removed");
+        }
+        Ok(removed)
+    }
+
+    pub async fn contains_room(&self, room_id: &RoomId) -> bool {
+        self.rooms.read().await.contains_key(room_id)
+    }
+
+    /// Ids of every live room.
+    pub async fn list_rooms(&self) -> Vec<RoomId> {
+        self.rooms.read().await.keys().cloned().collect()
+    }
+
+    /// Snapshots of every room, live or only persisted, sorted by id. Persisted rooms
+    /// are read from the store without being loaded.
+    pub async fn room_snapshots(&self) -> Result<Vec<RoomSnapshot>, RoomError> {
+        let live: Vec<SharedRoom<R>> = self.rooms.read().await.values().cloned().collect();
+        let mut snapshots = BTreeMap::new();
+        for room in live {
+            let snapshot = RoomSnapshot::from_room(&*room.lock().await)?;
+            snapshots.insert(snapshot.room_id.clone(), snapshot);
+        }
+
+        if let Some(store) = &self.store {
+            for persisted in store.list().await? {
+                if snapshots.contains_key(&persisted.room_id) {
+                    continue;
+                }
+                let room: R = persisted.into_room()?;
+                snapshots.insert(room.id().clone(), RoomSnapshot::from_room(&room)?);
+            }
+        }
+        Ok(snapshots.into_values().collect())
+    }
+
+    /// Runs `f` with shared access to a room, loading it from the store if it isn't
+    /// live.
+    pub async fn with_room<F, T>(&self, room_id: &RoomId, f: F) -> Result<T, RoomError>
+    where
+        F: FnOnce(&R) -> T,
+    {
+        let guard = self.lock_live_room(room_id).await?;
+        Ok(f(&guard))
+    }
+
+    /// Runs `f` with exclusive access to a room, loading it from the store if it isn't
+    /// live.
+    pub async fn with_room_mut<F, T>(&self, room_id: &RoomId, f: F) -> Result<T, RoomError>
+    where
+        F: FnOnce(&mut R) -> T,
+    {
+        let mut guard = self.lock_live_room(room_id).await?;
+        let version = guard.version();
+        let revision = guard.private_state_revision();
+        let result = f(&mut guard);
+        if guard.version() != version {
+            self.mark_unsaved(room_id);
+            // `f` leaves no diff behind, so history gets the whole storage
+            let snapshot = Self::snapshot_change(&guard);
+            self.record_history(room_id, None, guard.version(), snapshot)
+                .await;
+        } else if guard.private_state_revision() != revision {
+            self.mark_unsaved(room_id);
+        }
+        Ok(result)
+    }
+
+    /// Adds a client to a room and to the room's broadcast group, sends it the full
+    /// [`RoomState`] and announces it to everyone else.
+    ///
+    /// All of this happens under the room lock, so the joiner sees every update
+    /// applied after its snapshot and none applied before it. A room that isn't live
+    /// is loaded from the store first.
+    pub async fn join_room(
+        &self,
+        room_id: &RoomId,
+        client_id: ClientId,
+        metadata: R::ClientMetadata,
+    ) -> Result<(), RoomError> {
+        let mut room = self.lock_live_room(room_id).await?;
+        room.add_client(client_id.clone(), metadata)?;
+
+        if let Err(err) = self.sync_joiner(room_id, &client_id, &room).await {
+            // Don't leave a member behind that can never receive updates
+            let _ = room.remove_client(&client_id);
+            if let Err(err) = self.msg_broker.leave(room_id.as_str(), &client_id).await {
+                warn!(
+                    room_id = %room_id,
+                    client_id = %client_id,
+                    error = %err,
+                    "Failed to roll back join"
+                );
+            }
+            return Err(err);
+        }
+
+        self.announce(
+            room_id,
+            &client_id,
+            ServerMessageType::RoomJoined {
+                room_id: room_id.clone(),
+                socket_id: client_id.clone(),
+                user_info: room.get_user_info(&client_id),
+                entered_at: Utc::now(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Subscribes a client that was just added to `room` and sends it the room state.
+    async fn sync_joiner(
+        &self,
         room_id: &RoomId,
         client_id: &ClientId,
-        message: Message<R::ClientMessageType>,
+        room: &R,
     ) -> Result<(), RoomError> {
-        let room = self
+        self.msg_broker
+            .join(room_id.as_str(), client_id)
+            .await
+            .map_err(Into::<RoomError>::into)?;
+
+        let clients = room
+            .get_connected_clients()
+            .into_iter()
+            .filter_map(|id| {
+                let metadata = room.get_client_metadata(&id)?.clone();
+                Some((id, metadata))
+            })
+            .collect();
+        let state = Message {
+            room_id: room_id.clone(),
+            payload: RoomState {
+                version: room.version(),
+                storage: room.storage().snapshot()?,
+                presence: room.get_all_presence(),
+                clients,
+            },
+            datetime: Utc::now(),
+            sender_id: None,
+            request_id: None,
+            broadcast: Some(false),
+            base_version: None,
+        };
+
+        self.msg_broker
+            .send(std::slice::from_ref(client_id), STATE_EVENT, &state)
+            .await
+            .map_err(Into::<RoomError>::into)
+    }
+
+    /// Returns what a client at storage version `since` needs to get to the room's
+    /// current version: the logged updates after `since` if the room still has all of
+    /// them, and otherwise a full snapshot. Without `since` the snapshot is returned.
+    /// A room that isn't live is loaded from the store first.
+    pub async fn catch_up(
+        &self,
+        room_id: &RoomId,
+        since: Option<u64>,
+    ) -> Result<StorageCatchUp, RoomError> {
+        let room = self.lock_live_room(room_id).await?;
+        let version = room.version();
+
+        if let Some(diffs) = since.and_then(|since| self.logged_since(room_id, since, version)) {
+            return Ok(StorageCatchUp::Diffs { version, diffs });
+        }
+
+        debug!(room_id = %room_id, ?since, version, "Diff log doesn't cover catch-up");
+        Ok(StorageCatchUp::Snapshot {
+            version,
+            storage: room.storage().snapshot()?,
+        })
+    }
+
+    /// The logged updates taking a room from `since` to `version`, if all are logged.
+    fn logged_since(
+        &self,
+        room_id: &RoomId,
+        since: u64,
+        version: u64,
+    ) -> Option<Vec<StorageUpdate<Value>>> {
+        if since == version {
+            return Some(Vec::new());
+        }
+        if since > version {
+            return None;
+        }
+
+        let logs = self.diff_logs();
+        let log = logs.get(room_id)?;
+        let first = log.front()?.version;
+        if first > since + 1 || log.back()?.version != version {
+            return None;
+        }
+        // Versions in the log are consecutive, so the updates after `since` are a suffix
+        let skip = usize::try_from(since + 1 - first).ok()?;
+        Some(log.iter().skip(skip).cloned().collect())
+    }
+
+    /// Appends a storage update to a room's log, dropping the oldest beyond capacity.
+    /// Anything that breaks the one-diff-per-version sequence, like storage being
+    /// replaced without a diff, restarts the log.
+    fn log_diff<D: Serialize>(&self, room_id: &RoomId, version: u64, diff: &D) {
+        if self.diff_log_capacity == 0 {
+            return;
+        }
+
+        let mut logs = self.diff_logs();
+        let log = logs.entry(room_id.clone()).or_default();
+        match log.back() {
+            // Several diffs for one version can't be replayed on their own
+            Some(last) if last.version == version => {
+                log.clear();
+                return;
+            }
+            Some(last) if last.version + 1 != version => log.clear(),
+            _ => {}
+        }
+        match serde_json::to_value(diff) {
+            Ok(diff) => {
+                log.push_back(StorageUpdate { diff, version });
+                while log.len() > self.diff_log_capacity {
+                    log.pop_front();
+                }
+            }
+            Err(err) => {
+                warn!(room_id = %room_id, error = %err, "Failed to log storage diff");
+                log.clear();
+            }
+        }
+    }
+
+    fn diff_logs(&self) -> std::sync::MutexGuard<'_, DiffLogs> {
+        self.diff_logs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A page of a room's history, oldest first.
+    pub async fn history(
+        &self,
+        room_id: &RoomId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, RoomError> {
+        self.history_store()?
+            .list_history(room_id, offset, limit)
+            .await
+    }
+
+    /// Rebuilds a room's storage as it was at `point`, along with its version then.
+    pub async fn storage_at(
+        &self,
+        room_id: &RoomId,
+        point: HistoryPoint,
+    ) -> Result<(u64, R::Storage), RoomError> {
+        let store = self.history_store()?;
+        let version = match point {
+            HistoryPoint::Version(version) => version,
+            HistoryPoint::Time(at) => store.version_at(room_id, at).await?.ok_or_else(|| {
+                RoomError::PersistenceError(format!("Room {room_id} has no history before {at}"))
+            })?,
+        };
+
+        let entries = store.history_until(room_id, version).await?;
+        Ok((version, rebuild_storage(entries, version)?))
+    }
+
+    /// Brings a room's storage back to how it was at `point` and returns the new version.
+    ///
+    /// The restore is a change like any other: it gets the next version, is broadcast
+    /// as a diff and is recorded in history, so it can itself be undone by a restore.
+    pub async fn restore(&self, room_id: &RoomId, point: HistoryPoint) -> Result<u64, RoomError> {
+        self.ensure_writable(room_id)?;
+        let (restored, storage) = self.storage_at(room_id, point).await?;
+
+        let mut room = self.lock_live_room(room_id).await?;
+        let version = self.swap_storage(room_id, &mut room, storage).await?;
+        info!(room_id = %room_id, restored, version, "Room restored from history");
+        Ok(version)
+    }
+
+    /// Replaces a room's whole storage, e.g. through the HTTP API, and returns the new
+    /// version. Clients get the difference as a regular storage update.
+    pub async fn replace_storage(
+        &self,
+        room_id: &RoomId,
+        storage: R::Storage,
+    ) -> Result<u64, RoomError> {
+        self.ensure_writable(room_id)?;
+        let mut room = self.lock_live_room(room_id).await?;
+        let version = self.swap_storage(room_id, &mut room, storage).await?;
+        info!(room_id = %room_id, version, "Room storage replaced");
+        Ok(version)
+    }
+
+    /// Puts `storage` in place of a locked room's storage as a new version, recording
+    /// it in history and broadcasting the diff.
+    async fn swap_storage(
+        &self,
+        room_id: &RoomId,
+        room: &mut R,
+        storage: R::Storage,
+    ) -> Result<u64, RoomError> {
+        if let Some(schema) = room.storage_schema() {
+            schema.validate_storage(&storage)?;
+        }
+        let diff = room.storage().diff(&storage)?;
+        // Not every room counts a mutable borrow of its storage as a change
+        let version = room.version() + 1;
+        *room.storage_mut() = storage;
+        room.set_version(version);
+        self.mark_unsaved(room_id);
+
+        let change = serde_json::to_value(&diff)
+            .map(|diff| HistoryChange::Diff { diff })
+            .map_err(RoomError::from);
+        self.record_history(room_id, None, version, change).await;
+
+        let outcome = TransactionOutcome::BroadcastStorageUpdate {
+            diff,
+            exclude_sender: false,
+        };
+        self.dispatch(room_id, None, version, outcome).await;
+        Ok(version)
+    }
+
+    /// Packs a room up to be moved to another environment, with its whole history if
+    /// `with_history` is set.
+    pub async fn export_room(
+        &self,
+        room_id: &RoomId,
+        with_history: bool,
+    ) -> Result<RoomArchive, RoomError> {
+        // History is appended under the room lock, so it matches the storage
+        let room = self.lock_live_room(room_id).await?;
+        let history = if with_history {
+            Some(self.full_history(room_id).await?)
+        } else {
+            None
+        };
+        RoomArchive::from_room(&*room, history)
+    }
+
+    /// Recreates an archived room under its original id, with its history if the
+    /// archive has it and there is a store. Fails if the room already exists.
+    pub async fn import_room(&self, archive: RoomArchive) -> Result<RoomId, RoomError> {
+        let (room, history) = archive.into_room::<R>()?;
+        let room_id = room.id().clone();
+        if self.get_or_load_room(&room_id).await.is_ok() {
+            return Err(RoomError::RoomAlreadyExists(room_id));
+        }
+
+        let version = room.version();
+        self.create_room(room).await?;
+        // The created room's own snapshot stands in for its latest version
+        if let (Some(store), Some(history)) = (&self.store, history) {
+            for mut entry in history.into_iter().filter(|entry| entry.version < version) {
+                entry.room_id = room_id.clone();
+                store.append_history(entry).await?;
+            }
+        }
+        info!(room_id = %room_id, version, "Room imported");
+        Ok(room_id)
+    }
+
+    async fn full_history(&self, room_id: &RoomId) -> Result<Vec<HistoryEntry>, RoomError> {
+        let store = self.history_store()?;
+        let mut history = Vec::new();
+        loop {
+            let page = store
+                .list_history(room_id, history.len(), HISTORY_EXPORT_PAGE)
+                .await?;
+            let done = page.len() < HISTORY_EXPORT_PAGE;
+            history.extend(page);
+            if done {
+                return Ok(history);
+            }
+        }
+    }
+
+    fn history_store(&self) -> Result<&Arc<dyn RoomStore>, RoomError> {
+        self.store.as_ref().ok_or_else(|| {
+            RoomError::PersistenceError("Room history needs a room store".to_string())
+        })
+    }
+
+    /// Appends a storage change to a room's history, if there is a store. Failures are
+    /// only logged, as the change has already been applied.
+    async fn record_history(
+        &self,
+        room_id: &RoomId,
+        client_id: Option<&ClientId>,
+        version: u64,
+        change: Result<HistoryChange, RoomError>,
+    ) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let result = match change {
+            Ok(change) => {
+                let entry = HistoryEntry {
+                    room_id: room_id.clone(),
+                    version,
+                    client_id: client_id.cloned(),
+                    timestamp: Utc::now(),
+                    change,
+                };
+                store.append_history(entry).await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(room_id = %room_id, version, error = %err, "Failed to record room history");
+        }
+    }
+
+    /// How a client message took `room` from version `previous` to its current one:
+    /// the storage diff it broadcast if there is exactly one for a single new version,
+    /// and the whole storage otherwise.
+    fn history_change(
+        previous: u64,
+        room: &R,
+        outcome: &RoomOutcome<R>,
+    ) -> Result<HistoryChange, RoomError> {
+        let mut diffs = Vec::new();
+        Self::storage_diffs(outcome, &mut diffs);
+        match diffs.as_slice() {
+            [diff] if room.version() == previous + 1 => Ok(HistoryChange::Diff {
+                diff: serde_json::to_value(diff)?,
+            }),
+            _ => Self::snapshot_change(room),
+        }
+    }
+
+    fn storage_diffs<'a>(outcome: &'a RoomOutcome<R>, diffs: &mut Vec<&'a StorageDiff<R>>) {
+        match outcome {
+            TransactionOutcome::BroadcastStorageUpdate { diff, .. } => diffs.push(diff),
+            TransactionOutcome::Multiple(outcomes) => {
+                for outcome in outcomes {
+                    Self::storage_diffs(outcome, diffs);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn snapshot_change(room: &R) -> Result<HistoryChange, RoomError> {
+        Ok(HistoryChange::Snapshot {
+            storage: room.storage().snapshot()?,
+        })
+    }
+
+    /// Moves a new room's version past the history left by an earlier room with the
+    /// same id, so the two don't get mixed up.
+    async fn continue_history(&self, room: &mut R) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        match store.version_at(room.id(), Utc::now()).await {
+            Ok(Some(latest)) if latest >= room.version() => room.set_version(latest + 1),
+            Ok(_) => {}
+            Err(err) => {
+                warn!(room_id = %room.id(), error = %err, "Failed to look up room history");
+            }
+        }
+    }
+
+    /// Removes a client from a room, returning its connection metadata.
+    pub async fn leave_room(
+        &self,
+        room_id: &RoomId,
+        client_id: &ClientId,
+    ) -> Result<R::ClientMetadata, RoomError> {
+        let shared = self.room_or_err(room_id).await?;
+        let mut room = shared.lock().await;
+        let metadata = room.remove_client(client_id)?;
+        let closed = room.is_empty();
+        self.forget_presence(room_id, client_id);
+
+        self.msg_broker
+            .leave(room_id.as_str(), client_id)
+            .await
+            .map_err(Into::<RoomError>::into)?;
+
+        self.announce(room_id, client_id, Self::room_left(room_id, client_id))
+            .await;
+        drop(room);
+
+        if closed {
+            self.flush_closed(room_id).await;
+        }
+        Ok(metadata)
+    }
+
+    /// Removes a client from every room it is connected to (e.g. on disconnect).
+    /// Returns the ids of the rooms the client was removed from.
+    pub async fn leave_all(&self, client_id: &ClientId) -> Vec<RoomId> {
+        let rooms: Vec<(RoomId, SharedRoom<R>)> = self
             .rooms
-            .get_mut(room_id)
-            .ok_or(RoomError::RoomNotFound(room_id.clone()))?;
+            .read()
+            .await
+            .iter()
+            .map(|(id, room)| (id.clone(), Arc::clone(room)))
+            .collect();
+
+        let mut left = Vec::new();
+        for (room_id, room) in rooms {
+            let closed = {
+                let mut room = room.lock().await;
+                match room.remove_client(client_id) {
+                    Ok(_) => room.is_empty(),
+                    Err(_) => continue,
+                }
+            };
+
+            debug!(room_id = %room_id, client_id = %client_id, "Client removed from room");
+            self.forget_presence(&room_id, client_id);
+            if let Err(err) = self.msg_broker.leave(room_id.as_str(), client_id).await {
+                warn!(
+                    room_id = %room_id,
+                    client_id = %client_id,
+                    error = %err,
+                    "Failed to leave broadcast group"
+                );
+            }
+            self.announce(&room_id, client_id, Self::room_left(&room_id, client_id))
+                .await;
+            if closed {
+                self.flush_closed(&room_id).await;
+            }
+            left.push(room_id);
+        }
+        left
+    }
+
+    /// Broadcasts an arbitrary payload to every client in a live room.
+    pub async fn broadcast<P>(
+        &self,
+        room_id: &RoomId,
+        msg_name: &str,
+        payload: P,
+    ) -> Result<(), RoomError>
+    where
+        P: Serialize + Send + Sync,
+    {
+        if !self.contains_room(room_id).await {
+            return Err(RoomError::RoomNotFound(room_id.clone()));
+        }
+
+        self.broadcast_to_room(room_id, msg_name, &payload, &[])
+            .await
+            .map_err(Into::<RoomError>::into)?;
+        Ok(())
+    }
+
+    /// Broadcasts to a room's clients, adding the broadcast to the room's recording if
+    /// it is being recorded. Every room-wide broadcast goes through here.
+    async fn broadcast_to_room<P>(
+        &self,
+        room_id: &RoomId,
+        msg_name: &str,
+        payload: &P,
+        exclude: &[ClientId],
+    ) -> Result<(), B::Error>
+    where
+        P: Serialize + Send + Sync,
+    {
+        self.recorder.record(room_id, msg_name, payload);
+        self.msg_broker
+            .broadcast(room_id.as_str(), msg_name, payload, exclude)
+            .await
+    }
+
+    /// Starts recording everything broadcast to a room; see [`Recording`].
+    pub async fn start_recording(&self, room_id: &RoomId) -> Result<(), RoomError> {
+        // Under the room lock, so nothing is broadcast between the snapshot and the start
+        let room = self.lock_live_room(room_id).await?;
+        self.recorder.start(Recording::start(&*room)?)?;
+        info!(room_id = %room_id, version = room.version(), "Recording started");
+        Ok(())
+    }
 
+    /// Stops recording a room and returns the recording.
+    pub fn stop_recording(&self, room_id: &RoomId) -> Result<Recording, RoomError> {
+        let recording = self.recorder.stop(room_id).ok_or_else(|| {
+            RoomError::TransactionError(format!("Room {room_id} isn't being recorded"))
+        })?;
+        info!(room_id = %room_id, events = recording.events.len(), "Recording stopped");
+        Ok(recording)
+    }
+
+    /// Replays `recording` into a new read-only room and returns its id.
+    ///
+    /// Everything the recorded room broadcast is broadcast again with the original
+    /// timing, `speed` times as fast. Storage updates are applied as well, so clients
+    /// joining midway get the storage as it was at that point of the session.
+    pub async fn replay(&self, recording: Recording, speed: f64) -> Result<RoomId, RoomError> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(RoomError::TransactionError(
+                "Replay speed must be positive".to_string(),
+            ));
+        }
+
+        let header = recording.header.clone();
+        let room_id = RoomId::new();
+        let metadata = RoomMetadata {
+            name: format!("Replay of {}", header.metadata.name),
+            ..header.metadata
+        };
+        let storage = R::Storage::from_snapshot(header.storage)?;
+        let mut room = R::create(room_id.clone(), metadata, storage);
+        if room.room_type() != header.room_type {
+            return Err(RoomError::TransactionError(format!(
+                "Recording is of a {}, not a {}",
+                header.room_type,
+                room.room_type()
+            )));
+        }
+        room.set_version(header.version);
+
+        let room = Arc::new(Mutex::new(room));
+        let mut rooms = self.rooms.write().await;
+        // Registered before the room is visible, so no client ever gets to change it
+        let task = tokio::spawn(self.clone().run_replay(
+            room_id.clone(),
+            Arc::clone(&room),
+            recording,
+            speed,
+        ));
+        self.replays().insert(room_id.clone(), task.abort_handle());
+        rooms.insert(room_id.clone(), room);
+        self.check_capacity(rooms.len());
+        info!(room_id = %room_id, recorded = %header.room_id, speed, "Replay started");
+
+        Ok(room_id)
+    }
+
+    async fn run_replay(
+        self,
+        room_id: RoomId,
+        room: SharedRoom<R>,
+        recording: Recording,
+        speed: f64,
+    ) {
+        let start = Instant::now();
+        for event in &recording.events {
+            let due = start + recording.offset(event).div_f64(speed);
+            tokio::time::sleep_until(due).await;
+
+            let RecordedEvent { event, payload, .. } = event;
+            let mut payload = payload.clone();
+            retarget(&mut payload, &recording.header.room_id, &room_id);
+
+            // Held while broadcasting, so joiners see each update exactly once
+            let mut guard = room.lock().await;
+            if event == STORAGE_EVENT {
+                if let Err(err) = self.replay_storage_update(&room_id, &mut guard, &payload) {
+                    warn!(room_id = %room_id, error = %err, "Failed to replay storage update");
+                }
+            }
+            if let Err(err) = self.broadcast_to_room(&room_id, event, &payload, &[]).await {
+                warn!(room_id = %room_id, event = %event, error = %err, "Failed to replay event");
+            }
+        }
+        info!(room_id = %room_id, "Replay finished");
+    }
+
+    fn replay_storage_update(
+        &self,
+        room_id: &RoomId,
+        room: &mut R,
+        payload: &Value,
+    ) -> Result<(), RoomError> {
+        let update: Message<StorageUpdate<Value>> = serde_json::from_value(payload.clone())?;
+        let StorageUpdate { diff, version } = update.payload;
+        room.storage_mut()
+            .apply_diff(serde_json::from_value(diff.clone())?)?;
+        room.set_version(version);
+        self.log_diff(room_id, version, &diff);
+        Ok(())
+    }
+
+    /// Forgets a replay room, stopping the replay. Returns whether it was one.
+    fn end_replay(&self, room_id: &RoomId) -> bool {
+        match self.replays().remove(room_id) {
+            Some(replay) => {
+                replay.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fails for replay rooms, whose storage only the replay itself may change.
+    fn ensure_writable(&self, room_id: &RoomId) -> Result<(), RoomError> {
+        if self.replays().contains_key(room_id) {
+            return Err(RoomError::PermissionDenied(
+                "Replays are read-only".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn replays(&self) -> std::sync::MutexGuard<'_, HashMap<RoomId, AbortHandle>> {
+        self.replays.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies a client message to its room and dispatches the resulting outcome.
+    ///
+    /// The room stays locked until the outcome has been handed to the broker, so
+    /// updates from a single room are delivered in the order they were applied.
+    /// Versioned messages (see [`ClientMessageTypeLike::is_versioned`]) are rejected
+    /// unless they were based on the room's current storage version. If the room has a
+    /// [`RoomLike::storage_schema`], a message leaving the storage in breach of it is
+    /// rolled back, storage and version alike, and nothing is broadcast.
+    /// Returns an error only if the message could not be applied; delivery
+    /// problems are reported in the returned [`DispatchReport`].
+    pub async fn handle_client_message(
+        &self,
+        room_id: &RoomId,
+        client_id: &ClientId,
+        message: Message<R::ClientMessageType>,
+    ) -> Result<DispatchReport, RoomError> {
+        self.ensure_writable(room_id)?;
+
+        let room = self.room_or_err(room_id).await?;
+        let mut room = room.lock().await;
+
+        if message.payload.is_versioned() {
+            let current = room.version();
+            match message.base_version {
+                None => return Err(RoomError::MissingBaseVersion),
+                Some(base) if base != current => {
+                    return Err(RoomError::StaleVersion { base, current });
+                }
+                Some(_) => {}
+            }
+        }
+
+        let version = room.version();
+        let revision = room.private_state_revision();
+        let before = room.storage_schema().map(|_| room.storage().clone());
         let outcome = room.apply_client_message(client_id, message)?;
+        // Whatever the room did, a change that breaks its schema is taken back
+        let violation = match room.storage_schema() {
+            Some(schema) if room.version() != version => {
+                schema.validate_storage(room.storage()).err()
+            }
+            _ => None,
+        };
+        if let (Some(e), Some(before)) = (violation, before) {
+            *room.storage_mut() = before;
+            room.set_version(version);
+            return Err(e.into());
+        }
+        if room.version() != version {
+            self.mark_unsaved(room_id);
+            let change = Self::history_change(version, &room, &outcome);
+            self.record_history(room_id, Some(client_id), room.version(), change)
+                .await;
+        } else if room.private_state_revision() != revision {
+            self.mark_unsaved(room_id);
+        }
 
-        // Handle the outcome using the shared communicator
-        match outcome {
-            TransactionOutcome::None => {}
-            TransactionOutcome::Broadcast {
-                message,
-                exclude_sender,
-            } => {
-                let exclude = if exclude_sender {
-                    vec![client_id.clone()]
-                } else {
-                    vec![]
-                };
-                self.msg_broker
-                    .as_ref()
-                    .broadcast(room_id.as_str(), "message", &message, &exclude)
-                    .await?;
-            }
-            TransactionOutcome::BroadcastStorageUpdate {
-                diff,
-                exclude_sender,
-            } => {
-                let exclude = if exclude_sender {
-                    vec![client_id.clone()]
-                } else {
-                    vec![]
-                };
-                self.msg_broker
-                    .as_ref()
-                    .broadcast_all("message", &diff, &exclude)
-                    .await?;
+        Ok(self
+            .dispatch(room_id, Some(client_id), room.version(), outcome)
+            .await)
+    }
+
+    /// Delivers every action described by `outcome` to the clients of `room_id`.
+    ///
+    /// `sender` is the client that triggered the outcome, if any; it is used for
+    /// `exclude_sender` and recorded on storage updates. `version` is the room's
+    /// storage version once the outcome was applied, sent along with storage updates.
+    /// Nested [`TransactionOutcome::Multiple`] outcomes are executed depth-first, in order.
+    /// A failed delivery does not stop the remaining ones.
+    pub async fn dispatch(
+        &self,
+        room_id: &RoomId,
+        sender: Option<&ClientId>,
+        version: u64,
+        outcome: RoomOutcome<R>,
+    ) -> DispatchReport {
+        let mut report = DispatchReport::default();
+        let mut pending = VecDeque::from([outcome]);
+
+        let exclusions = |exclude_sender: bool| match sender {
+            Some(sender) if exclude_sender => vec![sender.clone()],
+            _ => vec![],
+        };
+
+        while let Some(outcome) = pending.pop_front() {
+            match outcome {
+                TransactionOutcome::None => {}
+                TransactionOutcome::Broadcast {
+                    message,
+                    exclude_sender,
+                } => {
+                    let exclude = exclusions(exclude_sender);
+                    let result = self
+                        .broadcast_to_room(room_id, MESSAGE_EVENT, &message, &exclude)
+                        .await;
+                    report.record(DeliveryTarget::Room { exclude }, result);
+                }
+                TransactionOutcome::BroadcastStorageUpdate {
+                    diff,
+                    exclude_sender,
+                } => {
+                    let exclude = exclusions(exclude_sender);
+                    self.log_diff(room_id, version, &diff);
+                    let update = Message {
+                        room_id: room_id.clone(),
+                        payload: StorageUpdate { diff, version },
+                        datetime: Utc::now(),
+                        sender_id: sender.cloned(),
+                        request_id: None,
+                        broadcast: Some(true),
+                        base_version: None,
+                    };
+                    let result = self
+                        .broadcast_to_room(room_id, STORAGE_EVENT, &update, &exclude)
+                        .await;
+                    report.record(DeliveryTarget::Room { exclude }, result);
+                }
+                TransactionOutcome::SendTo { clients, message } => {
+                    if clients.is_empty() {
+                        continue;
+                    }
+                    let result = self.msg_broker.send(&clients, MESSAGE_EVENT, &message).await;
+                    report.record(DeliveryTarget::Clients(clients), result);
+                }
+                TransactionOutcome::PresenceChanged {
+                    client_id,
+                    presence,
+                } => {
+                    if self.presence_window.is_zero() {
+                        let batch = HashMap::from([(client_id, presence)]);
+                        let result = self.broadcast_presence(room_id, batch).await;
+                        report.record(DeliveryTarget::Room { exclude: vec![] }, result);
+                    } else {
+                        // Never waits on the broker, so storage updates aren't held up
+                        self.queue_presence(room_id, client_id, presence);
+                    }
+                }
+                TransactionOutcome::Multiple(outcomes) => {
+                    // Queue nested outcomes ahead of their siblings to preserve order
+                    for outcome in outcomes.into_iter().rev() {
+                        pending.push_front(outcome);
+                    }
+                }
             }
-            TransactionOutcome::SendTo { clients, message } => {}
-            TransactionOutcome::Multiple(msgs) => {} // Handle other outcomes...
         }
 
+        if !report.is_complete() {
+            warn!(
+                room_id = %room_id,
+                delivered = report.delivered,
+                failed = report.failures.len(),
+                "Transaction outcome only partially delivered"
+            );
+        }
+
+        report
+    }
+
+    /// Records a presence change, starting the room's batching window if it isn't
+    /// already running. Later changes from the same client replace earlier ones.
+    fn queue_presence(&self, room_id: &RoomId, client_id: ClientId, presence: Value) {
+        let opens_window = {
+            let mut pending = self.pending();
+            let room = pending.entry(room_id.clone()).or_default();
+            let opens_window = room.is_empty();
+            room.insert(client_id, presence);
+            opens_window
+        };
+
+        if opens_window {
+            let manager = self.clone();
+            let room_id = room_id.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(manager.presence_window).await;
+                if let Err(err) = manager.flush_presence(&room_id).await {
+                    warn!(room_id = %room_id, error = %err, "Failed to broadcast presence");
+                }
+            });
+        }
+    }
+
+    /// Broadcasts a room's queued presence changes right away, as one batch.
+    pub async fn flush_presence(&self, room_id: &RoomId) -> Result<(), RoomError> {
+        let batch = self.pending().remove(room_id).unwrap_or_default();
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.broadcast_presence(room_id, batch).await
+    }
+
+    async fn broadcast_presence(
+        &self,
+        room_id: &RoomId,
+        presence: HashMap<ClientId, Value>,
+    ) -> Result<(), RoomError> {
+        let message = Message {
+            room_id: room_id.clone(),
+            payload: PresenceUpdated { presence },
+            datetime: Utc::now(),
+            sender_id: None,
+            request_id: None,
+            broadcast: Some(true),
+            base_version: None,
+        };
+        self.broadcast_to_room(room_id, PRESENCE_EVENT, &message, &[])
+            .await
+            .map_err(Into::<RoomError>::into)
+    }
+
+    /// Drops a departed client's queued presence so it isn't broadcast after it left.
+    fn forget_presence(&self, room_id: &RoomId, client_id: &ClientId) {
+        if let Some(room) = self.pending().get_mut(room_id) {
+            room.remove(client_id);
+        }
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, PendingPresence> {
+        self.pending_presence
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Tells the rest of a room that `client_id` joined or left.
+    /// Best effort: a failed announcement never undoes the membership change.
+    async fn announce(&self, room_id: &RoomId, client_id: &ClientId, payload: ServerMessageType) {
+        let message = Message {
+            room_id: room_id.clone(),
+            payload,
+            datetime: Utc::now(),
+            sender_id: Some(client_id.clone()),
+            request_id: None,
+            broadcast: Some(true),
+            base_version: None,
+        };
+
+        if let Err(err) = self
+            .broadcast_to_room(
+                room_id,
+                MESSAGE_EVENT,
+                &message,
+                std::slice::from_ref(client_id),
+            )
+            .await
+        {
+            warn!(
+                room_id = %room_id,
+                client_id = %client_id,
+                error = %err,
+                "Failed to announce membership change"
+            );
+        }
+    }
+
+    fn room_left(room_id: &RoomId, client_id: &ClientId) -> ServerMessageType {
+        ServerMessageType::RoomLeft {
+            room_id: room_id.clone(),
+            socket_id: client_id.clone(),
+        }
+    }
+
+    /// Returns a live room, loading it from the store if it isn't live.
+    pub async fn get_or_load_room(&self, room_id: &RoomId) -> Result<SharedRoom<R>, RoomError> {
+        if let Some(room) = self.get_room(room_id).await {
+            return Ok(room);
+        }
+        let Some(store) = &self.store else {
+            return Err(RoomError::RoomNotFound(room_id.clone()));
+        };
+
+        let persisted = store
+            .load(room_id)
+            .await?
+            .ok_or_else(|| RoomError::RoomNotFound(room_id.clone()))?;
+        let version = persisted.version;
+        let room: R = persisted.into_room()?;
+
+        // Another client may have loaded the room in the meantime; keep theirs
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .entry(room_id.clone())
+            .or_insert_with(|| {
+                info!(room_id = %room_id, version, "Room loaded from store");
+                Arc::new(Mutex::new(room))
+            })
+            .clone();
+        self.check_capacity(rooms.len());
+        Ok(room)
+    }
+
+    /// Saves a room if it changed since it was last saved.
+    pub async fn flush_room(&self, room_id: &RoomId) -> Result<(), RoomError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let Some(room) = self.get_room(room_id).await else {
+            self.unsaved().remove(room_id);
+            return Ok(());
+        };
+
+        let persisted = {
+            // Changes are marked under the room lock, so anything after this snapshot
+            // marks the room unsaved again
+            let room = room.lock().await;
+            if self.unsaved().remove(room_id).is_none() {
+                return Ok(());
+            }
+            PersistedRoom::from_room(&*room)?
+        };
+        let version = persisted.version;
+        store.save(persisted).await?;
+        debug!(room_id = %room_id, version, "Room saved");
         Ok(())
     }
+
+    /// Saves every room with unsaved changes, e.g. before shutting down.
+    /// Keeps going past failures and returns the first one.
+    pub async fn flush_all(&self) -> Result<(), RoomError> {
+        let room_ids: Vec<RoomId> = self.unsaved().keys().cloned().collect();
+        let mut result = Ok(());
+        for room_id in room_ids {
+            if let Err(err) = self.flush_room(&room_id).await {
+                warn!(room_id = %room_id, error = %err, "Failed to save room");
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    /// Saves an empty room to the store and drops it from memory; it is loaded again
+    /// when a client next joins. Returns `false` if the room isn't live, has clients,
+    /// or there is no store to keep it in.
+    pub async fn unload_room(&self, room_id: &RoomId) -> Result<bool, RoomError> {
+        let Some(store) = &self.store else {
+            return Ok(false);
+        };
+        let Some(shared) = self.get_room(room_id).await else {
+            return Ok(false);
+        };
+
+        // Held until the room is out of the map, so nothing can change it after the save
+        let room = shared.lock().await;
+        if !room.is_empty() || !self.is_live(room_id, &shared).await {
+            return Ok(false);
+        }
+        self.unsaved().remove(room_id);
+        // Replays can be started again from their recording; they aren't kept
+        if !self.end_replay(room_id) {
+            store.save(PersistedRoom::from_room(&*room)?).await?;
+        }
+
+        self.rooms.write().await.remove(room_id);
+        self.diff_logs().remove(room_id);
+        self.pending().remove(room_id);
+        info!(room_id = %room_id, version = room.version(), "Room unloaded");
+        Ok(true)
+    }
+
+    /// Unloads rooms that have been empty for longer than the eviction TTL, then
+    /// enforces the room cap. Returns the unloaded rooms.
+    pub async fn evict_idle_rooms(&self) -> Vec<RoomId> {
+        let now = Utc::now();
+        let mut evicted = Vec::new();
+
+        for (room_id, _) in self.empty_rooms().await {
+            // Looked up without loading, as the room may have been unloaded meanwhile
+            let expired = match self.get_room(&room_id).await {
+                Some(room) => {
+                    let room = room.lock().await;
+                    let idle = now.signed_duration_since(room.last_activity_at());
+                    room.is_empty() && idle.to_std().unwrap_or_default() >= self.eviction.empty_ttl
+                }
+                None => false,
+            };
+            if expired && self.try_unload(&room_id).await {
+                evicted.push(room_id);
+            }
+        }
+
+        evicted.extend(self.enforce_max_rooms().await);
+        evicted
+    }
+
+    /// Unloads the least recently active empty rooms until no more than
+    /// `max_rooms` are live. Returns the unloaded rooms.
+    pub async fn enforce_max_rooms(&self) -> Vec<RoomId> {
+        let Some(max_rooms) = self.eviction.max_rooms else {
+            return Vec::new();
+        };
+        let live = self.rooms.read().await.len();
+        if live <= max_rooms {
+            return Vec::new();
+        }
+
+        let mut candidates = self.empty_rooms().await;
+        candidates.sort_by_key(|(_, last_activity)| *last_activity);
+
+        let mut evicted = Vec::new();
+        for (room_id, _) in candidates {
+            if live - evicted.len() <= max_rooms {
+                break;
+            }
+            if self.try_unload(&room_id).await {
+                evicted.push(room_id);
+            }
+        }
+        let remaining = live - evicted.len();
+        if remaining > max_rooms {
+            warn!(
+                remaining,
+                max_rooms, "Over the room cap, but every remaining room has clients"
+            );
+        }
+        evicted
+    }
+
+    /// Runs [`RoomManager::evict_idle_rooms`] every eviction interval until the
+    /// returned task is aborted.
+    pub fn spawn_eviction(&self) -> JoinHandle<()> {
+        let manager = self.clone();
+        info!(config = ?self.eviction, "Starting room eviction");
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(manager.eviction.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let evicted = manager.evict_idle_rooms().await;
+                if !evicted.is_empty() {
+                    debug!(count = evicted.len(), "Evicted idle rooms");
+                }
+            }
+        })
+    }
+
+    /// Live rooms without clients, with when they were last active.
+    async fn empty_rooms(&self) -> Vec<(RoomId, chrono::DateTime<Utc>)> {
+        let rooms: Vec<(RoomId, SharedRoom<R>)> = self
+            .rooms
+            .read()
+            .await
+            .iter()
+            .map(|(id, room)| (id.clone(), Arc::clone(room)))
+            .collect();
+
+        let mut empty = Vec::new();
+        for (room_id, room) in rooms {
+            let room = room.lock().await;
+            if room.is_empty() {
+                empty.push((room_id, room.last_activity_at()));
+            }
+        }
+        empty
+    }
+
+    async fn try_unload(&self, room_id: &RoomId) -> bool {
+        match self.unload_room(room_id).await {
+            Ok(unloaded) => unloaded,
+            Err(err) => {
+                warn!(room_id = %room_id, error = %err, "Failed to unload room");
+                false
+            }
+        }
+    }
+
+    /// Starts enforcing the room cap in the background if `live` rooms exceed it.
+    fn check_capacity(&self, live: usize) {
+        if self.store.is_none() || self.eviction.max_rooms.is_none_or(|max| live <= max) {
+            return;
+        }
+        let manager = self.clone();
+        tokio::spawn(async move {
+            manager.enforce_max_rooms().await;
+        });
+    }
+
+    /// Locks a live room, loading it from the store first if needed.
+    async fn lock_live_room(&self, room_id: &RoomId) -> Result<OwnedMutexGuard<R>, RoomError> {
+        loop {
+            let shared = self.get_or_load_room(room_id).await?;
+            let room = Arc::clone(&shared).lock_owned().await;
+            // The room may have been unloaded while we waited for it
+            if self.is_live(room_id, &shared).await {
+                return Ok(room);
+            }
+        }
+    }
+
+    /// `true` if `room` is still the live room for `room_id`, i.e. it wasn't unloaded
+    /// or replaced.
+    async fn is_live(&self, room_id: &RoomId, room: &SharedRoom<R>) -> bool {
+        self.rooms
+            .read()
+            .await
+            .get(room_id)
+            .is_some_and(|live| Arc::ptr_eq(live, room))
+    }
+
+    /// Saves a room its last client just left, rather than waiting for the save delay.
+    async fn flush_closed(&self, room_id: &RoomId) {
+        if let Err(err) = self.flush_room(room_id).await {
+            warn!(room_id = %room_id, error = %err, "Failed to save closed room");
+        }
+    }
+
+    /// Records that a room changed, starting its background save if none is pending.
+    ///
+    /// The save waits until the room has gone `save_delay` without changes, but no
+    /// longer than [`MAX_SAVE_DELAY_FACTOR`] delays after the first unsaved change.
+    fn mark_unsaved(&self, room_id: &RoomId) {
+        if self.store.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let starts_writer = {
+            let mut unsaved = self.unsaved();
+            match unsaved.get_mut(room_id) {
+                Some(changes) => {
+                    changes.last = now;
+                    false
+                }
+                None => {
+                    let changes = UnsavedChanges {
+                        first: now,
+                        last: now,
+                    };
+                    unsaved.insert(room_id.clone(), changes);
+                    true
+                }
+            }
+        };
+
+        if starts_writer {
+            let manager = self.clone();
+            let room_id = room_id.clone();
+            tokio::spawn(async move {
+                loop {
+                    // Gone if something else saved the room in the meantime
+                    let Some(due) = manager.save_due(&room_id) else {
+                        return;
+                    };
+                    if Instant::now() >= due {
+                        break;
+                    }
+                    tokio::time::sleep_until(due).await;
+                }
+                if let Err(err) = manager.flush_room(&room_id).await {
+                    warn!(room_id = %room_id, error = %err, "Failed to save room");
+                }
+            });
+        }
+    }
+
+    fn save_due(&self, room_id: &RoomId) -> Option<Instant> {
+        let changes = *self.unsaved().get(room_id)?;
+        Some(
+            (changes.last + self.save_delay)
+                .min(changes.first + self.save_delay * MAX_SAVE_DELAY_FACTOR),
+        )
+    }
+
+    fn unsaved(&self) -> std::sync::MutexGuard<'_, HashMap<RoomId, UnsavedChanges>> {
+        self.unsaved.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn room_or_err(&self, room_id: &RoomId) -> Result<SharedRoom<R>, RoomError> {
+        self.get_room(room_id)
+            .await
+            .ok_or_else(|| RoomError::RoomNotFound(room_id.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_broker::memory::InMemoryMessageBroker,
+        presentation::{
+            PresentationClientData, PresentationClientMessage, PresentationServerMessage,
+            PresentationStorage,
+        },
+        room::{
+            persistence::InMemoryRoomStore,
+            storage::StorageError,
+            test_support::{
+                client_data, client_message, create_presentation, presentation_with_clients,
+            },
+        },
+    };
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn room_manager_delivers_slide_change_to_room() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let _bob = broker.connect("bob");
+        let _outsider = broker.connect("outsider");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice", "bob"]).await;
+
+        let message = client_message(
+            &room_id,
+            PresentationClientMessage::ChangeSlide { slide_index: 2 },
+        );
+        let report = manager
+            .handle_client_message(&room_id, &"alice".to_string(), message)
+            .await
+            .unwrap();
+        assert!(report.is_complete());
+
+        let deliveries = broker.deliveries();
+        let recipients = |event: &str| -> Vec<ClientId> {
+            deliveries
+                .iter()
+                .filter(|d| d.msg_name == event)
+                .map(|d| d.client_id.clone())
+                .collect()
+        };
+        let everyone = vec!["alice".to_string(), "bob".to_string()];
+        assert_eq!(recipients("storage"), everyone);
+        assert_eq!(recipients("message"), everyone);
+
+        let update: Message<StorageUpdate<json_patch::Patch>> = broker
+            .deliveries_to(&"bob".to_string())[0]
+            .decode()
+            .unwrap();
+        assert_eq!(update.payload.version, 1);
+        let received: Message<ServerMessageType> = broker.deliveries_to(&"bob".to_string())[1]
+            .decode()
+            .unwrap();
+        assert!(matches!(
+            received.payload,
+            ServerMessageType::Presentation(PresentationServerMessage::SlideChanged {
+                slide_index: 2
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn partial_failures_are_reported() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let bob = broker.connect("bob");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice", "bob"]).await;
+        drop(bob);
+
+        let message = client_message(
+            &room_id,
+            PresentationClientMessage::ChangeSlide { slide_index: 1 },
+        );
+        let report = manager
+            .handle_client_message(&room_id, &"alice".to_string(), message)
+            .await
+            .unwrap();
+
+        // Both the storage update and the slide change miss bob
+        assert_eq!(report.failures.len(), 2);
+        assert_eq!(report.delivered, 0);
+        assert_eq!(broker.deliveries_to(&"alice".to_string()).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn presence_changes_are_batched_per_window() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let _bob = broker.connect("bob");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice", "bob"]).await;
+        let manager = manager.with_presence_window(Duration::from_millis(20));
+
+        for (client, slide) in [("alice", 0), ("alice", 1), ("bob", 2)] {
+            let update = PresentationClientMessage::UpdateMyPresence {
+                presence: json!({ "current_slide": slide }),
+            };
+            manager
+                .handle_client_message(
+                    &room_id,
+                    &client.to_string(),
+                    client_message(&room_id, update),
+                )
+                .await
+                .unwrap();
+        }
+        // Storage-bound messages go out immediately, ahead of the queued presence
+        manager
+            .handle_client_message(
+                &room_id,
+                &"alice".to_string(),
+                client_message(
+                    &room_id,
+                    PresentationClientMessage::ChangeSlide { slide_index: 1 },
+                ),
+            )
+            .await
+            .unwrap();
+        let immediate = broker.take_deliveries();
+        assert!(!immediate.is_empty());
+        assert!(immediate.iter().all(|d| d.msg_name != "presence"));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let batched = broker.take_deliveries();
+        assert_eq!(batched.len(), 2, "one batch per room member");
+
+        let update: Message<PresenceUpdated> = batched[0].decode().unwrap();
+        assert_eq!(batched[0].msg_name, "presence");
+        assert_eq!(update.payload.presence.len(), 2);
+        assert_eq!(update.payload.presence["alice"]["current_slide"], json!(1));
+        assert_eq!(update.payload.presence["bob"]["current_slide"], json!(2));
+    }
+
+    #[tokio::test]
+    async fn joiner_gets_room_state_and_others_get_room_joined() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let _bob = broker.connect("bob");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice"]).await;
+        manager
+            .handle_client_message(
+                &room_id,
+                &"alice".to_string(),
+                client_message(
+                    &room_id,
+                    PresentationClientMessage::ChangeSlide { slide_index: 2 },
+                ),
+            )
+            .await
+            .unwrap();
+        broker.take_deliveries();
+
+        manager
+            .join_room(&room_id, "bob".to_string(), client_data("bob"))
+            .await
+            .unwrap();
+
+        let to_bob = broker.deliveries_to(&"bob".to_string());
+        assert_eq!(to_bob.len(), 1);
+        assert_eq!(to_bob[0].msg_name, "state");
+        let state: Message<RoomState<PresentationClientData, serde_json::Value>> =
+            to_bob[0].decode().unwrap();
+        assert_eq!(state.payload.version, 1);
+        assert_eq!(state.payload.storage["current_slide"], json!(2));
+        assert_eq!(state.payload.clients.len(), 2);
+        assert!(state.payload.presence.contains_key("alice"));
+
+        let to_alice = broker.deliveries_to(&"alice".to_string());
+        assert_eq!(to_alice.len(), 1);
+        let joined = &to_alice[0].payload["payload"];
+        assert_eq!(joined["socket_id"], json!("bob"));
+        assert_eq!(joined["user_info"]["user_name"], json!("bob"));
+    }
+
+    #[tokio::test]
+    async fn stale_storage_updates_are_rejected() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice"]).await;
+        let alice = "alice".to_string();
+
+        let update = |base_version: Option<u64>, title: &str| {
+            let diff = serde_json::from_value(json!([
+                { "op": "add", "path": "/slide_data/0/title", "value": title }
+            ]))
+            .unwrap();
+            Message {
+                base_version,
+                ..client_message(&room_id, PresentationClientMessage::UpdateStorage { diff })
+            }
+        };
+
+        assert!(matches!(
+            manager
+                .handle_client_message(&room_id, &alice, update(None, "a"))
+                .await,
+            Err(RoomError::MissingBaseVersion)
+        ));
+        manager
+            .handle_client_message(&room_id, &alice, update(Some(0), "a"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            manager
+                .handle_client_message(&room_id, &alice, update(Some(0), "b"))
+                .await,
+            Err(RoomError::StaleVersion {
+                base: 0,
+                current: 1
+            })
+        ));
+
+        let title = manager
+            .with_room(&room_id, |room| room.storage().snapshot().unwrap())
+            .await
+            .unwrap()["slide_data"][0]["title"]
+            .clone();
+        assert_eq!(title, json!("a"));
+
+        // Replacing the storage wholesale is a versioned update like any other
+        broker.take_deliveries();
+        let version = manager
+            .replace_storage(&room_id, PresentationStorage::new(vec![json!({})]))
+            .await
+            .unwrap();
+        assert_eq!(version, 2);
+        let updates = broker.take_deliveries();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].msg_name, STORAGE_EVENT);
+        assert_eq!(updates[0].payload["payload"]["version"], json!(2));
+        assert!(matches!(
+            manager
+                .handle_client_message(&room_id, &alice, update(Some(1), "b"))
+                .await,
+            Err(RoomError::StaleVersion {
+                base: 1,
+                current: 2
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn updates_breaking_the_storage_schema_are_rejected() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice"]).await;
+        let alice = "alice".to_string();
+        let state = || {
+            manager.with_room(&room_id, |room| {
+                (room.storage().snapshot().unwrap(), room.version())
+            })
+        };
+        let before = state().await.unwrap();
+
+        // Slides have to be objects
+        let not_a_slide = json!([{ "op": "replace", "path": "/slide_data/1", "value": "x" }]);
+        let not_an_object = json!([{ "op": "replace", "path": "", "value": 3 }]);
+        for payload in [
+            PresentationClientMessage::UpdateStorage {
+                diff: serde_json::from_value(not_a_slide).unwrap(),
+            },
+            PresentationClientMessage::PatchSlide {
+                index: 0,
+                patch: serde_json::from_value(not_an_object).unwrap(),
+            },
+        ] {
+            let message = Message {
+                base_version: Some(0),
+                ..client_message(&room_id, payload)
+            };
+            assert!(matches!(
+                manager
+                    .handle_client_message(&room_id, &alice, message)
+                    .await,
+                Err(RoomError::StorageError(
+                    StorageError::SchemaViolation { .. }
+                ))
+            ));
+        }
+
+        assert_eq!(state().await.unwrap(), before);
+        assert!(broker.take_deliveries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn catch_up_replays_logged_diffs_or_falls_back_to_snapshot() {
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let (manager, room_id) = presentation_with_clients(&broker, &["alice"]).await;
+        let alice = "alice".to_string();
+
+        for slide_index in [1, 2, 0] {
+            manager
+                .handle_client_message(
+                    &room_id,
+                    &alice,
+                    client_message(
+                        &room_id,
+                        PresentationClientMessage::ChangeSlide { slide_index },
+                    ),
+                )
+                .await
+                .unwrap();
+        }
+
+        match manager.catch_up(&room_id, Some(1)).await.unwrap() {
+            StorageCatchUp::Diffs { version, diffs } => {
+                assert_eq!(version, 3);
+                let versions: Vec<u64> = diffs.iter().map(|update| update.version).collect();
+                assert_eq!(versions, vec![2, 3]);
+                assert_eq!(diffs[1].diff[0]["value"], json!(0));
+            }
+            other => panic!("expected diffs, got {other:?}"),
+        }
+        assert!(matches!(
+            manager.catch_up(&room_id, Some(3)).await.unwrap(),
+            StorageCatchUp::Diffs { version: 3, diffs } if diffs.is_empty()
+        ));
+
+        // Replacing the storage bumps the version without a diff, so the log has a gap
+        manager
+            .with_room_mut(&room_id, |room| {
+                *room.storage_mut() = PresentationStorage::new(vec![json!({})]);
+            })
+            .await
+            .unwrap();
+        match manager.catch_up(&room_id, Some(2)).await.unwrap() {
+            StorageCatchUp::Snapshot { version, storage } => {
+                assert_eq!(version, 4);
+                assert_eq!(storage["slide_data"], json!([{}]));
+            }
+            other => panic!("expected a snapshot, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_rooms_are_unloaded_after_ttl_and_over_cap() {
+        let store = InMemoryRoomStore::new();
+        let broker = InMemoryMessageBroker::new();
+        let _alice = broker.connect("alice");
+        let manager = RoomManager::new(broker.clone())
+            .with_store(store.clone())
+            .with_eviction(EvictionConfig {
+                empty_ttl: Duration::ZERO,
+                ..EvictionConfig::default()
+            });
+
+        let empty = create_presentation(&manager, "room_empty", vec![json!({})]).await;
+        let occupied = create_presentation(&manager, "room_occupied", vec![json!({})]).await;
+        manager
+            .join_room(&occupied, "alice".to_string(), client_data("alice"))
+            .await
+            .unwrap();
+
+        assert_eq!(manager.evict_idle_rooms().await, vec![empty.clone()]);
+        assert_eq!(manager.list_rooms().await, vec![occupied.clone()]);
+        assert!(store.get(&empty).is_some());
+
+        // Clients catching up on an unloaded room get it back from the store
+        assert!(matches!(
+            manager.catch_up(&empty, Some(0)).await.unwrap(),
+            StorageCatchUp::Diffs { version: 0, diffs } if diffs.is_empty()
+        ));
+        assert!(manager.contains_room(&empty).await);
+
+        // Over the cap, the least recently active empty room goes first
+        let manager = RoomManager::new(broker.clone())
+            .with_store(store.clone())
+            .with_eviction(EvictionConfig {
+                max_rooms: Some(1),
+                ..EvictionConfig::default()
+            });
+        let older = create_presentation(&manager, "room_older", vec![json!({})]).await;
+        let newer = create_presentation(&manager, "room_newer", vec![json!({})]).await;
+        manager.enforce_max_rooms().await;
+        assert_eq!(manager.list_rooms().await, vec![newer]);
+
+        // Unloaded rooms come back when a client joins
+        manager
+            .join_room(&older, "alice".to_string(), client_data("alice"))
+            .await
+            .unwrap();
+        assert!(manager.contains_room(&older).await);
+    }
 }
@@ -0,0 +1,202 @@
+use std::fmt;
+
+use jsonschema::{ValidationError, Validator, error::ValidationErrorKind};
+use schemars::JsonSchema;
+use serde_json::Value;
+
+use super::storage::{StorageError, StorageLike};
+
+/// A JSON Schema (draft 2020-12) every state of a room's storage has to satisfy.
+#[derive(Clone)]
+pub struct StorageSchema {
+    schema: Value,
+    validator: Validator,
+}
+
+impl fmt::Debug for StorageSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StorageSchema")
+            .field("schema", &self.schema)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StorageSchema {
+    pub fn new(schema: Value) -> Result<Self, StorageError> {
+        if !matches!(schema, Value::Bool(_) | Value::Object(_)) {
+            return Err(StorageError::InvalidSchema(
+                "A schema must be an object or a boolean".to_string(),
+            ));
+        }
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| StorageError::InvalidSchema(e.to_string()))?;
+        Ok(Self { schema, validator })
+    }
+
+    /// Derives the schema from `T`'s [`JsonSchema`] implementation, which follows its
+    /// serde attributes.
+    pub fn derive<T: JsonSchema>() -> Result<Self, StorageError> {
+        Self::new(schemars::schema_for!(T).to_value())
+    }
+
+    pub fn as_value(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Checks `value` against the schema, failing with the place it doesn't match.
+    pub fn validate(&self, value: &Value) -> Result<(), StorageError> {
+        match self.validator.validate(value) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let err = most_specific(&err);
+                let path = match err.instance_path().as_str() {
+                    "" => "/".to_string(),
+                    path => path.to_string(),
+                };
+                Err(StorageError::SchemaViolation {
+                    path,
+                    message: err.to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn validate_storage<S: StorageLike>(&self, storage: &S) -> Result<(), StorageError> {
+        self.validate(&storage.snapshot()?)
+    }
+}
+
+/// When a value matches none of a union's alternatives, the error from the alternative
+/// that was most likely meant.
+///
+/// An alternative whose `const` failed, such as the tag of an internally tagged enum,
+/// wasn't meant. Of the others, the one whose error got the furthest into the value
+/// wins. If every alternative's tag failed, the value has an unknown tag, and that is
+/// the error.
+fn most_specific<'a>(err: &'a ValidationError<'_>) -> &'a ValidationError<'a> {
+    let alternatives = match err.kind() {
+        ValidationErrorKind::AnyOf { context } | ValidationErrorKind::OneOfNotValid { context } => {
+            context
+        }
+        _ => return err,
+    };
+
+    let is_tag =
+        |err: &ValidationError<'_>| matches!(err.kind(), ValidationErrorKind::Constant { .. });
+    let meant: Vec<_> = alternatives
+        .iter()
+        .filter(|errors| !errors.iter().any(is_tag))
+        .collect();
+    if meant.is_empty() {
+        return alternatives
+            .iter()
+            .flatten()
+            .find(|err| is_tag(err))
+            .unwrap_or(err);
+    }
+
+    let depth = |err: &ValidationError<'_>| err.instance_path().as_str().matches('/').count();
+    meant
+        .into_iter()
+        .flatten()
+        .map(|err| most_specific(err))
+        .max_by_key(|err| depth(err))
+        .unwrap_or(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Shape {
+        Circle { radius: f64 },
+        Polygon { points: Vec<(i32, i32)> },
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    struct Board {
+        /// Shown above the board
+        title: String,
+        subtitle: Option<String>,
+        shapes: Vec<Shape>,
+        votes: std::collections::HashMap<String, u64>,
+        locked: bool,
+    }
+
+    fn violation_at(schema: &StorageSchema, value: Value) -> String {
+        match schema.validate(&value) {
+            Err(StorageError::SchemaViolation { path, .. }) => path,
+            other => panic!("Expected a violation, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn derived_schemas_follow_the_serde_shape() {
+        let schema = StorageSchema::derive::<Board>().unwrap();
+        let board = json!({
+            "title": "Ideas",
+            "shapes": [
+                { "type": "circle", "radius": 2.5 },
+                { "type": "polygon", "points": [[0, 0], [1, 2], [3, -1]] },
+            ],
+            "votes": { "alice": 3 },
+            "locked": false,
+        });
+        schema.validate(&board).unwrap();
+
+        let mut broken = board.clone();
+        broken["shapes"][1]["points"][2] = json!([3]);
+        assert_eq!(violation_at(&schema, broken), "/shapes/1/points/2");
+
+        let mut broken = board.clone();
+        broken["votes"]["bob"] = json!(1.5);
+        assert_eq!(violation_at(&schema, broken), "/votes/bob");
+
+        // An unknown tag is reported on the tag, a known one on what's wrong with the rest
+        let mut broken = board.clone();
+        broken["shapes"][0] = json!({ "type": "square" });
+        assert_eq!(violation_at(&schema, broken), "/shapes/0/type");
+        let mut broken = board.clone();
+        broken["shapes"][0]["radius"] = json!("big");
+        assert_eq!(violation_at(&schema, broken), "/shapes/0/radius");
+
+        let mut broken = board;
+        broken.as_object_mut().unwrap().remove("locked");
+        assert!(matches!(
+            schema.validate(&broken),
+            Err(StorageError::SchemaViolation { message, .. }) if message.contains("locked")
+        ));
+    }
+
+    #[test]
+    fn hand_written_schemas_support_refs_and_bounds() {
+        let schema = StorageSchema::new(json!({
+            "$defs": { "name": { "type": "string", "minLength": 1, "maxLength": 5 } },
+            "type": "object",
+            "properties": {
+                "names": { "type": "array", "items": { "$ref": "#/$defs/name" }, "maxItems": 2 },
+                "level": { "type": "integer", "minimum": 1, "exclusiveMaximum": 10 },
+            },
+            "additionalProperties": false,
+        }))
+        .unwrap();
+
+        schema
+            .validate(&json!({ "names": ["ann"], "level": 9 }))
+            .unwrap();
+        assert_eq!(violation_at(&schema, json!({ "names": [""] })), "/names/0");
+        assert_eq!(
+            violation_at(&schema, json!({ "names": ["a", "b", "c"] })),
+            "/names"
+        );
+        assert_eq!(violation_at(&schema, json!({ "level": 10 })), "/level");
+        assert_eq!(violation_at(&schema, json!({ "level": 1.5 })), "/level");
+        assert_eq!(violation_at(&schema, json!({ "other": 1 })), "/");
+        assert!(StorageSchema::new(json!("object")).is_err());
+        assert!(StorageSchema::new(json!({ "type": 5 })).is_err());
+    }
+}
@@ -3,6 +3,8 @@ use std::fmt::Debug;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+use super::schema::StorageSchema;
+
 // Define a potential error type for storage operations
 #[derive(thiserror::Error, Debug)]
 pub enum StorageError {
@@ -14,11 +16,17 @@ pub enum StorageError {
     SerializationError(#[from] serde_json::Error),
     #[error("Incompatible storage types")]
     IncompatibleTypes,
+    #[error("Can't read snapshot version {version}; the current version is {current}")]
+    UnsupportedSnapshotVersion { version: u32, current: u32 },
+    #[error("Invalid storage schema: {0}")]
+    InvalidSchema(String),
+    #[error("Storage doesn't match its schema at {path}: {message}")]
+    SchemaViolation { path: String, message: String },
     // Add other specific storage errors as needed
 }
 
 pub trait StorageLike:
-    for<'de> Deserialize<'de> + Serialize + Send + Sync + Clone + Debug + 'static + TS
+    for<'de> Deserialize<'de> + Serialize + Send + Sync + Clone + Debug + Default + 'static + TS
 {
     // Consider using an associated type for Diffs if they aren't always JSON
     // type Diff: for<'de> Deserialize<'de> + Serialize + Send + Sync + Debug + 'static;
@@ -42,12 +50,29 @@ pub trait StorageLike:
     /// Returns information about the effect of applying the diff.
     fn apply_diff(&mut self, diff: Self::Diff) -> Result<Self::ApplyResult, StorageError>;
 
+    /// Applies a diff like [`StorageLike::apply_diff`], then checks the resulting state
+    /// against `schema`. If it doesn't match, `self` is left as it was.
+    fn apply_diff_checked(
+        &mut self,
+        diff: Self::Diff,
+        schema: Option<&StorageSchema>,
+    ) -> Result<Self::ApplyResult, StorageError> {
+        let Some(schema) = schema else {
+            return self.apply_diff(diff);
+        };
+        let mut next = self.clone();
+        let result = next.apply_diff(diff)?;
+        schema.validate_storage(&next)?;
+        *self = next;
+        Ok(result)
+    }
+
     // --- New/Revised Methods ---
 
-    /// Applies a specific operation/mutation originating from a client command.
-    /// This is often the primary way storage is modified in response to user actions.
-    /// It should return the necessary information to broadcast updates (e.g., the diff/ops applied).
-    /// The `Op` type would likely be part of your `ClientMessage` enum.
+    // Applies a specific operation/mutation originating from a client command.
+    // This is often the primary way storage is modified in response to user actions.
+    // It should return the necessary information to broadcast updates (e.g., the diff/ops applied).
+    // The `Op` type would likely be part of your `ClientMessage` enum.
     // fn apply_op(&mut self, op: Self::Operation) -> Result<Self::Diff, StorageError>;
     // Note: We might handle this within RoomLike::transaction instead of directly here.
 
@@ -60,6 +85,30 @@ pub trait StorageLike:
     where
         Self: Sized;
 
+    /// Version of the snapshot format. Bump it whenever snapshots change shape, and
+    /// teach [`StorageLike::upgrade_snapshot`] to bring the previous version up to it.
+    const SNAPSHOT_VERSION: u32 = 1;
+
+    /// Upgrades a snapshot from snapshot version `from` to `from + 1`. Called once per
+    /// version when reading older snapshots, e.g. from an imported archive.
+    fn upgrade_snapshot(
+        _snapshot: serde_json::Value,
+        from: u32,
+    ) -> Result<serde_json::Value, StorageError>
+    where
+        Self: Sized,
+    {
+        Err(StorageError::UnsupportedSnapshotVersion {
+            version: from,
+            current: Self::SNAPSHOT_VERSION,
+        })
+    }
+
+    /// Keys of files in file storage the storage refers to, e.g. uploaded images.
+    fn file_keys(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     // Optional: Explicitly track changes for persistence optimization
     // fn clear_changes_flag(&mut self);
     // fn has_changes(&self) -> bool;
@@ -0,0 +1,109 @@
+use chrono::Utc;
+use serde_json::{Value, json};
+
+use super::{RoomMetadata, room_id::RoomId, room_manager::RoomManager};
+use crate::{
+    message::Message,
+    message_broker::memory::InMemoryMessageBroker,
+    presentation::{
+        Presentation, PresentationClientData, PresentationClientMessage, PresentationStorage,
+    },
+};
+
+pub type TestManager = RoomManager<InMemoryMessageBroker, Presentation>;
+
+pub fn client_data(name: &str) -> PresentationClientData {
+    PresentationClientData {
+        user_id: name.to_string(),
+        name: name.to_string(),
+        email: None,
+        avatar: None,
+    }
+}
+
+pub fn client_message(
+    room_id: &RoomId,
+    payload: PresentationClientMessage,
+) -> Message<PresentationClientMessage> {
+    Message {
+        room_id: room_id.clone(),
+        payload,
+        datetime: Utc::now(),
+        sender_id: None,
+        request_id: None,
+        broadcast: None,
+        base_version: None,
+    }
+}
+
+/// Creates a presentation of `slides` in `manager`.
+pub async fn create_presentation(manager: &TestManager, name: &str, slides: Vec<Value>) -> RoomId {
+    let room_id = RoomId::from_string(name);
+    let storage = PresentationStorage::new(slides);
+    manager
+        .create_room(Presentation::new(
+            room_id.clone(),
+            RoomMetadata::default(),
+            storage,
+        ))
+        .await
+        .unwrap();
+    room_id
+}
+
+/// Sends each of `payloads` to the room as `client`.
+pub async fn send_all(
+    manager: &TestManager,
+    room_id: &RoomId,
+    client: &str,
+    payloads: impl IntoIterator<Item = PresentationClientMessage>,
+) {
+    for payload in payloads {
+        manager
+            .handle_client_message(
+                room_id,
+                &client.to_string(),
+                client_message(room_id, payload),
+            )
+            .await
+            .unwrap();
+    }
+}
+
+/// Joins `client` to the room and has them claim the presenter role.
+pub async fn join_as_presenter(manager: &TestManager, room_id: &RoomId, client: &str) {
+    manager
+        .join_room(room_id, client.to_string(), client_data(client))
+        .await
+        .unwrap();
+    send_all(
+        manager,
+        room_id,
+        client,
+        [PresentationClientMessage::ClaimPresenter],
+    )
+    .await;
+}
+
+/// Creates a three-slide presentation that `clients` have joined, with the first
+/// of them presenting.
+pub async fn presentation_with_clients(
+    broker: &InMemoryMessageBroker,
+    clients: &[&str],
+) -> (TestManager, RoomId) {
+    let manager = RoomManager::new(broker.clone());
+    let room_id = create_presentation(&manager, "room_test", vec![json!({}); 3]).await;
+
+    if let Some((presenter, audience)) = clients.split_first() {
+        join_as_presenter(&manager, &room_id, presenter).await;
+        for client in audience {
+            manager
+                .join_room(&room_id, client.to_string(), client_data(client))
+                .await
+                .unwrap();
+        }
+    }
+    // Start each test from a clean log, without the setup announcements
+    broker.take_deliveries();
+    (manager, room_id)
+}
@@ -1,24 +1,28 @@
-use serde::{Deserialize, Serialize};
+use json_patch::{
+    AddOperation, Patch, PatchOperation, RemoveOperation, ReplaceOperation, TestOperation,
+    jsonptr::{Pointer, PointerBuf},
+};
+use serde_json::Value;
 use std::collections::VecDeque;
-use ts_rs::TS;
 use uuid::Uuid;
 
-use crate::message::ClientMessage;
+use super::{client_id::ClientId, storage::StorageError};
 
-use super::presence::PresenceLike;
-
-#[derive(Clone, Debug, Serialize, TS)]
-#[ts(export)]
-pub struct Transaction<Presence: PresenceLike + TS> {
+/// An applied storage change, with the diffs that revert and re-apply it.
+#[derive(Clone, Debug)]
+pub struct Transaction<D> {
     pub id: String,
-    pub client_id: String,
+    pub client_id: ClientId,
     pub timestamp: u64,
-    pub msg: ClientMessage<Presence>,
+    /// Takes the storage from after the change back to before it.
+    pub undo: D,
+    /// Takes the storage from before the change to after it.
+    pub redo: D,
 }
 
-impl<Presence: PresenceLike + TS> Transaction<Presence> {
+impl<D> Transaction<D> {
     #[must_use]
-    pub fn new(client_id: String, msg: ClientMessage<Presence>) -> Self {
+    pub fn new(client_id: ClientId, undo: D, redo: D) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             client_id,
@@ -26,31 +30,162 @@ impl<Presence: PresenceLike + TS> Transaction<Presence> {
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_millis() as u64,
-            msg,
+            undo,
+            redo,
         }
     }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct TransactionManager<Presence: PresenceLike + TS> {
-    history: VecDeque<Transaction<Presence>>,
-    undone: VecDeque<Transaction<Presence>>,
+impl Transaction<Patch> {
+    /// Records `patch`, which a client applied to the document `before`.
+    ///
+    /// Each operation is inverted on its own, so undoing only touches what the patch
+    /// touched, and is guarded by `test` operations, so undoing or redoing fails
+    /// rather than overwriting a later change to the same place.
+    pub fn from_patch(
+        client_id: ClientId,
+        before: &Value,
+        patch: &Patch,
+    ) -> Result<Self, StorageError> {
+        let mut after = before.clone();
+        let undo = invert(&mut after, patch)?;
+        let redo = invert(&mut after, &undo)?;
+        Ok(Self::new(client_id, undo, redo))
+    }
+}
+
+/// Applies `patch` to `document` and returns the patch reverting it: the inverse of
+/// each operation, last first, each checking that what it reverts is still there.
+fn invert(document: &mut Value, patch: &[PatchOperation]) -> Result<Patch, StorageError> {
+    let mut inverses = Vec::new();
+    for op in patch {
+        for op in primitive(document, op)? {
+            inverses.push(inverse(document, &op)?);
+            json_patch::patch(document, std::slice::from_ref(&op))
+                .map_err(|e| StorageError::ApplyDiffError(e.to_string()))?;
+        }
+    }
+    Ok(Patch(inverses.into_iter().rev().flatten().collect()))
+}
+
+/// `op` as adds, removes, replaces and tests, which invert on their own.
+fn primitive(document: &Value, op: &PatchOperation) -> Result<Vec<PatchOperation>, StorageError> {
+    Ok(match op {
+        PatchOperation::Move(op) => vec![
+            PatchOperation::Remove(RemoveOperation {
+                path: op.from.clone(),
+            }),
+            add(op.path.clone(), value_at(document, &op.from)?),
+        ],
+        PatchOperation::Copy(op) => vec![add(op.path.clone(), value_at(document, &op.from)?)],
+        op => vec![op.clone()],
+    })
+}
+
+/// The operations reverting the primitive `op` on `document`.
+fn inverse(document: &Value, op: &PatchOperation) -> Result<Vec<PatchOperation>, StorageError> {
+    Ok(match op {
+        PatchOperation::Add(op) => {
+            let (path, into_array) = match op.path.split_back() {
+                Some((parent, token)) => match parent.resolve(document) {
+                    Ok(Value::Array(items)) if token.encoded() == "-" => {
+                        (parent.with_trailing_token(items.len()), true)
+                    }
+                    Ok(Value::Array(_)) => (op.path.clone(), true),
+                    _ => (op.path.clone(), false),
+                },
+                None => (op.path.clone(), false),
+            };
+            let restore = match path.resolve(document) {
+                Ok(old) if !into_array => replace(path.clone(), old.clone()),
+                _ => PatchOperation::Remove(RemoveOperation { path: path.clone() }),
+            };
+            vec![test(path, op.value.clone()), restore]
+        }
+        PatchOperation::Remove(op) => {
+            let old = value_at(document, &op.path)?;
+            // Puts an array item back only in front of the item that followed it
+            let next = op.path.split_back().and_then(|(parent, token)| {
+                let Ok(Value::Array(items)) = parent.resolve(document) else {
+                    return None;
+                };
+                let index: usize = token.decoded().parse().ok()?;
+                items.get(index + 1).cloned()
+            });
+            let mut ops: Vec<_> = next
+                .map(|next| test(op.path.clone(), next))
+                .into_iter()
+                .collect();
+            ops.push(add(op.path.clone(), old));
+            ops
+        }
+        PatchOperation::Replace(op) => vec![
+            test(op.path.clone(), op.value.clone()),
+            replace(op.path.clone(), value_at(document, &op.path)?),
+        ],
+        _ => Vec::new(),
+    })
+}
+
+fn value_at(document: &Value, path: &Pointer) -> Result<Value, StorageError> {
+    path.resolve(document)
+        .cloned()
+        .map_err(|e| StorageError::ApplyDiffError(e.to_string()))
+}
+
+fn add(path: PointerBuf, value: Value) -> PatchOperation {
+    PatchOperation::Add(AddOperation { path, value })
+}
+
+fn replace(path: PointerBuf, value: Value) -> PatchOperation {
+    PatchOperation::Replace(ReplaceOperation { path, value })
+}
+
+fn test(path: PointerBuf, value: Value) -> PatchOperation {
+    PatchOperation::Test(TestOperation { path, value })
+}
+
+/// Whose transactions an undo or redo reverts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UndoScope {
+    /// The latest transaction in the room, whoever made it.
+    #[default]
+    Room,
+    /// The latest transaction made by the client asking.
+    Client,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransactionManager<D> {
+    history: VecDeque<Transaction<D>>,
+    undone: VecDeque<Transaction<D>>,
     max_history: usize,
+    scope: UndoScope,
 }
 
-impl<Presence: PresenceLike + TS> TransactionManager<Presence> {
+impl<D: Clone> TransactionManager<D> {
     #[must_use]
-    pub fn new(max_history: usize) -> Self {
+    pub fn new(max_history: usize, scope: UndoScope) -> Self {
         Self {
             history: VecDeque::with_capacity(max_history),
             undone: VecDeque::new(),
             max_history,
+            scope,
         }
     }
 
-    pub fn add_transaction(&mut self, transaction: Transaction<Presence>) {
-        // Clear the undo stack when a new transaction is added
-        self.undone.clear();
+    pub fn scope(&self) -> UndoScope {
+        self.scope
+    }
+
+    pub fn add_transaction(&mut self, transaction: Transaction<D>) {
+        // A new change invalidates whatever could have been redone in its scope
+        match self.scope {
+            UndoScope::Room => self.undone.clear(),
+            UndoScope::Client => self
+                .undone
+                .retain(|undone| undone.client_id != transaction.client_id),
+        }
 
         // Add the transaction to history
         self.history.push_back(transaction);
@@ -61,26 +196,55 @@ impl<Presence: PresenceLike + TS> TransactionManager<Presence> {
         }
     }
 
-    pub fn undo(&mut self) -> Option<Transaction<Presence>> {
-        if let Some(transaction) = self.history.pop_back() {
-            self.undone.push_back(transaction.clone());
-            Some(transaction)
-        } else {
-            None
-        }
+    /// Reverts the latest transaction in scope for `client_id` by handing its inverse
+    /// diff to `apply`, and returns what `apply` returned. Returns `None` if there is
+    /// nothing to undo.
+    ///
+    /// If `apply` fails, e.g. because someone has since changed the same part of the
+    /// storage, the transaction stays where it was.
+    pub fn undo<T, E>(
+        &mut self,
+        client_id: &ClientId,
+        apply: impl FnOnce(&D) -> Result<T, E>,
+    ) -> Result<Option<T>, E> {
+        let Some(index) = Self::latest(&self.history, self.scope, client_id) else {
+            return Ok(None);
+        };
+        let applied = apply(&self.history[index].undo)?;
+
+        self.undone.extend(self.history.remove(index));
+        Ok(Some(applied))
     }
 
-    pub fn redo(&mut self) -> Option<Transaction<Presence>> {
-        if let Some(transaction) = self.undone.pop_back() {
-            self.history.push_back(transaction.clone());
-            Some(transaction)
-        } else {
-            None
-        }
+    /// Re-applies the latest undone transaction in scope for `client_id`; the
+    /// counterpart of [`TransactionManager::undo`].
+    pub fn redo<T, E>(
+        &mut self,
+        client_id: &ClientId,
+        apply: impl FnOnce(&D) -> Result<T, E>,
+    ) -> Result<Option<T>, E> {
+        let Some(index) = Self::latest(&self.undone, self.scope, client_id) else {
+            return Ok(None);
+        };
+        let applied = apply(&self.undone[index].redo)?;
+
+        self.history.extend(self.undone.remove(index));
+        Ok(Some(applied))
     }
 
     #[must_use]
-    pub fn get_history(&self) -> &VecDeque<Transaction<Presence>> {
+    pub fn get_history(&self) -> &VecDeque<Transaction<D>> {
         &self.history
     }
+
+    fn latest(
+        stack: &VecDeque<Transaction<D>>,
+        scope: UndoScope,
+        client_id: &ClientId,
+    ) -> Option<usize> {
+        match scope {
+            UndoScope::Room => stack.len().checked_sub(1),
+            UndoScope::Client => stack.iter().rposition(|t| t.client_id == *client_id),
+        }
+    }
 }